@@ -0,0 +1,47 @@
+// headless emulation speed: boots the machine and runs frames as fast as possible,
+// without window, frame pacing or audio output
+// usage: cargo bench [-- FRAMES]
+extern crate rust64;
+
+use rust64::c64;
+use std::env;
+use std::process;
+use std::time::Instant;
+
+const DEFAULT_FRAMES: u32 = 500;
+
+
+fn main() {
+    // cargo passes --bench to the target, so only pick up numeric args
+    let frames = env::args().skip(1).filter_map(|arg| arg.parse::<u32>().ok()).next().unwrap_or(DEFAULT_FRAMES);
+
+    // PAL and NTSC machines differ in the number of cycles per frame
+    for vic_model in [c64::vic::VICModel::MOS6569, c64::vic::VICModel::MOS6567R8].iter() {
+        let mut model = c64::model::MachineModel::from_name("c64").unwrap();
+        model.vic_model = *vic_model;
+
+        let roms = match c64::roms::RomSet::load(&c64::roms::RomConfig::default()) {
+            Ok(roms) => roms,
+            Err(e) => {
+                println!("Error: {}", e);
+                process::exit(1);
+            }
+        };
+
+        let mut machine = c64::machine::Machine::new(roms, &model);
+        machine.reset();
+
+        let start = Instant::now();
+        for _ in 0..frames {
+            while !machine.step() {}
+        }
+        let elapsed = start.elapsed();
+        let secs = elapsed.as_secs() as f64 + elapsed.subsec_nanos() as f64 * 1e-9;
+
+        let cycles = machine.scheduler.now();
+        let mhz = cycles as f64 / secs / 1e6;
+        let real_time = 100.0 * mhz * 1e6 / model.vic_model.clock_freq() as f64;
+        println!("{} VIC-II: {} frames, {} cycles in {:.3}s: {:.2} MHz ({:.0}% of real time)",
+                 model.vic_model.name(), frames, cycles, secs, mhz, real_time);
+    }
+}
@@ -0,0 +1,154 @@
+; digi playback test - plays a 500 Hz sine for two seconds each with three sample techniques,
+; driven by a CIA2 timer A NMI at ~8 kHz (123 cycles on PAL):
+;   1. 4-bit samples written to the $d418 master volume (loud on a 6581, needs --digiboost on an 8580)
+;   2. pulse width modulation: voice 1 pulse restarted with the test bit, width set by the sample
+;   3. test bit and floating waveform DAC: voice 1 sawtooth runs for a fixed time from zero with the
+;      sample as frequency, then no waveform is selected and the DAC holds the level it reached
+; assemble with: acme -f cbm -o digi.prg digi.asm
+
+        * = $0801
+        !byte $0b, $08, $0a, $00, $9e, $32, $30, $36, $31, $00, $00, $00 ; 10 SYS2061
+
+start   sei                 ; NMIs only
+        lda #$0b            ; blank the screen: bad lines would delay the NMIs by up to 43 cycles
+        sta $d011
+
+        lda #$00            ; clear the SID
+        ldx #$18
+clear   sta $d400,x
+        dex
+        bpl clear
+
+        lda #<nmivol        ; 1. volume register
+        sta $0318
+        lda #>nmivol
+        sta $0319
+        jsr play
+
+        lda #$f0            ; voice 1 at full level: sustain 15, gate on
+        sta $d406
+        lda #$ff            ; fastest oscillator: 256 cycles per period
+        sta $d400
+        sta $d401
+        lda #$0f
+        sta $d418
+        lda #$41            ; pulse, gate
+        sta $d404
+
+        lda #<nmipwm        ; 2. pulse width
+        sta $0318
+        lda #>nmipwm
+        sta $0319
+        jsr play
+
+        lda #$00
+        sta $d400
+        lda #$21            ; sawtooth, gate
+        sta $d404
+
+        lda #<nmitest       ; 3. test bit
+        sta $0318
+        lda #>nmitest
+        sta $0319
+        jsr play
+
+        lda #$00            ; silence and back to BASIC
+        sta $d404
+        sta $d418
+        lda #$47
+        sta $0318
+        lda #$fe
+        sta $0319
+        lda #$1b
+        sta $d011
+        cli
+        rts
+
+        ; start the sample timer and wait 100 frames
+play    lda #$00
+        sta idx
+        lda #$7a            ; 123 cycles per sample
+        sta $dd04
+        lda #$00
+        sta $dd05
+        lda #$81            ; timer A NMI
+        sta $dd0d
+        lda #$11            ; load and start, continuous
+        sta $dd0e
+
+        ldy #100
+frame1  lda $d011
+        bpl frame1
+frame2  lda $d011
+        bmi frame2
+        dey
+        bne frame1
+
+        lda #$7f            ; stop the timer and its NMI
+        sta $dd0d
+        lda #$00
+        sta $dd0e
+        lda $dd0d
+        rts
+
+nmivol  pha
+        txa
+        pha
+        ldx idx
+        lda sine4,x
+        sta $d418
+        jmp next
+
+nmipwm  pha
+        txa
+        pha
+        ldx idx
+        lda pwlo,x
+        sta $d402
+        lda pwhi,x
+        sta $d403
+        lda #$49            ; test bit: oscillator back to zero
+        sta $d404
+        lda #$41
+        sta $d404
+        jmp next
+
+nmitest pha
+        txa
+        pha
+        ldx idx
+        lda sine8,x
+        sta $d401
+        lda #$29            ; test bit: oscillator to zero
+        sta $d404
+        lda #$21            ; sawtooth rises for 26 cycles
+        sta $d404
+        nop
+        nop
+        nop
+        nop
+        nop
+        nop
+        nop
+        nop
+        nop
+        nop
+        lda #$01            ; no waveform: the DAC holds the sawtooth level
+        sta $d404
+
+next    inx
+        txa
+        and #$0f
+        sta idx
+        lda $dd0d           ; acknowledge the NMI
+        pla
+        tax
+        pla
+        rti
+
+idx     !byte $00
+sine4   !byte 8, 10, 13, 14, 15, 14, 13, 10, 8, 5, 2, 1, 0, 1, 2, 5
+sine8   !byte 128, 176, 218, 245, 255, 245, 218, 176, 128, 79, 37, 10, 0, 10, 37, 79
+        ; pulse widths: sample x 7 - the oscillator gets just under halfway in 123 cycles
+pwlo    !byte $80, $d0, $f6, $b3, $f9, $b3, $f6, $d0, $80, $29, $03, $46, $00, $46, $03, $29
+pwhi    !byte $03, $04, $05, $06, $06, $06, $05, $04, $03, $02, $01, $00, $00, $00, $01, $02
@@ -0,0 +1,94 @@
+; VIC-II model detection - prints the model byte and chip name, the byte is also left in $02
+;   $00 - 6569 (PAL)
+;   $01 - 6567R8 (NTSC)
+;   $02 - 6567R56A (early NTSC)
+;   $03 - 6569R1 (early PAL)
+; assemble with: acme -f cbm -o vicmodel.prg vicmodel.asm
+
+        * = $0801
+        !byte $0b, $08, $0a, $00, $9e, $32, $30, $36, $31, $00, $00, $00 ; 10 SYS2061
+
+start   sei
+
+        ; last rasterline: wait until $d012 wraps around
+line1   lda $d012
+line2   cmp $d012
+        beq line2
+        bmi line1
+
+        ldx #$01            ; last line $106 - 6567R8
+        cmp #$06
+        beq found
+        ldx #$02            ; last line $105 - 6567R56A
+        cmp #$05
+        beq found
+
+        ; PAL: later revisions trigger the lightpen again if its input is held low at the start of a frame
+        lda #$ef
+        sta $dc01
+wait80  lda $d011
+        bmi wait80
+        lda $d012
+        cmp #$80
+        bne wait80
+
+        lda #$10
+        sta $dc03           ; port B bit 4 (lightpen) low
+        lda #$08
+        sta $d019           ; acknowledge lightpen interrupt
+
+wait20  lda $d011           ; line $20 of the next frame
+        bmi wait20
+        lda $d012
+        cmp #$20
+        bne wait20
+
+        ldx #$00            ; retriggered - 6569
+        lda $d019
+        and #$08
+        bne restore
+        ldx #$03            ; 6569R1
+
+restore lda #$00
+        sta $dc03
+        lda #$ff
+        sta $dc01
+        lda #$08
+        sta $d019
+
+found   stx $02
+        cli
+
+        ldy #$00
+print1  lda msg,y
+        beq print2
+        jsr $ffd2
+        iny
+        bne print1
+
+print2  lda $02
+        ora #$30
+        jsr $ffd2
+        lda #$20
+        jsr $ffd2
+
+        lda $02             ; names are 16 bytes apart
+        asl
+        asl
+        asl
+        asl
+        tax
+print3  lda names,x
+        beq done
+        jsr $ffd2
+        inx
+        bne print3
+
+done    lda #$0d
+        jmp $ffd2
+
+msg     !text "VIC-II MODEL BYTE: $0", $00
+names   !text "6569", $00, $00, $00, $00, $00, $00, $00, $00, $00, $00, $00, $00
+        !text "6567R8", $00, $00, $00, $00, $00, $00, $00, $00, $00, $00
+        !text "6567R56A", $00, $00, $00, $00, $00, $00, $00, $00
+        !text "6569R1", $00, $00, $00, $00, $00, $00, $00, $00, $00, $00
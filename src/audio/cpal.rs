@@ -0,0 +1,49 @@
+// cpal audio output
+extern crate cpal;
+
+use self::cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use audio::{AudioSink, SharedSource, CHANNELS, SAMPLE_RATE};
+
+
+pub struct CpalSink {
+    stream: cpal::Stream,
+}
+
+impl CpalSink {
+    pub fn open(source: SharedSource) -> Result<CpalSink, String> {
+        let host = cpal::default_host();
+        let device = host.default_output_device().ok_or("no audio output device")?;
+
+        let config = cpal::StreamConfig {
+            channels: CHANNELS as u16,
+            sample_rate: cpal::SampleRate(SAMPLE_RATE),
+            buffer_size: cpal::BufferSize::Default,
+        };
+
+        // float output is supported by every host, so convert from the SID's 16-bit samples
+        let mut samples: Vec<i16> = Vec::new();
+        let stream = device.build_output_stream(&config, move |out: &mut [f32], _: &cpal::OutputCallbackInfo| {
+            samples.resize(out.len(), 0);
+            source.lock().unwrap().fill(&mut samples);
+
+            for (x, &sample) in out.iter_mut().zip(samples.iter()) {
+                *x = sample as f32 / 32768.0;
+            }
+        }, |e| println!("Audio stream error: {}", e)).map_err(|e| e.to_string())?;
+
+        stream.pause().map_err(|e| e.to_string())?;
+        Ok(CpalSink { stream: stream })
+    }
+}
+
+
+impl AudioSink for CpalSink {
+    fn resume(&mut self) {
+        let _ = self.stream.play();
+    }
+
+
+    fn pause(&mut self) {
+        let _ = self.stream.pause();
+    }
+}
@@ -0,0 +1,197 @@
+// audio output backends
+// the SID renders samples into a ring as it is clocked: sound card backends (SDL2, cpal) drain it from
+// their own callback thread, the WAV sink writes out whatever was produced each frame and the null sink ignores it
+#[cfg(feature = "cpal")]
+mod cpal;
+mod recorder;
+mod resampler;
+mod ring;
+#[cfg(all(feature = "sdl2", not(target_os = "redox")))]
+pub mod sdl;
+mod wav;
+
+pub use self::recorder::Recorder;
+pub use self::resampler::Resampler;
+pub use self::ring::SampleRing;
+pub use self::wav::WavWriter;
+
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+pub const SAMPLE_RATE: u32 = 44100; // output frequency (16-bit)
+pub const CHANNELS: usize = 2;       // stereo, samples are interleaved left/right
+
+
+// something that produces audio samples
+pub trait SampleSource: Send {
+    // next out.len() samples for a sound card - padded if not enough are ready
+    // samples are interleaved stereo frames, so lengths are always a multiple of CHANNELS
+    fn fill(&mut self, out: &mut [i16]);
+
+    // append all samples that are ready
+    fn take(&mut self, out: &mut Vec<i16>);
+}
+
+pub type SharedSource = Arc<Mutex<dyn SampleSource>>;
+
+
+pub trait AudioSink {
+    // start/stop playback
+    fn resume(&mut self);
+    fn pause(&mut self);
+
+    // emulated time advanced by a frame - sinks not driven by a sound card collect the samples now
+    fn advance(&mut self) {}
+}
+
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Backend {
+    Null,
+    Sdl,
+    Cpal,
+    Wav(String), // write to a file instead of playing
+    Remote(RemoteOutput),
+}
+
+impl Backend {
+    // none, sdl, cpal or a *.wav file name
+    pub fn from_name(name: &str) -> Option<Backend> {
+        if name.to_lowercase().ends_with(".wav") {
+            return Some(Backend::Wav(String::from(name)));
+        }
+
+        match &name.to_lowercase()[..] {
+            "none" | "null" => Some(Backend::Null),
+            "sdl" | "sdl2"  => Some(Backend::Sdl),
+            "cpal"          => Some(Backend::Cpal),
+            _ => None
+        }
+    }
+
+
+    // first sound card backend compiled in
+    pub fn default() -> Backend {
+        if cfg!(all(feature = "sdl2", not(target_os = "redox"))) {
+            Backend::Sdl
+        }
+        else if cfg!(feature = "cpal") {
+            Backend::Cpal
+        }
+        else {
+            Backend::Null
+        }
+    }
+}
+
+
+// sound card opened by another thread - SDL2 allows a single context per process, so with SDL2
+// video the UI thread owns the audio device and the core hands its samples over through this
+#[derive(Clone)]
+pub struct RemoteOutput {
+    source: Arc<Mutex<Option<SharedSource>>>,
+    playing: Arc<AtomicBool>,
+}
+
+impl RemoteOutput {
+    pub fn new() -> RemoteOutput {
+        RemoteOutput {
+            source: Arc::new(Mutex::new(None)),
+            playing: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+
+    // called by the device - silence until the core has attached its source and resumed playback
+    pub fn fill(&self, out: &mut [i16]) {
+        if self.playing.load(Ordering::Relaxed) {
+            if let Some(ref source) = *self.source.lock().unwrap() {
+                source.lock().unwrap().fill(out);
+                return;
+            }
+        }
+
+        for sample in out.iter_mut() {
+            *sample = 0;
+        }
+    }
+}
+
+impl fmt::Debug for RemoteOutput {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "RemoteOutput")
+    }
+}
+
+impl PartialEq for RemoteOutput {
+    fn eq(&self, other: &RemoteOutput) -> bool {
+        Arc::ptr_eq(&self.playing, &other.playing)
+    }
+}
+
+
+// core side of a RemoteOutput
+struct RemoteSink {
+    playing: Arc<AtomicBool>,
+}
+
+impl AudioSink for RemoteSink {
+    fn resume(&mut self) {
+        self.playing.store(true, Ordering::Relaxed);
+    }
+
+
+    fn pause(&mut self) {
+        self.playing.store(false, Ordering::Relaxed);
+    }
+}
+
+
+// discards everything - no sound card needed
+pub struct NullSink;
+
+impl AudioSink for NullSink {
+    fn resume(&mut self) {}
+    fn pause(&mut self) {}
+}
+
+
+pub fn open(backend: &Backend, source: SharedSource) -> Result<Box<dyn AudioSink>, String> {
+    match *backend {
+        Backend::Null => Ok(Box::new(NullSink)),
+        Backend::Sdl  => open_sdl(source),
+        Backend::Cpal => open_cpal(source),
+        Backend::Wav(ref filename) => Ok(Box::new(wav::WavSink::create(filename, source)?)),
+        Backend::Remote(ref output) => {
+            *output.source.lock().unwrap() = Some(source);
+            Ok(Box::new(RemoteSink { playing: output.playing.clone() }))
+        },
+    }
+}
+
+
+// *** private functions *** //
+
+#[cfg(all(feature = "sdl2", not(target_os = "redox")))]
+fn open_sdl(source: SharedSource) -> Result<Box<dyn AudioSink>, String> {
+    Ok(Box::new(sdl::SdlSink::open(source)?))
+}
+
+
+#[cfg(not(all(feature = "sdl2", not(target_os = "redox"))))]
+fn open_sdl(_source: SharedSource) -> Result<Box<dyn AudioSink>, String> {
+    Err(String::from("SDL2 audio is not compiled in (build with --features sdl2)"))
+}
+
+
+#[cfg(feature = "cpal")]
+fn open_cpal(source: SharedSource) -> Result<Box<dyn AudioSink>, String> {
+    Ok(Box::new(cpal::CpalSink::open(source)?))
+}
+
+
+#[cfg(not(feature = "cpal"))]
+fn open_cpal(_source: SharedSource) -> Result<Box<dyn AudioSink>, String> {
+    Err(String::from("cpal audio is not compiled in (build with --features cpal)"))
+}
@@ -0,0 +1,60 @@
+// capture of the emulated audio to a WAV file, independent of the live output: the recorder
+// resamples the chip output itself, so it can use its own rate and channel count and follows
+// emulated time (warp mode records at the right speed, pauses leave no gaps)
+use audio::{Resampler, WavWriter};
+
+
+pub struct Recorder {
+    writer: WavWriter,
+    resamplers: Vec<Resampler>, // one per recorded channel
+    buffer: Vec<i16>,           // samples not written yet
+}
+
+impl Recorder {
+    // channels: 1 (mono) or 2 (stereo), clock_freq: rate of the samples pushed in
+    pub fn create(filename: &str, channels: u16, sample_rate: u32, clock_freq: u32) -> Result<Recorder, String> {
+        Ok(Recorder {
+            writer: WavWriter::create(filename, channels, sample_rate)?,
+            resamplers: (0..channels).map(|_| Resampler::new(clock_freq, sample_rate)).collect(),
+            buffer: Vec::new(),
+        })
+    }
+
+
+    pub fn filename(&self) -> &str {
+        self.writer.filename()
+    }
+
+
+    // one stereo frame at the input rate - mixed down for mono recordings
+    pub fn push(&mut self, left: i32, right: i32) {
+        if self.resamplers.len() == 1 {
+            if let Some(sample) = self.resamplers[0].push((left + right) / 2) {
+                self.buffer.push(sample);
+            }
+        }
+        else {
+            // both resamplers run in step, so they produce a sample at the same time
+            let l = self.resamplers[0].push(left);
+            let r = self.resamplers[1].push(right);
+
+            if let (Some(l), Some(r)) = (l, r) {
+                self.buffer.push(l);
+                self.buffer.push(r);
+            }
+        }
+    }
+
+
+    pub fn flush(&mut self) {
+        self.writer.write(&self.buffer);
+        self.buffer.clear();
+    }
+}
+
+
+impl Drop for Recorder {
+    fn drop(&mut self) {
+        self.flush();
+    }
+}
@@ -0,0 +1,128 @@
+// band-limited conversion of the SID output, computed once per chip clock, down to the output rate
+// polyphase FIR: a Kaiser windowed sinc evaluated at a fractional cycle offset for every output sample
+use std::f64::consts::PI;
+
+const PHASES: usize = 64;      // fractional cycle resolution
+const ATTENUATION: f64 = 60.0; // stopband attenuation in dB
+
+
+pub struct Resampler {
+    fir: Vec<f32>,      // PHASES filters of num_taps coefficients each
+    num_taps: usize,
+    history: Vec<f32>,  // last num_taps input samples, stored twice so a window is always contiguous
+    pos: usize,
+    cycles_per_sample: f64,
+    next_sample: f64,   // cycles until the next output sample is due
+}
+
+impl Resampler {
+    pub fn new(in_rate: u32, out_rate: u32) -> Resampler {
+        let in_rate = in_rate as f64;
+        let out_rate = out_rate as f64;
+
+        // pass everything up to 40% of the output rate - aliases of the transition band land above that
+        let pass = 0.4 * out_rate;
+        let stop = out_rate - pass;
+        let cutoff = (pass + stop) / 2.0 / in_rate;
+        let transition = 2.0 * PI * (stop - pass) / in_rate;
+
+        let filter_len = ((ATTENUATION - 8.0) / (2.285 * transition)).ceil() as usize | 1;
+        let center = (filter_len - 1) as f64 / 2.0;
+        let beta = 0.1102 * (ATTENUATION - 8.7);
+
+        // padded with zeros to whole blocks of 8 for the dot product
+        let num_taps = (filter_len + 7) & !7;
+        let mut fir = vec![0.0; PHASES * num_taps];
+
+        for phase in 0..PHASES {
+            let offset = phase as f64 / PHASES as f64;
+            let coeffs = &mut fir[phase * num_taps..(phase + 1) * num_taps];
+            let mut sum = 0.0;
+
+            for (k, c) in coeffs.iter_mut().enumerate() {
+                let t = k as f64 - center - offset;
+                let sinc = if t == 0.0 { 2.0 * cutoff } else { (2.0 * PI * cutoff * t).sin() / (PI * t) };
+                let x = t / center;
+                let window = if x.abs() >= 1.0 { 0.0 } else { bessel_i0(beta * (1.0 - x * x).sqrt()) / bessel_i0(beta) };
+
+                *c = (sinc * window) as f32;
+                sum += sinc * window;
+            }
+
+            // unity gain for every phase
+            for c in coeffs.iter_mut() {
+                *c /= sum as f32;
+            }
+        }
+
+        Resampler {
+            fir: fir,
+            num_taps: num_taps,
+            history: vec![0.0; 2 * num_taps],
+            pos: 0,
+            cycles_per_sample: in_rate / out_rate,
+            next_sample: 0.0,
+        }
+    }
+
+
+    pub fn reset(&mut self) {
+        for x in self.history.iter_mut() {
+            *x = 0.0;
+        }
+        self.next_sample = 0.0;
+    }
+
+
+    // feed one input sample - returns an output sample when one is due
+    pub fn push(&mut self, sample: i32) -> Option<i16> {
+        let x = sample as f32;
+        self.pos = if self.pos == 0 { self.num_taps - 1 } else { self.pos - 1 };
+        self.history[self.pos] = x;
+        self.history[self.pos + self.num_taps] = x;
+
+        self.next_sample -= 1.0;
+        if self.next_sample > 0.0 {
+            return None;
+        }
+
+        // the output sample lies this many cycles before the newest input sample
+        let offset = -self.next_sample;
+        self.next_sample += self.cycles_per_sample;
+
+        let phase = ((offset * PHASES as f64) as usize).min(PHASES - 1);
+        let coeffs = &self.fir[phase * self.num_taps..(phase + 1) * self.num_taps];
+        let window = &self.history[self.pos..self.pos + self.num_taps];
+
+        // independent partial sums let the compiler vectorize the loop
+        let mut sums = [0.0f32; 8];
+        for (c, x) in coeffs.chunks_exact(8).zip(window.chunks_exact(8)) {
+            for i in 0..8 {
+                sums[i] += c[i] * x[i];
+            }
+        }
+        let out: f32 = sums.iter().sum();
+
+        Some(out.round().max(-32768.0).min(32767.0) as i16)
+    }
+}
+
+
+// *** private functions *** //
+
+// zeroth order modified Bessel function of the first kind
+fn bessel_i0(x: f64) -> f64 {
+    let mut sum = 1.0;
+    let mut term = 1.0;
+    let half = x / 2.0;
+
+    for k in 1..50 {
+        term *= (half / k as f64) * (half / k as f64);
+        sum += term;
+        if term < sum * 1e-12 {
+            break;
+        }
+    }
+
+    sum
+}
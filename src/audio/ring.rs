@@ -0,0 +1,70 @@
+// buffer between the emulation core producing samples in bursts (a frame at a time) and a sound
+// card pulling them at its own pace
+use audio::{SampleSource, CHANNELS};
+use std::collections::VecDeque;
+
+
+pub struct SampleRing {
+    samples: VecDeque<i16>,
+    capacity: usize,  // older samples are dropped beyond this - nobody is draining the ring
+    prebuffer: usize, // level to refill to after an underrun
+    filling: bool,    // waiting for the prebuffer to fill
+    last: [i16; CHANNELS], // frame repeated on underrun to avoid clicks
+}
+
+impl SampleRing {
+    // prebuffer in frames
+    pub fn new(prebuffer: usize) -> SampleRing {
+        let prebuffer = prebuffer * CHANNELS;
+
+        SampleRing {
+            samples: VecDeque::with_capacity(prebuffer * 4),
+            capacity: prebuffer * 4,
+            prebuffer: prebuffer,
+            filling: true,
+            last: [0; CHANNELS],
+        }
+    }
+
+
+    pub fn push(&mut self, samples: &[i16]) {
+        self.samples.extend(samples.iter());
+
+        if self.samples.len() > self.capacity {
+            let excess = self.samples.len() - self.capacity;
+            self.samples.drain(..excess);
+        }
+    }
+}
+
+
+impl SampleSource for SampleRing {
+    fn fill(&mut self, out: &mut [i16]) {
+        if self.filling && self.samples.len() < self.prebuffer {
+            for (i, x) in out.iter_mut().enumerate() {
+                *x = self.last[i % CHANNELS];
+            }
+            return;
+        }
+        self.filling = false;
+
+        // too far ahead of the sound card (playback was paused, clocks drifted) - skip to the prebuffer level
+        if self.samples.len() > self.prebuffer * 3 {
+            let excess = self.samples.len() - self.prebuffer;
+            self.samples.drain(..excess);
+        }
+
+        // whole frames are pushed and taken, so the channels stay in step
+        for (i, x) in out.iter_mut().enumerate() {
+            match self.samples.pop_front() {
+                Some(sample) => { self.last[i % CHANNELS] = sample; *x = sample; },
+                None => { self.filling = true; *x = self.last[i % CHANNELS]; },
+            }
+        }
+    }
+
+
+    fn take(&mut self, out: &mut Vec<i16>) {
+        out.extend(self.samples.drain(..));
+    }
+}
@@ -0,0 +1,86 @@
+// SDL2 audio output
+extern crate sdl2;
+
+use self::sdl2::AudioSubsystem;
+use self::sdl2::audio::{AudioCallback, AudioDevice, AudioSpecDesired};
+use audio::{AudioSink, RemoteOutput, SharedSource, CHANNELS, SAMPLE_RATE};
+
+
+pub struct Callback {
+    source: SharedSource,
+}
+
+impl AudioCallback for Callback {
+    type Channel = i16;
+
+    fn callback(&mut self, out: &mut [i16]) {
+        self.source.lock().unwrap().fill(out);
+    }
+}
+
+
+pub struct RemoteCallback {
+    output: RemoteOutput,
+}
+
+impl AudioCallback for RemoteCallback {
+    type Channel = i16;
+
+    fn callback(&mut self, out: &mut [i16]) {
+        self.output.fill(out);
+    }
+}
+
+
+pub struct SdlSink {
+    device: AudioDevice<Callback>,
+}
+
+impl SdlSink {
+    pub fn open(source: SharedSource) -> Result<SdlSink, String> {
+        let sdl_context = sdl2::init()?;
+        let audio_subsystem = sdl_context.audio()?;
+
+        let device = audio_subsystem.open_playback(None, &desired_spec(), |spec| {
+            println!("{:?}", spec);
+            Callback { source: source }
+        })?;
+
+        Ok(SdlSink { device: device })
+    }
+}
+
+
+impl AudioSink for SdlSink {
+    fn resume(&mut self) {
+        self.device.resume();
+    }
+
+
+    fn pause(&mut self) {
+        self.device.pause();
+    }
+}
+
+
+// open a device on an existing SDL context - it plays silence until the core resumes the output
+pub fn open_remote(audio_subsystem: &AudioSubsystem, output: RemoteOutput) -> Result<AudioDevice<RemoteCallback>, String> {
+    let device = audio_subsystem.open_playback(None, &desired_spec(), |spec| {
+        println!("{:?}", spec);
+        RemoteCallback { output: output }
+    })?;
+
+    device.resume();
+    Ok(device)
+}
+
+
+// *** private functions *** //
+
+fn desired_spec() -> AudioSpecDesired {
+    AudioSpecDesired {
+        freq: Some(SAMPLE_RATE as i32),
+        channels: Some(CHANNELS as u8),
+        samples: Some(512), // default sample size
+    }
+}
@@ -0,0 +1,137 @@
+// WAV file output - samples are written as emulated time passes, so warp mode and
+// slow hosts still produce a recording at the right speed
+use audio::{AudioSink, SharedSource, CHANNELS, SAMPLE_RATE};
+use byteorder::{LittleEndian, WriteBytesExt};
+use std::fs::File;
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+
+const HEADER_LEN: u32 = 44;
+
+
+// 16-bit PCM file - the header sizes are filled in when it is dropped
+pub struct WavWriter {
+    filename: String,
+    file: BufWriter<File>,
+    channels: u16,
+    sample_rate: u32,
+    num_samples: u64,  // samples written so far (all channels)
+    failed: bool,      // stop reporting after the first write error
+}
+
+impl WavWriter {
+    pub fn create(filename: &str, channels: u16, sample_rate: u32) -> Result<WavWriter, String> {
+        let file = File::create(filename).map_err(|e| format!("Couldn't create {}: {}", filename, e))?;
+
+        let mut writer = WavWriter {
+            filename: String::from(filename),
+            file: BufWriter::new(file),
+            channels: channels,
+            sample_rate: sample_rate,
+            num_samples: 0,
+            failed: false,
+        };
+
+        writer.write_header(0).map_err(|e| format!("Error writing {}: {}", filename, e))?;
+        Ok(writer)
+    }
+
+
+    pub fn filename(&self) -> &str {
+        &self.filename
+    }
+
+
+    // interleaved samples, a multiple of the channel count
+    pub fn write(&mut self, samples: &[i16]) {
+        if self.failed {
+            return;
+        }
+
+        for &sample in samples.iter() {
+            if let Err(e) = self.file.write_i16::<LittleEndian>(sample) {
+                println!("Error writing {}: {}", self.filename, e);
+                self.failed = true;
+                return;
+            }
+        }
+
+        self.num_samples += samples.len() as u64;
+    }
+
+
+    // *** private functions *** //
+
+    fn write_header(&mut self, data_len: u32) -> Result<(), String> {
+        let frame_len = 2 * self.channels as u32;
+        let f = &mut self.file;
+        f.write_all(b"RIFF").map_err(|e| e.to_string())?;
+        f.write_u32::<LittleEndian>(HEADER_LEN - 8 + data_len).map_err(|e| e.to_string())?;
+        f.write_all(b"WAVE").map_err(|e| e.to_string())?;
+        f.write_all(b"fmt ").map_err(|e| e.to_string())?;
+        f.write_u32::<LittleEndian>(16).map_err(|e| e.to_string())?;              // fmt chunk size
+        f.write_u16::<LittleEndian>(1).map_err(|e| e.to_string())?;               // PCM
+        f.write_u16::<LittleEndian>(self.channels).map_err(|e| e.to_string())?;
+        f.write_u32::<LittleEndian>(self.sample_rate).map_err(|e| e.to_string())?;
+        f.write_u32::<LittleEndian>(self.sample_rate * frame_len).map_err(|e| e.to_string())?; // bytes per second
+        f.write_u16::<LittleEndian>(frame_len as u16).map_err(|e| e.to_string())?;             // bytes per frame
+        f.write_u16::<LittleEndian>(16).map_err(|e| e.to_string())?;              // bits per sample
+        f.write_all(b"data").map_err(|e| e.to_string())?;
+        f.write_u32::<LittleEndian>(data_len).map_err(|e| e.to_string())?;
+        Ok(())
+    }
+
+
+    fn finish(&mut self) -> Result<(), String> {
+        let data_len = (self.num_samples * 2) as u32;
+        self.file.seek(SeekFrom::Start(0)).map_err(|e| e.to_string())?;
+        self.write_header(data_len)?;
+        self.file.flush().map_err(|e| e.to_string())
+    }
+}
+
+
+impl Drop for WavWriter {
+    fn drop(&mut self) {
+        let seconds = self.num_samples as f64 / self.channels as f64 / self.sample_rate as f64;
+
+        match self.finish() {
+            Ok(_)  => println!("Wrote {}: {:.1} seconds", self.filename, seconds),
+            Err(e) => println!("Error writing {}: {}", self.filename, e),
+        }
+    }
+}
+
+
+// audio output going to a file instead of the sound card
+pub struct WavSink {
+    writer: WavWriter,
+    source: SharedSource,
+    buffer: Vec<i16>,
+}
+
+impl WavSink {
+    pub fn create(filename: &str, source: SharedSource) -> Result<WavSink, String> {
+        let writer = WavWriter::create(filename, CHANNELS as u16, SAMPLE_RATE)?;
+        println!("Writing audio to {}", filename);
+
+        Ok(WavSink {
+            writer: writer,
+            source: source,
+            buffer: Vec::new(),
+        })
+    }
+}
+
+
+impl AudioSink for WavSink {
+    // recording follows emulated time only
+    fn resume(&mut self) {}
+    fn pause(&mut self) {}
+
+
+    fn advance(&mut self) {
+        self.buffer.clear();
+        self.source.lock().unwrap().take(&mut self.buffer);
+        self.writer.write(&self.buffer);
+    }
+}
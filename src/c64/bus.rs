@@ -0,0 +1,232 @@
+// system bus: memory and I/O chips as seen by the CPU, plus the signal lines between the chips
+// all components are owned here and passed around by reference, so the per-cycle path
+// doesn't go through any RefCell borrow checks
+use c64::cia;
+use c64::cpu;
+use c64::expansion::{DmaBus, PortLines};
+use c64::memory;
+use c64::scheduler::Cycle;
+use c64::sound;
+use c64::vic;
+
+
+// interrupt and bus request lines driven by the VIC, CIAs and expansion port
+pub struct Lines {
+    pub ba_low:  bool, // is BA low?
+    pub cia_irq: bool,
+    pub vic_irq: bool,
+    pub exp_irq: bool, // IRQ line driven by the expansion port
+    pub nmi:     bool,
+    pub first_nmi_cycle: Cycle,
+    pub first_irq_cycle: Cycle,
+}
+
+impl Lines {
+    pub fn new() -> Lines {
+        Lines {
+            ba_low:  false,
+            cia_irq: false,
+            vic_irq: false,
+            exp_irq: false,
+            nmi:     false,
+            first_nmi_cycle: 0,
+            first_irq_cycle: 0,
+        }
+    }
+
+
+    pub fn set_vic_irq(&mut self, val: bool) {
+        self.vic_irq = val;
+    }
+
+
+    pub fn set_nmi(&mut self, val: bool) {
+        self.nmi = val;
+    }
+
+
+    pub fn set_cia_irq(&mut self, val: bool) {
+        self.cia_irq = val;
+    }
+
+
+    // on VIC/CIA register access perform necessary action on the CPU lines
+    pub fn apply(&mut self, callback: cpu::Callback) {
+        match callback {
+            cpu::Callback::TriggerVICIrq => self.set_vic_irq(true),
+            cpu::Callback::ClearVICIrq   => self.set_vic_irq(false),
+            cpu::Callback::TriggerCIAIrq => self.set_cia_irq(true),
+            cpu::Callback::ClearCIAIrq   => self.set_cia_irq(false),
+            cpu::Callback::TriggerNMI    => self.set_nmi(true),
+            cpu::Callback::ClearNMI      => self.set_nmi(false),
+            cpu::Callback::None => (),
+        }
+    }
+}
+
+
+pub struct Bus {
+    pub memory: memory::Memory,
+    pub vic:  vic::VIC,
+    pub cia1: cia::CIA,
+    pub cia2: cia::CIA,
+    pub sound: sound::Sound, // SID chips
+    pub lines: Lines,
+    pub cycle: Cycle, // current machine cycle - sleeping CIAs catch up to it when accessed
+    dfff_byte: u8,
+}
+
+impl Bus {
+    pub fn new(memory: memory::Memory, vic: vic::VIC, cia1: cia::CIA, cia2: cia::CIA, sound: sound::Sound) -> Bus {
+        Bus {
+            memory: memory,
+            vic:  vic,
+            cia1: cia1,
+            cia2: cia2,
+            sound: sound,
+            lines: Lines::new(),
+            cycle: 0,
+            dfff_byte: 0x55,
+        }
+    }
+
+
+    // CPU write - returns whether RAM was written (true) or RAM under ROM (false)
+    pub fn write_byte(&mut self, addr: u16, value: u8) -> bool {
+        let mut on_write = cpu::Callback::None;
+        let mut mem_write_ok = true;
+        let io_enabled = self.memory.io_on;
+
+        if io_enabled {
+            match addr {
+ /*   VIC-II  */ 0xD000..=0xD3FF => self.vic.write_register(&mut self.memory, addr, value, &mut on_write),
+ /*    SID    */ 0xD400..=0xD7FF => self.sound.write_register(&mut self.memory, addr, value),
+ /* color RAM */ 0xD800..=0xDBFF => mem_write_ok = self.memory.write_byte(addr, value & 0x0F),
+ /*    CIA1   */ 0xDC00..=0xDCFF => {
+                     self.cia1.wake(self.cycle);
+                     self.cia1.write_register(&mut self.memory, &mut self.lines, &mut self.vic, addr, value, &mut on_write);
+                     self.sound.select_pots(self.cia1.pot_select());
+                 },
+ /*    CIA2   */ 0xDD00..=0xDDFF => {
+                     self.cia2.wake(self.cycle);
+                     self.cia2.write_register(&mut self.memory, &mut self.lines, &mut self.vic, addr, value, &mut on_write);
+                 },
+ /* extra SID */ 0xDE00..=0xDFFF if self.sound.maps(addr) => self.sound.write_register(&mut self.memory, addr, value),
+ /* I/O1, I/O2 */ 0xDE00..=0xDFFF => {
+                     let claimed = self.memory.write_io(addr, value);
+                     if !claimed {
+                         mem_write_ok = self.memory.write_byte(addr, value);
+                     }
+                 },
+                 _               => mem_write_ok = self.memory.write_byte(addr, value),
+            }
+        }
+        else {
+            mem_write_ok = self.memory.write_byte(addr, value);
+        }
+
+        self.memory.snoop_write(addr, value);
+        self.lines.apply(on_write);
+
+        mem_write_ok
+    }
+
+
+    // CPU read
+    pub fn read_byte(&mut self, addr: u16) -> u8 {
+        let byte: u8;
+        let mut on_read = cpu::Callback::None;
+        let io_enabled = self.memory.io_on;
+
+        if io_enabled {
+            match addr {
+   /*  VIC-II   */ 0xD000..=0xD3FF => byte = self.vic.read_register(&mut self.memory, addr),
+   /*   SID     */ 0xD400..=0xD7FF => byte = self.sound.read_register(&mut self.memory, addr),
+   /* color RAM */ 0xD800..=0xDBFF => byte = (self.memory.read_byte(addr) & 0x0F) | (self.vic.last_byte & 0xF0),
+   /*   CIA1    */ 0xDC00..=0xDCFF => {
+                       self.cia1.wake(self.cycle);
+                       byte = self.cia1.read_register(addr, &mut on_read);
+                   },
+   /*   CIA2    */ 0xDD00..=0xDDFF => {
+                       self.cia2.wake(self.cycle);
+                       byte = self.cia2.read_register(addr, &mut on_read);
+                   },
+   /* extra SID */ 0xDE00..=0xDFFF if self.sound.maps(addr) => byte = self.sound.read_register(&mut self.memory, addr),
+   /* I/O1, I/O2 */ 0xDE00..=0xDFFF => {
+                       let io_byte = self.memory.read_io(addr);
+                       byte = match io_byte {
+                           Some(value) => value,
+                           None        => self.read_open_io(addr),
+                       };
+                   },
+                   _ => byte = self.memory.read_byte(addr)
+            }
+        }
+        else {
+            byte = self.memory.read_byte(addr);
+        }
+
+        self.lines.apply(on_read);
+
+        byte
+    }
+
+
+    pub fn read_word_le(&mut self, addr: u16) -> u16 {
+        self.memory.read_word_le(addr)
+    }
+
+
+    // clock the expansion device and perform its DMA cycle if it owns the bus
+    pub fn update_expansion(&mut self) -> PortLines {
+        let mut device = match self.memory.take_expansion() {
+            Some(device) => device,
+            None => return PortLines::default()
+        };
+
+        device.clock();
+
+        let dma = device.dma_active();
+        if dma {
+            device.dma_cycle(self);
+        }
+
+        let lines = PortLines {
+            irq: device.irq(),
+            nmi: device.nmi(),
+            dma: dma,
+        };
+
+        self.memory.return_expansion(device);
+        lines
+    }
+
+
+    // *** private functions *** //
+
+    // I/O1/I/O2 read with nothing on the expansion port driving the data bus
+    fn read_open_io(&mut self, addr: u16) -> u8 {
+        match addr {
+            0xDF00..=0xDF9F => self.vic.last_byte,
+            0xDFFF => {
+                self.dfff_byte = !self.dfff_byte;
+                self.dfff_byte
+            },
+            _ => self.memory.read_byte(addr)
+        }
+    }
+}
+
+
+// expansion port DMA sees the C64 side of the bus like the CPU does: RAM and ROM as mapped
+// by the current latch setting and the I/O chips at $D000-$DFFF
+impl DmaBus for Bus {
+    fn dma_read(&mut self, addr: u16) -> u8 {
+        self.read_byte(addr)
+    }
+
+
+    fn dma_write(&mut self, addr: u16, value: u8) {
+        let _ = self.write_byte(addr, value);
+    }
+}
@@ -1,11 +1,17 @@
 // CIA chip
+use c64::bus::Lines;
 use c64::cpu;
 use c64::memory;
+use c64::scheduler::Cycle;
 use c64::vic;
-use std::rc::Rc;
-use std::cell::RefCell;
 
-pub type CIAShared = Rc<RefCell<CIA>>;
+
+// CIA chip revision
+#[derive(Clone, Copy, PartialEq)]
+pub enum CIAModel {
+    MOS6526,  // original C64 - timer interrupt is delivered one cycle after underflow
+    MOS6526A, // C64C and later - timer interrupt is delivered in the same cycle
+}
 
 enum TimerState {
     Stop,
@@ -176,6 +182,27 @@ impl CIATimer {
     }
 
 
+    // cycles the timer can be left alone for: it's stopped or decrementing by one per cycle
+    // with the underflow still more than a cycle away - None if it needs to be clocked
+    fn idle_cycles(&self, ta_underflow: bool) -> Option<Cycle> {
+        if self.has_new_ctrl {
+            return None;
+        }
+
+        match self.state {
+            TimerState::Stop => Some(Cycle::max_value()),
+            TimerState::Count if !self.counts_every_cycle(ta_underflow) => Some(Cycle::max_value()),
+            TimerState::Count if self.value >= 2 => Some(self.value as Cycle - 1),
+            _ => None
+        }
+    }
+
+
+    fn counts_every_cycle(&self, ta_underflow: bool) -> bool {
+        self.is_cnt_phi2 || (self.cnt_ta_underflow && ta_underflow)
+    }
+
+
     pub fn count(&mut self, cia_icr: &mut u8, ta_underflow: bool) {
         if self.is_cnt_phi2 || (self.cnt_ta_underflow && ta_underflow) {
             let curr_val = self.value;
@@ -195,14 +222,13 @@ impl CIATimer {
 
 // the actual CIA chip including both timers
 pub struct CIA {
-    mem_ref: Option<memory::MemShared>,
-    cpu_ref: Option<cpu::CPUShared>,
-    vic_ref: Option<vic::VICShared>,
 
     is_cia1: bool,  // is this CIA1 or CIA2 chip?
+    model: CIAModel,
     
     timer_a: CIATimer,
     timer_b: CIATimer,
+    asleep_since: Option<Cycle>, // first cycle the timers weren't clocked in - None while awake
     irq_mask: u8,
     icr:  u8,
     pra:  u8,
@@ -230,6 +256,8 @@ pub struct CIA {
     pub rev_matrix: [u8; 8],
     pub joystick_1: u8,
     pub joystick_2: u8,
+    pub device_1: u8, // lines pulled low by paddles and mice in the control ports
+    pub device_2: u8,
     prev_lp: u8,
 
     // CIA2 only
@@ -237,15 +265,14 @@ pub struct CIA {
 }
 
 impl CIA {
-    pub fn new_shared(is_cia1: bool) -> CIAShared {
-        Rc::new(RefCell::new(CIA {
-            mem_ref: None,
-            cpu_ref: None,
-            vic_ref: None,
+    pub fn new(is_cia1: bool, model: CIAModel) -> CIA {
+        CIA {
 
             is_cia1: is_cia1,
+            model: model,
             timer_a: CIATimer::new(true),
             timer_b: CIATimer::new(false),
+            asleep_since: None,
             irq_mask: 0,
             icr: 0,
             pra: 0,
@@ -270,24 +297,20 @@ impl CIA {
             rev_matrix: [0xFF; 8],
             joystick_1: 0xFF,
             joystick_2: 0xFF,
+            device_1: 0xFF,
+            device_2: 0xFF,
             prev_lp: 0x10,
 
             // CIA2 only
             iec_lines: 0xD0
-        }))
-    }
-
-
-    pub fn set_references(&mut self, memref: memory::MemShared, cpuref: cpu::CPUShared, vicref: vic::VICShared) {
-        self.mem_ref = Some(memref);
-        self.cpu_ref = Some(cpuref);
-        self.vic_ref = Some(vicref);
+        }
     }
 
 
     pub fn reset(&mut self) {
         self.timer_a.reset();
         self.timer_b.reset();
+        self.asleep_since = None;
         self.irq_mask = 0;
         self.icr = 0;
         self.pra = 0;
@@ -321,10 +344,72 @@ impl CIA {
     }
 
 
-    pub fn update(&mut self) {
+    // CIA1: control ports connected to the SID's POT pins (bit 0: port 1, bit 1: port 2)
+    pub fn pot_select(&self) -> u8 {
+        ((self.pra | !self.ddra) >> 6) & 0x03
+    }
+
+
+    pub fn update(&mut self, lines: &mut Lines) {
+        if self.asleep_since.is_some() {
+            return;
+        }
+
         self.timer_a.update(&mut self.icr, false);
         let ta_underflow = self.timer_a.underflow;
         self.timer_b.update(&mut self.icr, ta_underflow);
+
+        if self.model == CIAModel::MOS6526A {
+            self.process_irq(lines);
+        }
+    }
+
+
+    // cycles both timers can skip without anything observable happening - None if the CIA
+    // has to be clocked next cycle, Cycle::max_value() if nothing is counting at all
+    pub fn idle_cycles(&self) -> Option<Cycle> {
+        if self.timer_a.irq_next_cycle {
+            return None;
+        }
+
+        let ta_idle = self.timer_a.idle_cycles(false)?;
+        let tb_idle = self.timer_b.idle_cycles(self.timer_a.underflow)?;
+        Some(ta_idle.min(tb_idle))
+    }
+
+
+    // stop clocking the timers from the given cycle on - only valid while idle_cycles() allows it
+    pub fn sleep(&mut self, from: Cycle) {
+        self.asleep_since = Some(from);
+    }
+
+
+    pub fn is_asleep(&self) -> bool {
+        self.asleep_since.is_some()
+    }
+
+
+    // catch up on the cycles slept through, up to and including the given one
+    pub fn wake(&mut self, through: Cycle) {
+        let since = match self.asleep_since.take() {
+            Some(since) => since,
+            None => return
+        };
+
+        let elapsed = (through + 1).saturating_sub(since) as u16;
+        let ta_underflow = self.timer_a.underflow;
+
+        if let TimerState::Count = self.timer_a.state {
+            if self.timer_a.counts_every_cycle(false) {
+                self.timer_a.value -= elapsed;
+            }
+        }
+
+        if let TimerState::Count = self.timer_b.state {
+            if self.timer_b.counts_every_cycle(ta_underflow) {
+                self.timer_b.value -= elapsed;
+            }
+        }
     }
 
 
@@ -370,29 +455,29 @@ impl CIA {
     }
 
 
-    pub fn write_register(&mut self, addr: u16, value: u8, on_cia_write: &mut cpu::Callback) {
+    pub fn write_register(&mut self, mem: &mut memory::Memory, lines: &mut Lines, vic: &mut vic::VIC, addr: u16, value: u8, on_cia_write: &mut cpu::Callback) {
         match addr & 0x00FF {
             0x04 => {
                 self.timer_a.latch = (self.timer_a.latch & 0xFF00) | value as u16;
-                as_ref!(self.mem_ref).get_ram_bank(memory::MemType::Io).write(addr, value);
+                mem.get_ram_bank(memory::MemType::Io).write(addr, value);
             },
             0x05 => {
                 self.timer_a.latch = (self.timer_a.latch & 0x00FF) | ((value as u16) << 8);
                 if (self.timer_a.ctrl & 1) == 0 {
                     self.timer_a.value = self.timer_a.latch;
                 }
-                as_ref!(self.mem_ref).get_ram_bank(memory::MemType::Io).write(addr, value);
+                mem.get_ram_bank(memory::MemType::Io).write(addr, value);
             },
             0x06 => {
                 self.timer_b.latch = (self.timer_b.latch & 0xFF00) | value as u16;
-                as_ref!(self.mem_ref).get_ram_bank(memory::MemType::Io).write(addr, value);
+                mem.get_ram_bank(memory::MemType::Io).write(addr, value);
             },
             0x07 => {
                 self.timer_b.latch = (self.timer_b.latch & 0x00FF) | ((value as u16) << 8);
                 if (self.timer_b.ctrl & 1) == 0 {
                     self.timer_b.value = self.timer_b.latch;
                 }
-                as_ref!(self.mem_ref).get_ram_bank(memory::MemType::Io).write(addr, value);
+                mem.get_ram_bank(memory::MemType::Io).write(addr, value);
             },
             0x08 => {
                 if (self.timer_b.ctrl & 0x80) != 0 {
@@ -401,7 +486,7 @@ impl CIA {
                 else {
                     self.tod_dsec = value & 0x0F;
                 }
-                as_ref!(self.mem_ref).get_ram_bank(memory::MemType::Io).write(addr, value);
+                mem.get_ram_bank(memory::MemType::Io).write(addr, value);
             },
             0x09 => {
                 if (self.timer_b.ctrl & 0x80) != 0 {
@@ -410,7 +495,7 @@ impl CIA {
                 else {
                     self.tod_sec = value & 0x7F;
                 }
-                as_ref!(self.mem_ref).get_ram_bank(memory::MemType::Io).write(addr, value);
+                mem.get_ram_bank(memory::MemType::Io).write(addr, value);
             },
             0x0A => {
                 if (self.timer_b.ctrl & 0x80) != 0 {
@@ -419,7 +504,7 @@ impl CIA {
                 else {
                     self.tod_min = value & 0x7F;
                 }
-                as_ref!(self.mem_ref).get_ram_bank(memory::MemType::Io).write(addr, value);
+                mem.get_ram_bank(memory::MemType::Io).write(addr, value);
             },
              0x0B => {
                 if (self.timer_b.ctrl & 0x80) != 0 {
@@ -428,7 +513,7 @@ impl CIA {
                 else {
                     self.tod_hour = value & 0x9F;
                 }
-                 as_ref!(self.mem_ref).get_ram_bank(memory::MemType::Io).write(addr, value);
+                 mem.get_ram_bank(memory::MemType::Io).write(addr, value);
              },
             0x0C => {
                 self.sdr = value;
@@ -449,41 +534,41 @@ impl CIA {
                     self.icr |= 0x80;
                     *on_cia_write = if self.is_cia1 { cpu::Callback::TriggerCIAIrq } else { cpu::Callback::TriggerNMI };
                 }
-                as_ref!(self.mem_ref).get_ram_bank(memory::MemType::Io).write(addr, value);
+                mem.get_ram_bank(memory::MemType::Io).write(addr, value);
             },
             0x0E => {
                 self.timer_a.has_new_ctrl = true;
                 self.timer_a.new_ctrl = value;
                 self.timer_a.is_cnt_phi2 = (value & 0x20) == 0;
-                as_ref!(self.mem_ref).get_ram_bank(memory::MemType::Io).write(addr, value);
+                mem.get_ram_bank(memory::MemType::Io).write(addr, value);
             },
             0x0F => {
                 self.timer_b.has_new_ctrl = true;
                 self.timer_b.new_ctrl = value;
                 self.timer_b.is_cnt_phi2 = (value & 0x60) == 0;
                 self.timer_b.cnt_ta_underflow = (value & 0x60) == 0x40;
-                as_ref!(self.mem_ref).get_ram_bank(memory::MemType::Io).write(addr, value);
+                mem.get_ram_bank(memory::MemType::Io).write(addr, value);
             },
             _ => {
                 if self.is_cia1 {
-                    self.write_cia1_register(addr, value, on_cia_write);
+                    self.write_cia1_register(mem, lines, vic, addr, value, on_cia_write);
                 }
                 else {
-                    self.write_cia2_register(addr, value, on_cia_write);
+                    self.write_cia2_register(mem, lines, vic, addr, value, on_cia_write);
                 }
             }
         }
     }
 
 
-    pub fn process_irq(&mut self) {
+    pub fn process_irq(&mut self, lines: &mut Lines) {
         if self.timer_a.irq_next_cycle {
             if self.trigger_irq(1) {
                 if self.is_cia1 {
-                    as_mut!(self.cpu_ref).set_cia_irq(true);
+                    lines.set_cia_irq(true);
                 }
                 else {
-                    as_mut!(self.cpu_ref).set_nmi(true);
+                    lines.set_nmi(true);
                 }
             }
             
@@ -492,10 +577,10 @@ impl CIA {
         if self.timer_a.irq_next_cycle {
             if self.trigger_irq(2) {
                 if self.is_cia1 {
-                    as_mut!(self.cpu_ref).set_cia_irq(true);
+                    lines.set_cia_irq(true);
                 }
                 else {
-                    as_mut!(self.cpu_ref).set_nmi(true);
+                    lines.set_nmi(true);
                 }
             }
             
@@ -504,7 +589,7 @@ impl CIA {
     }
 
 
-    pub fn count_tod(&mut self) {
+    pub fn count_tod(&mut self, lines: &mut Lines) {
         let mut lo: u8;
         let mut hi: u8;
 
@@ -576,10 +661,10 @@ impl CIA {
                (self.tod_hour == self.alarm_hour) {
                 if self.trigger_irq(4) {
                     if self.is_cia1 {
-                        as_mut!(self.cpu_ref).set_cia_irq(true);
+                        lines.set_cia_irq(true);
                     }
                     else {
-                        as_mut!(self.cpu_ref).set_nmi(true);
+                        lines.set_nmi(true);
                     };
                 }
             }
@@ -607,7 +692,7 @@ impl CIA {
         match addr {
             0xDC00 => {
                 let mut retval = self.pra | !self.ddra;
-                let tst = (self.prb | !self.ddrb) & self.joystick_1;
+                let tst = (self.prb | !self.ddrb) & self.joystick_1 & self.device_1;
 
                 if tst & 0x01 == 0 { retval &= self.rev_matrix[0]; }
                 if tst & 0x02 == 0 { retval &= self.rev_matrix[1]; }
@@ -618,11 +703,11 @@ impl CIA {
                 if tst & 0x40 == 0 { retval &= self.rev_matrix[6]; }
                 if tst & 0x80 == 0 { retval &= self.rev_matrix[7]; }
                 
-                retval & self.joystick_2
+                retval & self.joystick_2 & self.device_2
             },
             0xDC01 => {
                 let mut retval = !self.ddrb;
-                let tst = (self.pra | !self.ddra) & self.joystick_2;
+                let tst = (self.pra | !self.ddra) & self.joystick_2 & self.device_2;
 
                 if tst & 0x01 == 0 { retval &= self.key_matrix[0]; }
                 if tst & 0x02 == 0 { retval &= self.key_matrix[1]; }
@@ -633,7 +718,7 @@ impl CIA {
                 if tst & 0x40 == 0 { retval &= self.key_matrix[6]; }
                 if tst & 0x80 == 0 { retval &= self.key_matrix[7]; }
 
-                (retval | (self.prb & self.ddrb)) & self.joystick_1
+                (retval | (self.prb & self.ddrb)) & self.joystick_1 & self.device_1
             },
             0xDC10..=0xDCFF => self.read_cia1_register(0xDC00 + (addr % 0x0010)),
             _ => panic!("Address out of CIA1 memory range: ${:04X}", addr),
@@ -641,27 +726,27 @@ impl CIA {
     }
 
 
-    fn write_cia1_register(&mut self, addr: u16, value: u8, on_cia_write: &mut cpu::Callback) {
+    fn write_cia1_register(&mut self, mem: &mut memory::Memory, lines: &mut Lines, vic: &mut vic::VIC, addr: u16, value: u8, on_cia_write: &mut cpu::Callback) {
         match addr {
             0xDC00 => {
                 self.pra = value;
-                as_ref!(self.mem_ref).get_ram_bank(memory::MemType::Io).write(addr, value);
+                mem.get_ram_bank(memory::MemType::Io).write(addr, value);
             },
             0xDC01 => {
                 self.prb = value;
-                self.check_lp();
-                as_ref!(self.mem_ref).get_ram_bank(memory::MemType::Io).write(addr, value);
+                self.check_lp(mem, lines, vic);
+                mem.get_ram_bank(memory::MemType::Io).write(addr, value);
             },
             0xDC02 => {
                 self.ddra = value;
-                as_ref!(self.mem_ref).get_ram_bank(memory::MemType::Io).write(addr, value);
+                mem.get_ram_bank(memory::MemType::Io).write(addr, value);
             },
             0xDC03 => {
                 self.ddrb = value;
-                as_ref!(self.mem_ref).get_ram_bank(memory::MemType::Io).write(addr, value);
-                self.check_lp();
+                mem.get_ram_bank(memory::MemType::Io).write(addr, value);
+                self.check_lp(mem, lines, vic);
             },
-            0xDC10..=0xDCFF => self.write_cia1_register(0xDC00 + (addr % 0x0010), value, on_cia_write),
+            0xDC10..=0xDCFF => self.write_cia1_register(mem, lines, vic, 0xDC00 + (addr % 0x0010), value, on_cia_write),
             _ => panic!("Address out of CIA1 memory range"),
         }
     }
@@ -680,35 +765,121 @@ impl CIA {
     }
 
 
-    fn write_cia2_register(&mut self, addr: u16, value: u8, on_cia_write: &mut cpu::Callback) {
+    fn write_cia2_register(&mut self, mem: &mut memory::Memory, lines: &mut Lines, vic: &mut vic::VIC, addr: u16, value: u8, on_cia_write: &mut cpu::Callback) {
         match addr {
             0xDD00 => {
                 // TODO
                 self.pra = value;
-                as_mut!(self.vic_ref).on_va_change(!(self.pra | !self.ddra) & 3);
-                as_ref!(self.mem_ref).get_ram_bank(memory::MemType::Io).write(addr, value);
+                vic.on_va_change(mem, !(self.pra | !self.ddra) & 3);
+                mem.get_ram_bank(memory::MemType::Io).write(addr, value);
             },
             0xDD01 => {
                 self.prb = value;
-                as_ref!(self.mem_ref).get_ram_bank(memory::MemType::Io).write(addr, value);
+                mem.get_ram_bank(memory::MemType::Io).write(addr, value);
             },
             0xDD02 => {
                 self.ddra = value;
-                as_mut!(self.vic_ref).on_va_change(!(self.pra | !self.ddra) & 3);
-                as_ref!(self.mem_ref).get_ram_bank(memory::MemType::Io).write(addr, value);
+                vic.on_va_change(mem, !(self.pra | !self.ddra) & 3);
+                mem.get_ram_bank(memory::MemType::Io).write(addr, value);
             },
-            0xDD03 => { self.ddrb = value; as_ref!(self.mem_ref).get_ram_bank(memory::MemType::Io).write(addr, value); },
-            0xDD10..=0xDDFF => self.write_cia2_register(0xDD00 + (addr % 0x0010), value, on_cia_write),
+            0xDD03 => { self.ddrb = value; mem.get_ram_bank(memory::MemType::Io).write(addr, value); },
+            0xDD10..=0xDDFF => self.write_cia2_register(mem, lines, vic, 0xDD00 + (addr % 0x0010), value, on_cia_write),
             _ => panic!("Address out of CIA2 memory range"),
         }
     }
 
 
-    fn check_lp(&mut self) {
-        if ((self.prb | !self.ddrb) & 0x10) != self.prev_lp {
-            as_mut!(self.vic_ref).trigger_lp_irq();
+    fn check_lp(&mut self, mem: &mut memory::Memory, lines: &mut Lines, vic: &mut vic::VIC) {
+        let lp = (self.prb | !self.ddrb) & 0x10;
+        if lp != self.prev_lp {
+            vic.set_lp_input(mem, lines, lp == 0);
+        }
+
+        self.prev_lp = lp;
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use c64::roms::RomSet;
+
+    // a CIA that sleeps while idle has to look exactly like one clocked every cycle
+    #[test]
+    fn sleeping_timers_match_clocked_timers() {
+        let mut mem = memory::Memory::new(RomSet::unpopulated(), 0x10000, true);
+        let mut vic = vic::VIC::new(vic::VICModel::MOS6569);
+        let mut clocked = CIA::new(true, CIAModel::MOS6526);
+        let mut lazy    = CIA::new(true, CIAModel::MOS6526);
+        let mut clocked_lines = Lines::new();
+        let mut lazy_lines    = Lines::new();
+        let mut wake_at = None;
+        let mut seed: u32 = 0x1234_5678;
+        let mut slept = 0;
+
+        for cycle in 0..200_000 as Cycle {
+            clocked.process_irq(&mut clocked_lines);
+            clocked.update(&mut clocked_lines);
+            lazy.process_irq(&mut lazy_lines);
+            lazy.update(&mut lazy_lines);
+
+            seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+            let action = seed >> 16;
+
+            if action % 23 == 0 {
+                // CPU access this cycle
+                let (addr, value) = match (action >> 5) % 9 {
+                    0 => (0xDC04, (action >> 8) as u8 | 1),
+                    1 => (0xDC05, (action >> 9) as u8 & 0x03),
+                    2 => (0xDC06, (action >> 8) as u8 | 1),
+                    3 => (0xDC07, (action >> 9) as u8 & 0x03),
+                    4 => (0xDC0E, (action >> 8) as u8 & 0x19),
+                    5 => (0xDC0F, (action >> 8) as u8 & 0x59),
+                    6 => (0xDC0D, (action >> 8) as u8 & 0x83),
+                    _ => (0xDC04 + ((action >> 8) % 10) as u16, 0),
+                };
+
+                let mut clocked_cb = cpu::Callback::None;
+                let mut lazy_cb    = cpu::Callback::None;
+                lazy.wake(cycle);
+
+                if (action >> 5) % 9 < 7 {
+                    clocked.write_register(&mut mem, &mut clocked_lines, &mut vic, addr, value, &mut clocked_cb);
+                    lazy.write_register(&mut mem, &mut lazy_lines, &mut vic, addr, value, &mut lazy_cb);
+                }
+                else {
+                    let expected = clocked.read_register(addr, &mut clocked_cb);
+                    assert_eq!(lazy.read_register(addr, &mut lazy_cb), expected, "${:04X} at cycle {}", addr, cycle);
+                }
+
+                clocked_lines.apply(clocked_cb);
+                lazy_lines.apply(lazy_cb);
+            }
+
+            if wake_at == Some(cycle) {
+                lazy.wake(cycle);
+                wake_at = None;
+            }
+
+            if !lazy.is_asleep() {
+                match lazy.idle_cycles() {
+                    Some(idle) if idle > 0 => {
+                        lazy.sleep(cycle + 1);
+                        if idle != Cycle::max_value() {
+                            wake_at = Some(cycle + idle);
+                        }
+                    },
+                    _ => ()
+                }
+            }
+            else {
+                slept += 1;
+            }
+
+            assert_eq!(lazy_lines.cia_irq, clocked_lines.cia_irq, "IRQ line at cycle {}", cycle);
         }
 
-        self.prev_lp = (self.prb | !self.ddrb) & 0x10;
+        assert!(slept > 0);
     }
 }
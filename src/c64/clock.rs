@@ -1,32 +1,73 @@
 // timing clock structure
+// emulation runs in bursts of a frame worth of cycles, after which the clock
+// sleeps until wall-clock time catches up with emulated time
 extern crate time;
 
+use std::thread;
+use std::time::Duration;
+
+// don't try to catch up if emulation fell behind by more than this (in seconds)
+const MAX_LAG: f64 = 0.1;
+
 pub struct Clock {
-    curr_time: f64,
-    last_time: f64,
-    clock_period: f64,
+    next_time: f64, // wall-clock time at which emulation should continue
+    base_freq: f64, // frequency at 100% speed
+    speed: u32,     // emulation speed in percent
+    warp: bool,     // run as fast as possible
 }
 
 impl Clock {
     pub fn new(freq: f64) -> Clock {
-        let mut clock = Clock {
-            curr_time: 0.0,
-            last_time: 0.0,
-            clock_period: 1.0 / freq,
-        };
-
-        clock.last_time = time::precise_time_s();
-        clock
+        Clock {
+            next_time: time::precise_time_s(),
+            base_freq: freq,
+            speed: 100,
+            warp: false,
+        }
     }
 
-    pub fn tick(&mut self) -> bool {
-        self.curr_time = time::precise_time_s();
 
-        if self.curr_time - self.last_time >= self.clock_period {
-            self.last_time = self.curr_time;
-            return true
+    // account for emulated cycles and wait until they are due in real time
+    pub fn sync(&mut self, cycles: u32) {
+        if self.warp {
+            return;
+        }
+
+        self.next_time += (cycles as f64) * 100.0 / (self.base_freq * self.speed as f64);
+
+        let now = time::precise_time_s();
+        if self.next_time > now {
+            thread::sleep(Duration::from_secs_f64(self.next_time - now));
         }
+        else if now - self.next_time > MAX_LAG {
+            self.next_time = now;
+        }
+    }
+
+
+    // start counting from now - after a pause or leaving warp mode
+    pub fn resync(&mut self) {
+        self.next_time = time::precise_time_s();
+    }
+
+
+    pub fn speed(&self) -> u32 {
+        self.speed
+    }
+
+
+    pub fn set_speed(&mut self, percent: u32) {
+        self.speed = percent;
+    }
+
+
+    pub fn is_warp(&self) -> bool {
+        self.warp
+    }
+
 
-        false
+    pub fn set_warp(&mut self, warp: bool) {
+        self.warp = warp;
+        self.resync();
     }
 }
@@ -0,0 +1,208 @@
+// devices in the control ports besides the keyboard joystick: paddles and mice, driven by the host mouse
+// (and the num-pad for paddles). They pull the joystick lines of CIA1 low and set the POTX/POTY
+// inputs that the SID measures - the SID only sees the port selected with CIA1 port A bits 6-7
+extern crate minifb;
+
+use c64;
+use minifb::*;
+
+const PADDLE_KEY_SPEED: f32 = 4.0; // paddle units per frame while a num-pad key is held
+const MOUSE_MAX_STEP: i32 = 31;    // 1351: largest move per frame the 6 bit position can represent
+const MOUSE_1350_MAX: f32 = 8.0;   // 1350: frames of movement kept when the host mouse moves faster
+
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Device {
+    Joystick,  // nothing but the keyboard joystick
+    Paddles,   // pair of paddles: mouse X/Y or num-pad 4/6 and 8/2
+    Mouse1351, // proportional mouse: position in the POT registers
+    Mouse1350, // joystick mode mouse: movement as joystick directions
+}
+
+impl Device {
+    pub fn from_name(name: &str) -> Option<Device> {
+        match &name.to_lowercase()[..] {
+            "joystick"           => Some(Device::Joystick),
+            "paddles" | "paddle" => Some(Device::Paddles),
+            "1351" | "mouse"     => Some(Device::Mouse1351),
+            "1350"               => Some(Device::Mouse1350),
+            _ => None
+        }
+    }
+
+
+    pub fn name(&self) -> &str {
+        match *self {
+            Device::Joystick  => "joystick",
+            Device::Paddles   => "paddles",
+            Device::Mouse1351 => "1351 mouse",
+            Device::Mouse1350 => "1350 mouse",
+        }
+    }
+}
+
+
+pub struct ControlPort {
+    device: Device,
+    mouse_pos: Option<(f32, f32)>, // host mouse in frame pixels
+    buttons: [bool; 2],            // left, right mouse button
+    paddles: [f32; 2],             // paddle positions (0-255)
+    paddle_keys: [f32; 2],         // num-pad rotation direction per paddle
+    paddle_fire: [bool; 2],        // num-pad fire buttons
+    target: (i32, i32),            // 1351: where the host mouse has moved the mouse
+    position: (i32, i32),          // 1351: position the C64 sees (6 bits used)
+    motion: (f32, f32),            // 1350: movement not turned into joystick pulses yet
+    lines: u8,                     // joystick lines, active low
+}
+
+impl ControlPort {
+    pub fn new() -> ControlPort {
+        ControlPort {
+            device: Device::Joystick,
+            mouse_pos: None,
+            buttons: [false; 2],
+            paddles: [128.0; 2],
+            paddle_keys: [0.0; 2],
+            paddle_fire: [false; 2],
+            target: (0, 0),
+            position: (0, 0),
+            motion: (0.0, 0.0),
+            lines: 0xFF,
+        }
+    }
+
+
+    pub fn device(&self) -> Device {
+        self.device
+    }
+
+
+    pub fn set_device(&mut self, device: Device) {
+        self.device = device;
+        self.lines = 0xFF;
+    }
+
+
+    pub fn on_mouse_move(&mut self, x: f32, y: f32) {
+        // paddles follow the absolute position: moving right or down turns them clockwise
+        if self.device == Device::Paddles {
+            self.paddles[0] = (255.0 - x * 256.0 / c64::SCREEN_WIDTH as f32).max(0.0);
+            self.paddles[1] = (255.0 - y * 256.0 / c64::SCREEN_HEIGHT as f32).max(0.0);
+        }
+
+        let (last_x, last_y) = match self.mouse_pos {
+            Some(pos) => pos,
+            None => { self.mouse_pos = Some((x, y)); return; }
+        };
+
+        // whole pixels only - the rest is kept for the next move
+        let (dx, dy) = ((x - last_x).trunc(), (y - last_y).trunc());
+        self.mouse_pos = Some((last_x + dx, last_y + dy));
+
+        match self.device {
+            Device::Mouse1351 => {
+                // the mouse counts up when moved up, unlike screen coordinates
+                self.target.0 += dx as i32;
+                self.target.1 -= dy as i32;
+            },
+            Device::Mouse1350 => {
+                self.motion.0 = (self.motion.0 + dx).max(-MOUSE_1350_MAX).min(MOUSE_1350_MAX);
+                self.motion.1 = (self.motion.1 + dy).max(-MOUSE_1350_MAX).min(MOUSE_1350_MAX);
+            },
+            _ => (),
+        }
+    }
+
+
+    // the next move starts over when the mouse comes back into the window
+    pub fn on_mouse_out(&mut self) {
+        self.mouse_pos = None;
+    }
+
+
+    pub fn on_mouse_button(&mut self, button: MouseButton, down: bool) {
+        match button {
+            MouseButton::Left  => self.buttons[0] = down,
+            MouseButton::Right => self.buttons[1] = down,
+            _ => (),
+        }
+    }
+
+
+    // num-pad control of the paddles - returns false for keys that aren't used
+    pub fn on_key(&mut self, keycode: Key, key_pressed: bool) -> bool {
+        if self.device != Device::Paddles {
+            return false;
+        }
+
+        let rotation = if key_pressed { PADDLE_KEY_SPEED } else { 0.0 };
+
+        match keycode {
+            Key::NumPad4   => self.paddle_keys[0] = rotation,
+            Key::NumPad6   => self.paddle_keys[0] = -rotation,
+            Key::NumPad8   => self.paddle_keys[1] = rotation,
+            Key::NumPad2   => self.paddle_keys[1] = -rotation,
+            Key::RightCtrl => self.paddle_fire[0] = key_pressed,
+            Key::NumPad5   => self.paddle_fire[1] = key_pressed,
+            _ => return false,
+        }
+
+        true
+    }
+
+
+    // called every frame - moves the device and updates its lines
+    pub fn update(&mut self) {
+        self.lines = 0xFF;
+
+        match self.device {
+            Device::Joystick => (),
+            Device::Paddles => {
+                for i in 0..2 {
+                    self.paddles[i] = (self.paddles[i] + self.paddle_keys[i]).max(0.0).min(255.0);
+                }
+
+                // fire buttons are on the joystick left/right lines
+                if self.buttons[0] || self.paddle_fire[0] { self.lines &= !0x04; }
+                if self.buttons[1] || self.paddle_fire[1] { self.lines &= !0x08; }
+            },
+            Device::Mouse1351 | Device::Mouse1350 => {
+                if self.device == Device::Mouse1351 {
+                    // the driver reads the position once per frame and only sees 6 bits,
+                    // so fast moves are spread over several frames
+                    let (dx, dy) = (self.target.0 - self.position.0, self.target.1 - self.position.1);
+                    self.position.0 += dx.max(-MOUSE_MAX_STEP).min(MOUSE_MAX_STEP);
+                    self.position.1 += dy.max(-MOUSE_MAX_STEP).min(MOUSE_MAX_STEP);
+                }
+                else {
+                    // one pulse per frame in each direction the mouse moved
+                    if self.motion.0 <= -1.0 { self.lines &= !0x04; self.motion.0 += 1.0; }
+                    if self.motion.0 >= 1.0  { self.lines &= !0x08; self.motion.0 -= 1.0; }
+                    if self.motion.1 <= -1.0 { self.lines &= !0x01; self.motion.1 += 1.0; }
+                    if self.motion.1 >= 1.0  { self.lines &= !0x02; self.motion.1 -= 1.0; }
+                }
+
+                // left button is fire, the right one is joystick up
+                if self.buttons[0] { self.lines &= !0x10; }
+                if self.buttons[1] { self.lines &= !0x01; }
+            },
+        }
+    }
+
+
+    // joystick lines pulled low by the device
+    pub fn lines(&self) -> u8 {
+        self.lines
+    }
+
+
+    // POTX/POTY values - 0xFF with nothing connected
+    pub fn pots(&self) -> (u8, u8) {
+        match self.device {
+            Device::Paddles => (self.paddles[0] as u8, self.paddles[1] as u8),
+            // position in bits 1-6, bit 0 is noise on the real mouse and ignored by drivers
+            Device::Mouse1351 => ((((self.position.0 as u32) & 0x3F) << 1) as u8, (((self.position.1 as u32) & 0x3F) << 1) as u8),
+            _ => (0xFF, 0xFF),
+        }
+    }
+}
@@ -1,13 +1,20 @@
-use std;
 use std::fs::File;
-use std::io::{Read, Seek, SeekFrom};
-use std::str;
+use std::io::{self, BufReader, Read, Write};
+use std::error;
 use std::fmt;
-use c64::memory;
+use c64::expansion::ExpansionPort;
 
-use byteorder::{BigEndian, ReadBytesExt};
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
 use num::FromPrimitive;
 
+pub const CRT_SIGNATURE: &'static [u8; 16] = b"C64 CARTRIDGE   ";
+const HEADER_LEN:      u32 = 0x40;
+const CHIP_HEADER_LEN: u32 = 0x10;
+
+// hardware types
+pub const HW_GENERIC:   u16 = 0;
+pub const HW_EASYFLASH: u16 = 32;
+
 #[derive(Debug)]
 pub struct Crt {
     header: Header,
@@ -15,46 +22,83 @@ pub struct Crt {
 }
 
 impl Crt {
-    pub fn from_filename(filename: &str) -> Result<Crt, String> {
-        let mut file = File::open(filename).map_err(|e| e.to_string())?;
+    pub fn from_filename(filename: &str) -> Result<Crt, CrtError> {
+        let file = File::open(filename)?;
+        Crt::from_reader(&mut BufReader::new(file))
+    }
+
+
+    pub fn from_bytes(data: &[u8]) -> Result<Crt, CrtError> {
+        Crt::from_reader(&mut &data[..])
+    }
+
 
+    pub fn from_reader<R: Read>(reader: &mut R) -> Result<Crt, CrtError> {
         // Read Header
-        let mut signature = [0u8; 16];
-        file.read(&mut signature).map_err(|e| e.to_string())?;
-        if &signature != b"C64 CARTRIDGE   " {
-            return Err("Invalid cartridge signature".to_string())
-        }
-        let header_len = file.read_u32::<BigEndian>().map_err(|e| e.to_string())?;
-        let mut version = [0u8;2];
-        file.read(&mut version).map_err(|e| e.to_string())?;
-        let hw_type = file.read_u16::<BigEndian>().map_err(|e| e.to_string())?;
-        if hw_type != 0 {
-            return Err("Unsupported cartridge type".to_string())
-        }
-        let exrom = file.read_u8().map_err(|e| e.to_string())?;
-        let game = file.read_u8().map_err(|e| e.to_string())?;
-        file.seek(SeekFrom::Start(0x20)).map_err(|e| e.to_string())?;
+        let mut header_bytes = [0u8; HEADER_LEN as usize];
+        let header_read = read_fully(reader, &mut header_bytes)?;
+        if header_read < CRT_SIGNATURE.len() || &header_bytes[..16] != CRT_SIGNATURE {
+            return Err(CrtError::InvalidSignature);
+        }
+        if header_read < header_bytes.len() {
+            return Err(CrtError::TruncatedHeader);
+        }
+
+        let mut header_data = &header_bytes[16..];
+        let header_len = header_data.read_u32::<BigEndian>()?;
+        let mut version = [0u8; 2];
+        header_data.read_exact(&mut version)?;
+        let hw_type = header_data.read_u16::<BigEndian>()?;
+        let exrom = header_data.read_u8()?;
+        let game = header_data.read_u8()?;
         let mut name = [0u8; 32];
-        file.read(&mut name).map_err(|e| e.to_string())?;
-        
+        name.copy_from_slice(&header_bytes[0x20..0x40]);
+
+        // skip extended header - shorter header length fields are treated as the minimum of 0x40
+        let mut offset = HEADER_LEN as u64;
+        if header_len > HEADER_LEN {
+            let extra = (header_len - HEADER_LEN) as u64;
+            let skipped = io::copy(&mut reader.by_ref().take(extra), &mut io::sink())?;
+            if skipped < extra {
+                return Err(CrtError::TruncatedHeader);
+            }
+            offset += extra;
+        }
+
         // Read Chips
-        file.seek(SeekFrom::Start(header_len as u64)).map_err(|e| e.to_string())?;
         let mut chips: Vec<Chip> = Vec::new();
         loop {
-            let mut chip_signature = [0u8;4];
-            file.read(&mut chip_signature).map_err(|e| e.to_string())?;
-            if &chip_signature != b"CHIP" {
-                break;
+            let chip_index = chips.len();
+            let mut chip_signature = [0u8; 4];
+            match read_fully(reader, &mut chip_signature)? {
+                0 => break,
+                4 if &chip_signature == b"CHIP" => (),
+                _ => return Err(CrtError::InvalidChipSignature { chip: chip_index, offset: offset }),
+            }
+
+            let mut chip_header = [0u8; (CHIP_HEADER_LEN - 4) as usize];
+            let header_read = read_fully(reader, &mut chip_header)?;
+            if header_read < chip_header.len() {
+                return Err(CrtError::TruncatedChip { chip: chip_index, expected: CHIP_HEADER_LEN as usize, read: 4 + header_read });
             }
-            let length = file.read_u32::<BigEndian>().map_err(|e| e.to_string())?;
-            let chip_type = ChipType::from_u16(file.read_u16::<BigEndian>()
-                .map_err(|e| e.to_string())?).ok_or("Invalid chip type".to_string())?;
-            let bank_number = file.read_u16::<BigEndian>().map_err(|e| e.to_string())?;
-            let load_addr = file.read_u16::<BigEndian>().map_err(|e| e.to_string())?;
-            let data_size = file.read_u16::<BigEndian>().map_err(|e| e.to_string())?;
+
+            let mut chip_data = &chip_header[..];
+            let length = chip_data.read_u32::<BigEndian>()?;
+            let raw_chip_type = chip_data.read_u16::<BigEndian>()?;
+            let chip_type = ChipType::from_u16(raw_chip_type)
+                .ok_or(CrtError::InvalidChipType { chip: chip_index, chip_type: raw_chip_type })?;
+            let bank_number = chip_data.read_u16::<BigEndian>()?;
+            let load_addr = chip_data.read_u16::<BigEndian>()?;
+            let data_size = chip_data.read_u16::<BigEndian>()?;
+
+            // the data size is what the packet holds - a wrong length field is left for validate() to report
             let mut data: Vec<u8> = vec![0u8; data_size as usize];
-            file.read(&mut data).map_err(|e| e.to_string())?;
+            let data_read = read_fully(reader, &mut data)?;
+            if data_read < data.len() {
+                return Err(CrtError::TruncatedChip { chip: chip_index, expected: data.len(), read: data_read });
+            }
 
+            offset += (CHIP_HEADER_LEN + data_size as u32) as u64;
             chips.push(Chip {
                 signature: chip_signature,
                 length: length,
@@ -66,10 +110,9 @@ impl Crt {
             });
         }
 
-
         Ok(Crt {
             header: Header {
-                signature: signature,
+                signature: *CRT_SIGNATURE,
                 header_len: header_len,
                 version: version,
                 hw_type: hw_type,
@@ -81,15 +124,405 @@ impl Crt {
         })
     }
 
-    pub fn load_into_memory(&self, mut memory: std::cell::RefMut<memory::Memory>) {
-        memory.exrom = self.header.exrom == 1;
-        memory.game = self.header.game == 1;
+
+    // empty image to be filled with CHIP packets
+    pub fn new(hw_type: u16, exrom: u8, game: u8, name: &str) -> Crt {
+        let mut name_bytes = [0u8; 32];
+        for (dst, src) in name_bytes.iter_mut().zip(name.to_uppercase().bytes().filter(|c| c.is_ascii())) {
+            *dst = src;
+        }
+
+        Crt {
+            header: Header {
+                signature: *CRT_SIGNATURE,
+                header_len: HEADER_LEN,
+                version: [0x01, 0x00],
+                hw_type: hw_type,
+                exrom: exrom,
+                game: game,
+                name: name_bytes,
+            },
+            chips: Vec::new(),
+        }
+    }
+
+
+    // a CHIP packet stores its ROM size in 16 bits
+    pub fn add_chip(&mut self, chip_type: ChipType, bank_number: u16, load_addr: u16, data: Vec<u8>) -> Result<(), String> {
+        if data.len() > 0xFFFF {
+            return Err(format!("CHIP packet for bank {} at ${:04X} is {} bytes, at most 65535 fit", bank_number, load_addr, data.len()));
+        }
+
+        self.chips.push(Chip {
+            signature: *b"CHIP",
+            length: CHIP_HEADER_LEN + data.len() as u32,
+            chip_type: chip_type,
+            bank_number: bank_number,
+            load_addr: load_addr,
+            data_size: data.len() as u16,
+            data: data,
+        });
+        Ok(())
+    }
+
+
+    pub fn save(&self, filename: &str) -> Result<(), CrtError> {
+        let mut file = File::create(filename)?;
+
+        // Write Header
+        file.write_all(&self.header.signature)?;
+        file.write_u32::<BigEndian>(HEADER_LEN)?;
+        file.write_all(&self.header.version)?;
+        file.write_u16::<BigEndian>(self.header.hw_type)?;
+        file.write_u8(self.header.exrom)?;
+        file.write_u8(self.header.game)?;
+        file.write_all(&[0u8; 6])?;
+        file.write_all(&self.header.name)?;
+
+        // Write Chips
         for chip in self.chips.iter() {
-            let base_addr = chip.load_addr;
-            for (offset, byte) in chip.data.iter().enumerate() {
-                memory.write_byte(base_addr+offset as u16, *byte);
+            file.write_all(b"CHIP")?;
+            file.write_u32::<BigEndian>(CHIP_HEADER_LEN + chip.data.len() as u32)?;
+            file.write_u16::<BigEndian>(chip.chip_type as u16)?;
+            file.write_u16::<BigEndian>(chip.bank_number)?;
+            file.write_u16::<BigEndian>(chip.load_addr)?;
+            file.write_u16::<BigEndian>(chip.data_size)?;
+            file.write_all(&chip.data)?;
+        }
+
+        Ok(())
+    }
+
+
+    pub fn hw_type(&self) -> u16 {
+        self.header.hw_type
+    }
+
+
+    pub fn chips(&self) -> &[Chip] {
+        &self.chips
+    }
+
+
+    // sanity check of header and CHIP packets - returns a list of human-readable problems
+    pub fn validate(&self) -> Vec<Diagnostic> {
+        let mut diags = Vec::new();
+        let header = &self.header;
+
+        if header.header_len < HEADER_LEN {
+            diags.push(Diagnostic::warning(format!("header length field is {} bytes, should be at least {} (treated as {})",
+                                                   header.header_len, HEADER_LEN, HEADER_LEN)));
+        }
+
+        match (header.version[0], header.version[1]) {
+            (1, 0) | (1, 1) | (2, 0) => (),
+            (major, minor) => diags.push(Diagnostic::warning(format!("unknown CRT version {}.{:02}", major, minor))),
+        }
+
+        if hw_type_name(header.hw_type).is_none() {
+            diags.push(Diagnostic::warning(format!("unknown hardware type {}", header.hw_type)));
+        }
+
+        if header.exrom > 1 || header.game > 1 {
+            diags.push(Diagnostic::error(format!("EXROM ({}) and GAME ({}) lines must be 0 or 1", header.exrom, header.game)));
+        }
+
+        if header.name.iter().any(|&c| c != 0 && (c < 0x20 || c > 0x7E)) {
+            diags.push(Diagnostic::warning("cartridge name contains non-printable or non-ASCII characters".to_string()));
+        }
+
+        if self.chips.is_empty() {
+            diags.push(Diagnostic::error("no CHIP packets found".to_string()));
+        }
+
+        let mut seen: Vec<(u16, u16)> = Vec::new();
+
+        for (i, chip) in self.chips.iter().enumerate() {
+            let what = format!("CHIP #{} (bank {}, ${:04X})", i, chip.bank_number, chip.load_addr);
+            let size = chip.data_size as u32;
+
+            if chip.length != CHIP_HEADER_LEN + size {
+                diags.push(Diagnostic::error(format!("{}: packet length field is {} bytes, but header and data add up to {} bytes",
+                                                     what, chip.length, CHIP_HEADER_LEN + size)));
+            }
+
+            match chip.load_addr {
+                0x8000 | 0xA000 | 0xE000 | 0xF000 => (),
+                _ => diags.push(Diagnostic::error(format!("{}: load address is not a cartridge ROM area ($8000, $A000, $E000 or $F000)", what))),
             }
+
+            if chip.load_addr as u32 + size > 0x10000 {
+                diags.push(Diagnostic::error(format!("{}: {} bytes of data run past $FFFF", what, size)));
+            }
+            else if chip.load_addr != 0x8000 && size > 0x2000 {
+                diags.push(Diagnostic::error(format!("{}: {} bytes don't fit the 8k ROMH window", what, size)));
+            }
+
+            match size {
+                0x1000 | 0x2000 | 0x4000 => (),
+                _ => diags.push(Diagnostic::warning(format!("{}: unusual ROM size of {} bytes", what, size))),
+            }
+
+            match header.hw_type {
+                HW_GENERIC if chip.bank_number != 0 => {
+                    diags.push(Diagnostic::error(format!("{}: generic cartridges have a single bank (bank 0)", what)));
+                },
+                HW_EASYFLASH if chip.bank_number > 63 => {
+                    diags.push(Diagnostic::error(format!("{}: EasyFlash has 64 banks (0-63)", what)));
+                },
+                _ => ()
+            }
+
+            if seen.contains(&(chip.bank_number, chip.load_addr)) {
+                diags.push(Diagnostic::error(format!("{}: duplicate bank", what)));
+            }
+            seen.push((chip.bank_number, chip.load_addr));
+
+            // is the ROM visible in the configuration selected by the header?
+            if header.hw_type == HW_GENERIC {
+                match (header.exrom, header.game, chip.load_addr) {
+                    (0, 1, 0xA000) | (0, 1, 0xE000) => {
+                        diags.push(Diagnostic::warning(format!("{}: 8k cartridge mode never maps ROMH", what)));
+                    },
+                    (1, 0, 0xA000) => {
+                        diags.push(Diagnostic::warning(format!("{}: Ultimax mode maps ROMH at $E000, not $A000", what)));
+                    },
+                    (0, 0, 0xE000) => {
+                        diags.push(Diagnostic::warning(format!("{}: 16k cartridge mode maps ROMH at $A000, not $E000", what)));
+                    },
+                    _ => ()
+                }
+            }
+        }
+
+        if header.hw_type == HW_GENERIC && header.exrom == 1 && header.game == 1 {
+            diags.push(Diagnostic::warning("EXROM and GAME are both inactive - cartridge ROM is never visible".to_string()));
         }
+
+        diags
+    }
+
+
+    // turn the image into a device that can be plugged into the expansion port
+    pub fn into_cartridge(self) -> Result<Cartridge, CrtError> {
+        if self.header.hw_type != HW_GENERIC {
+            return Err(CrtError::UnsupportedHardware(self.header.hw_type))
+        }
+
+        let name_len = self.header.name.iter().position(|&c| c == 0).unwrap_or(self.header.name.len());
+        let mut cartridge = Cartridge {
+            name: String::from_utf8_lossy(&self.header.name[..name_len]).trim().to_string(),
+            exrom: self.header.exrom == 1,
+            game:  self.header.game == 1,
+            roml_banks: Vec::new(),
+            romh_banks: Vec::new(),
+            bank: 0,
+        };
+
+        for chip in self.chips.into_iter() {
+            let bank = chip.bank_number as usize;
+            match chip.load_addr {
+                0x8000 => {
+                    // 16k chips cover both ROML and ROMH
+                    if chip.data.len() > 0x2000 {
+                        Cartridge::set_bank(&mut cartridge.romh_banks, bank, chip.data[0x2000..].to_vec());
+                        Cartridge::set_bank(&mut cartridge.roml_banks, bank, chip.data[..0x2000].to_vec());
+                    }
+                    else {
+                        Cartridge::set_bank(&mut cartridge.roml_banks, bank, chip.data);
+                    }
+                },
+                0xA000 | 0xE000 | 0xF000 => Cartridge::set_bank(&mut cartridge.romh_banks, bank, chip.data),
+                _ => println!("Ignoring CHIP packet with unsupported load address ${:04X}", chip.load_addr),
+            }
+        }
+
+        Ok(cartridge)
+    }
+}
+
+
+// hardware type names as assigned in the CRT specification
+pub fn hw_type_name(hw_type: u16) -> Option<&'static str> {
+    let name = match hw_type {
+        0  => "Generic",
+        1  => "Action Replay",
+        2  => "KCS Power Cartridge",
+        3  => "Final Cartridge III",
+        4  => "Simons' BASIC",
+        5  => "Ocean",
+        6  => "Expert Cartridge",
+        7  => "Fun Play",
+        8  => "Super Games",
+        9  => "Atomic Power",
+        10 => "Epyx FastLoad",
+        11 => "Westermann Learning",
+        12 => "Rex Utility",
+        13 => "Final Cartridge I",
+        14 => "Magic Formel",
+        15 => "C64 Game System",
+        16 => "Warp Speed",
+        17 => "Dinamic",
+        18 => "Zaxxon",
+        19 => "Magic Desk",
+        20 => "Super Snapshot V5",
+        21 => "Comal-80",
+        22 => "Structured BASIC",
+        23 => "Ross",
+        24 => "Dela EP64",
+        25 => "Dela EP7x8",
+        26 => "Dela EP256",
+        27 => "Rex EP256",
+        28 => "Mikro Assembler",
+        29 => "Final Cartridge Plus",
+        30 => "Action Replay 4",
+        31 => "Stardos",
+        32 => "EasyFlash",
+        _  => return None
+    };
+
+    Some(name)
+}
+
+
+// reasons an image can't be loaded or saved
+#[derive(Debug)]
+pub enum CrtError {
+    Io(io::Error),
+    InvalidSignature,
+    TruncatedHeader,
+    InvalidChipSignature { chip: usize, offset: u64 },
+    InvalidChipType { chip: usize, chip_type: u16 },
+    TruncatedChip { chip: usize, expected: usize, read: usize },
+    UnsupportedHardware(u16),
+}
+
+impl fmt::Display for CrtError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            CrtError::Io(ref e) => write!(f, "{}", e),
+            CrtError::InvalidSignature => write!(f, "not a C64 cartridge image (missing \"C64 CARTRIDGE\" signature)"),
+            CrtError::TruncatedHeader => write!(f, "file ends inside the CRT header"),
+            CrtError::InvalidChipSignature { chip, offset } => {
+                write!(f, "CHIP #{}: expected CHIP packet at offset ${:X}", chip, offset)
+            },
+            CrtError::InvalidChipType { chip, chip_type } => write!(f, "CHIP #{}: invalid chip type {}", chip, chip_type),
+            CrtError::TruncatedChip { chip, expected, read } => {
+                write!(f, "CHIP #{}: file ends after {} of {} bytes", chip, read, expected)
+            },
+            CrtError::UnsupportedHardware(hw_type) => {
+                write!(f, "unsupported cartridge type {} ({})", hw_type, hw_type_name(hw_type).unwrap_or("Unknown"))
+            },
+        }
+    }
+}
+
+impl error::Error for CrtError {}
+
+impl From<io::Error> for CrtError {
+    fn from(e: io::Error) -> CrtError {
+        CrtError::Io(e)
+    }
+}
+
+
+// like read_exact, but reports how many bytes were read before the end of the stream
+fn read_fully<R: Read>(reader: &mut R, buf: &mut [u8]) -> io::Result<usize> {
+    let mut total = 0;
+    while total < buf.len() {
+        match reader.read(&mut buf[total..]) {
+            Ok(0) => break,
+            Ok(n) => total += n,
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => (),
+            Err(e) => return Err(e),
+        }
+    }
+
+    Ok(total)
+}
+
+
+// problem found while validating an image
+pub struct Diagnostic {
+    pub is_error: bool,
+    pub message: String,
+}
+
+impl Diagnostic {
+    fn error(message: String) -> Diagnostic {
+        Diagnostic { is_error: true, message: message }
+    }
+
+
+    fn warning(message: String) -> Diagnostic {
+        Diagnostic { is_error: false, message: message }
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", if self.is_error { "error" } else { "warning" }, self.message)
+    }
+}
+
+
+// generic cartridge as seen from the expansion port
+pub struct Cartridge {
+    name: String,
+    exrom: bool,
+    game:  bool,
+    roml_banks: Vec<Vec<u8>>,
+    romh_banks: Vec<Vec<u8>>,
+    bank: usize, // currently active bank
+}
+
+impl Cartridge {
+    fn set_bank(banks: &mut Vec<Vec<u8>>, bank: usize, data: Vec<u8>) {
+        while banks.len() <= bank {
+            banks.push(Vec::new());
+        }
+
+        banks[bank] = data;
+    }
+
+
+    // ROMs smaller than the 8k window are mirrored
+    fn read_bank(banks: &[Vec<u8>], bank: usize, addr: u16) -> Option<u8> {
+        match banks.get(bank) {
+            Some(data) if !data.is_empty() => Some(data[(addr & 0x1FFF) as usize % data.len()]),
+            _ => None
+        }
+    }
+}
+
+impl ExpansionPort for Cartridge {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+
+    fn read_roml(&mut self, addr: u16) -> Option<u8> {
+        Cartridge::read_bank(&self.roml_banks, self.bank, addr)
+    }
+
+
+    fn read_romh(&mut self, addr: u16) -> Option<u8> {
+        Cartridge::read_bank(&self.romh_banks, self.bank, addr)
+    }
+
+
+    fn game(&self) -> bool {
+        self.game
+    }
+
+
+    fn exrom(&self) -> bool {
+        self.exrom
+    }
+
+
+    fn reset(&mut self) {
+        self.bank = 0;
     }
 }
 
@@ -116,19 +549,19 @@ impl fmt::Debug for Header {
     game: {},
     name: {}
 }}",
-            str::from_utf8(&self.signature).unwrap(),
+            String::from_utf8_lossy(&self.signature),
             self.header_len,
             self.version[0],
             self.version[1],
             self.hw_type,
             self.exrom,
             self.game,
-            str::from_utf8(&self.name).unwrap()
+            String::from_utf8_lossy(&self.name).trim_end_matches('\0')
         )
     }
 }
 
-struct Chip {
+pub struct Chip {
     signature: [u8; 4],
     length: u32, // header and data combined
     chip_type: ChipType,
@@ -138,6 +571,22 @@ struct Chip {
     data: Vec<u8>,
 }
 
+impl Chip {
+    pub fn bank_number(&self) -> u16 {
+        self.bank_number
+    }
+
+
+    pub fn load_addr(&self) -> u16 {
+        self.load_addr
+    }
+
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+}
+
 impl fmt::Debug for Chip {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f,
@@ -150,7 +599,7 @@ impl fmt::Debug for Chip {
     data_size: {} bytes,
     data: (not shown)
 }}",
-            str::from_utf8(&self.signature).unwrap(),
+            String::from_utf8_lossy(&self.signature),
             self.length,
             self.chip_type,
             self.bank_number,
@@ -161,10 +610,105 @@ impl fmt::Debug for Chip {
 }
 
 enum_from_primitive! {
-    #[derive(Debug, PartialEq)]
-    enum ChipType {
+    #[derive(Debug, PartialEq, Clone, Copy)]
+    pub enum ChipType {
         ROM,
         RAM,
         Flash,
     }
 }
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header() -> Vec<u8> {
+        let mut bytes = CRT_SIGNATURE.to_vec();
+        bytes.extend_from_slice(&[0x00, 0x00, 0x00, 0x40, 0x01, 0x00, 0x00, 0x00, 0x00, 0x01]);
+        bytes.extend_from_slice(&[0u8; 6]);
+        bytes.extend_from_slice(&[0u8; 32]);
+        bytes
+    }
+
+
+    fn chip(length: u32, data_size: u16) -> Vec<u8> {
+        let mut bytes = b"CHIP".to_vec();
+        bytes.write_u32::<BigEndian>(length).unwrap();
+        bytes.extend_from_slice(&[0x00, 0x00, 0x00, 0x00, 0x80, 0x00]);
+        bytes.write_u16::<BigEndian>(data_size).unwrap();
+        bytes.extend(vec![0xEA; data_size as usize]);
+        bytes
+    }
+
+
+    #[test]
+    fn valid_image() {
+        let mut image = header();
+        image.extend(chip(0x2010, 0x2000));
+
+        let crt = Crt::from_bytes(&image).unwrap();
+        assert_eq!(crt.chips().len(), 1);
+        assert_eq!(crt.chips()[0].load_addr(), 0x8000);
+        assert!(crt.validate().iter().all(|diag| !diag.is_error));
+    }
+
+
+    #[test]
+    fn truncated_header() {
+        match Crt::from_bytes(&header()[..0x30]) {
+            Err(CrtError::TruncatedHeader) => (),
+            other => panic!("expected TruncatedHeader, got {:?}", other),
+        }
+    }
+
+
+    #[test]
+    fn bad_signature() {
+        let mut image = header();
+        image[0] = b'D';
+
+        match Crt::from_bytes(&image) {
+            Err(CrtError::InvalidSignature) => (),
+            other => panic!("expected InvalidSignature, got {:?}", other),
+        }
+    }
+
+
+    #[test]
+    fn truncated_chip_header() {
+        let mut image = header();
+        image.extend_from_slice(&chip(0x2010, 0x2000)[..10]);
+
+        match Crt::from_bytes(&image) {
+            Err(CrtError::TruncatedChip { chip: 0, expected: 0x10, read: 10 }) => (),
+            other => panic!("expected TruncatedChip, got {:?}", other),
+        }
+    }
+
+
+    #[test]
+    fn truncated_chip_data() {
+        let mut image = header();
+        image.extend(chip(0x2010, 0x2000));
+        image.truncate(image.len() - 0x1000);
+
+        match Crt::from_bytes(&image) {
+            Err(CrtError::TruncatedChip { chip: 0, expected: 0x2000, read: 0x1000 }) => (),
+            other => panic!("expected TruncatedChip, got {:?}", other),
+        }
+    }
+
+
+    #[test]
+    fn length_mismatch_is_reported_by_validate() {
+        let mut image = header();
+        image.extend(chip(0x4010, 0x2000));
+        image.extend(chip(0x2010, 0x2000));
+
+        // packets are parsed by data size, so the second CHIP is still found
+        let crt = Crt::from_bytes(&image).unwrap();
+        assert_eq!(crt.chips().len(), 2);
+        assert!(crt.validate().iter().any(|diag| diag.is_error && diag.message.contains("packet length field is 16400 bytes")));
+    }
+}
@@ -0,0 +1,67 @@
+// expansion port devices: cartridges, RAM expansions, debug devices
+// A device may supply ROML ($8000-$9FFF) and ROMH ($A000-$BFFF or $E000-$FFFF in Ultimax mode) data,
+// occupy the I/O1 ($DE00-$DEFF) and I/O2 ($DF00-$DFFF) areas, drive the GAME/EXROM lines,
+// assert IRQ/NMI and take over the bus for DMA transfers.
+
+// C64 side of the bus while an expansion device performs DMA
+pub trait DmaBus {
+    fn dma_read(&mut self, addr: u16) -> u8;
+    fn dma_write(&mut self, addr: u16, value: u8);
+}
+
+
+// state of the expansion port output lines after a cycle
+#[derive(Clone, Copy, Default)]
+pub struct PortLines {
+    pub irq: bool, // IRQ asserted
+    pub nmi: bool, // NMI asserted
+    pub dma: bool, // device owns the bus - CPU is halted
+}
+
+
+// devices are set up on the main thread and handed to the emulation core thread
+pub trait ExpansionPort: Send {
+    // device name, used for console output
+    fn name(&self) -> &str;
+
+    // ROML read - None if the device doesn't drive the data bus at this address
+    fn read_roml(&mut self, _addr: u16) -> Option<u8> { None }
+
+    // ROMH read - None if the device doesn't drive the data bus at this address
+    fn read_romh(&mut self, _addr: u16) -> Option<u8> { None }
+
+    // write to an address where ROML/ROMH is visible (cartridge RAM, flash)
+    fn write_rom(&mut self, _addr: u16, _value: u8) {}
+
+    // read from I/O1/I/O2 - None if the device doesn't drive the data bus at this address
+    fn read_io(&mut self, _addr: u16) -> Option<u8> { None }
+
+    // write to I/O1/I/O2 - returns true if the device claimed the write
+    fn write_io(&mut self, _addr: u16, _value: u8) -> bool { false }
+
+    // every CPU write is visible on the expansion port bus
+    fn snoop_write(&mut self, _addr: u16, _value: u8) {}
+
+    // GAME and EXROM lines - true means high (inactive)
+    fn game(&self)  -> bool { true }
+    fn exrom(&self) -> bool { true }
+
+    // interrupt lines - true means asserted
+    fn irq(&self) -> bool { false }
+    fn nmi(&self) -> bool { false }
+
+    // called once per cycle
+    fn clock(&mut self) {}
+
+    // true if the device requests the bus this cycle
+    fn dma_active(&self) -> bool { false }
+
+    // perform a single DMA cycle - only called when dma_active() is true
+    fn dma_cycle(&mut self, _bus: &mut dyn DmaBus) {}
+
+    // hardware reset (reset button or power cycle)
+    fn reset(&mut self) {}
+
+    // emulator is shutting down - persist any state that needs it
+    fn flush(&mut self) {}
+}
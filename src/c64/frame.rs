@@ -0,0 +1,130 @@
+// lock-free frame hand-off from the emulation core to the UI thread
+// triple buffering: the core always has a back buffer to fill and the UI always has a complete
+// front buffer to present - the middle buffer is exchanged between them with an atomic swap
+use std::cell::UnsafeCell;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+// set in the middle index when it holds a frame the UI hasn't picked up yet
+const NEW_FRAME: usize = 0x04;
+
+
+struct Buffers {
+    frames: [UnsafeCell<Vec<u32>>; 3],
+    middle: AtomicUsize,
+}
+
+// a buffer is only ever accessed by the side whose index currently points to it
+unsafe impl Sync for Buffers {}
+
+
+// core side
+pub struct FrameWriter {
+    buffers: Arc<Buffers>,
+    back: usize,
+}
+
+// UI side
+pub struct FrameReader {
+    buffers: Arc<Buffers>,
+    front: usize,
+}
+
+
+pub fn frame_buffers(size: usize) -> (FrameWriter, FrameReader) {
+    let buffers = Arc::new(Buffers {
+        frames: [UnsafeCell::new(vec![0; size]), UnsafeCell::new(vec![0; size]), UnsafeCell::new(vec![0; size])],
+        middle: AtomicUsize::new(1),
+    });
+
+    (FrameWriter { buffers: buffers.clone(), back: 0 }, FrameReader { buffers: buffers, front: 2 })
+}
+
+
+impl FrameWriter {
+    // copy a finished frame to the back buffer and hand it over as the newest one
+    pub fn publish(&mut self, frame: &[u32]) {
+        let back: &mut Vec<u32> = unsafe { &mut *self.buffers.frames[self.back].get() };
+        back.copy_from_slice(frame);
+
+        let prev = self.buffers.middle.swap(self.back | NEW_FRAME, Ordering::AcqRel);
+        self.back = prev & !NEW_FRAME;
+    }
+}
+
+
+impl FrameReader {
+    // newest complete frame - None if nothing was published since the last call
+    pub fn latest(&mut self) -> Option<&[u32]> {
+        if self.buffers.middle.load(Ordering::Acquire) & NEW_FRAME == 0 {
+            return None;
+        }
+
+        let prev = self.buffers.middle.swap(self.front, Ordering::AcqRel);
+        self.front = prev & !NEW_FRAME;
+
+        let frame: &Vec<u32> = unsafe { &*self.buffers.frames[self.front].get() };
+        Some(&frame[..])
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn reader_gets_the_newest_frame_once() {
+        let (mut writer, mut reader) = frame_buffers(4);
+        assert_eq!(reader.latest(), None);
+
+        writer.publish(&[1; 4]);
+        writer.publish(&[2; 4]);
+        assert_eq!(reader.latest(), Some(&[2; 4][..]));
+        assert_eq!(reader.latest(), None);
+
+        writer.publish(&[3; 4]);
+        assert_eq!(reader.latest(), Some(&[3; 4][..]));
+    }
+
+    #[test]
+    fn writer_and_reader_never_share_a_buffer() {
+        let (mut writer, mut reader) = frame_buffers(1);
+
+        for i in 0..10 {
+            writer.publish(&[i]);
+            if i % 3 == 0 {
+                reader.latest();
+            }
+
+            let middle = writer.buffers.middle.load(Ordering::Acquire) & !NEW_FRAME;
+            assert!(writer.back != reader.front && writer.back != middle && reader.front != middle);
+        }
+    }
+
+    #[test]
+    fn frames_are_never_torn_or_stale() {
+        const SIZE: usize = 4096;
+        const FRAMES: u32 = 2000;
+
+        let (mut writer, mut reader) = frame_buffers(SIZE);
+
+        let core = thread::spawn(move || {
+            for i in 1..=FRAMES {
+                writer.publish(&[i; SIZE]);
+            }
+        });
+
+        let mut last = 0;
+        while last < FRAMES {
+            if let Some(frame) = reader.latest() {
+                assert!(frame.iter().all(|&pixel| pixel == frame[0]), "torn frame");
+                assert!(frame[0] > last, "frame {} presented after frame {}", frame[0], last);
+                last = frame[0];
+            }
+        }
+
+        core.join().unwrap();
+    }
+}
@@ -0,0 +1,127 @@
+// GeoRAM/NeoRAM memory expansion
+// 256 byte window at $DE00-$DEFF, page register at $DFFE, block register at $DFFF
+use c64::expansion::ExpansionPort;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::Path;
+
+pub const VALID_SIZES_KB: [usize; 4] = [512, 1024, 2048, 4096];
+
+const BLOCK_SIZE: usize = 0x4000; // 16k per block
+const PAGE_SIZE:  usize = 0x0100; // 64 pages per block
+
+
+pub struct GeoRAM {
+    ram: Vec<u8>,
+    page:  u8,       // currently selected page within the block
+    block: u8,       // currently selected 16k block
+    block_mask: u8,  // number of blocks - 1
+    image_file: Option<String>, // image file to persist the contents in (NeoRAM)
+}
+
+impl GeoRAM {
+    pub fn new(size_kb: usize, image_file: Option<&str>) -> Result<GeoRAM, String> {
+        if !VALID_SIZES_KB.contains(&size_kb) {
+            return Err(format!("Invalid GeoRAM size: {}K (valid sizes: 512, 1024, 2048, 4096)", size_kb));
+        }
+
+        let size = size_kb * 1024;
+        let mut georam = GeoRAM {
+            ram: vec![0; size],
+            page:  0,
+            block: 0,
+            block_mask: ((size / BLOCK_SIZE) - 1) as u8,
+            image_file: image_file.map(String::from),
+        };
+
+        if let Some(filename) = image_file {
+            if Path::new(filename).exists() {
+                georam.load_image(filename)?;
+            }
+        }
+
+        Ok(georam)
+    }
+
+
+    // *** private functions *** //
+
+    fn load_image(&mut self, filename: &str) -> Result<(), String> {
+        let mut file = File::open(filename).map_err(|e| format!("Couldn't open {}: {}", filename, e))?;
+        let mut data = Vec::<u8>::new();
+        file.read_to_end(&mut data).map_err(|e| format!("Error reading {}: {}", filename, e))?;
+
+        if data.len() != self.ram.len() {
+            println!("Warning: GeoRAM image {} is {} bytes, expected {} - contents will be truncated or padded",
+                     filename, data.len(), self.ram.len());
+        }
+
+        let len = data.len().min(self.ram.len());
+        self.ram[..len].copy_from_slice(&data[..len]);
+        println!("Read {}: {} bytes", filename, len);
+        Ok(())
+    }
+
+
+    fn save_image(&self, filename: &str) -> Result<(), String> {
+        let mut file = File::create(filename).map_err(|e| format!("Couldn't create {}: {}", filename, e))?;
+        file.write_all(&self.ram).map_err(|e| format!("Error writing {}: {}", filename, e))?;
+        println!("Wrote {}: {} bytes", filename, self.ram.len());
+        Ok(())
+    }
+
+
+    // offset of the byte currently visible at $DE00 + (addr & 0xFF)
+    fn ram_offset(&self, addr: u16) -> usize {
+        ((self.block & self.block_mask) as usize) * BLOCK_SIZE +
+        ((self.page & 0x3F) as usize) * PAGE_SIZE +
+        (addr & 0x00FF) as usize
+    }
+}
+
+impl ExpansionPort for GeoRAM {
+    fn name(&self) -> &str {
+        if self.image_file.is_some() { "NeoRAM" } else { "GeoRAM" }
+    }
+
+
+    fn read_io(&mut self, addr: u16) -> Option<u8> {
+        match addr {
+            0xDE00..=0xDEFF => Some(self.ram[self.ram_offset(addr)]),
+            0xDFFE          => Some(self.page),
+            0xDFFF          => Some(self.block),
+            _               => None
+        }
+    }
+
+
+    fn write_io(&mut self, addr: u16, value: u8) -> bool {
+        match addr {
+            0xDE00..=0xDEFF => {
+                let offset = self.ram_offset(addr);
+                self.ram[offset] = value;
+            },
+            0xDFFE => self.page  = value & 0x3F,
+            0xDFFF => self.block = value & self.block_mask,
+            _      => return false
+        }
+
+        true
+    }
+
+
+    fn reset(&mut self) {
+        // RAM contents survive a reset, only the registers are cleared
+        self.page  = 0;
+        self.block = 0;
+    }
+
+
+    fn flush(&mut self) {
+        if let Some(ref filename) = self.image_file {
+            if let Err(e) = self.save_image(filename) {
+                println!("{}", e);
+            }
+        }
+    }
+}
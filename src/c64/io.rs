@@ -1,8 +1,10 @@
-// keyboard and joystick support
+// keyboard, joystick and control port device support
 extern crate minifb;
 
 use minifb::*;
 use c64::cia;
+use c64::control_port;
+use c64::sound;
 
 /*
  C64 keyboard map:
@@ -23,132 +25,120 @@ pub struct IO {
     keyboard_state: [bool; 0xFF], // key states, including shift presses
     joystick_state: [bool; 0x0A], // 9 directions (num-pad) + 1 fire button
     joy_port1: bool,  // is joystick plugged to port 1?
+    has_keyboard: bool, // C64GS has no keyboard (and no Restore key) - only joysticks
+    ports: [control_port::ControlPort; 2], // paddles and mice
 }
 
 impl IO {
-    pub fn new() -> IO {
+    pub fn new(has_keyboard: bool) -> IO {
         IO {
             keyboard_state: [false; 0xFF],
             joystick_state: [false; 0x0A],
-            joy_port1: false
+            joy_port1: false,
+            has_keyboard: has_keyboard,
+            ports: [control_port::ControlPort::new(), control_port::ControlPort::new()],
         }
     }
 
 
-    pub fn update(&mut self, window: &Window, cia1: &mut cia::CIAShared) {
-        // keyboard processing
-        // iterating over all keys is crawling-slow, so check individual keys
-        self.process_key(window.is_key_down(Key::Key0), Key::Key0, cia1);
-        self.process_key(window.is_key_down(Key::Key1), Key::Key1, cia1);
-        self.process_key(window.is_key_down(Key::Key2), Key::Key2, cia1);
-        self.process_key(window.is_key_down(Key::Key3), Key::Key3, cia1);
-        self.process_key(window.is_key_down(Key::Key4), Key::Key4, cia1);
-        self.process_key(window.is_key_down(Key::Key5), Key::Key5, cia1);
-        self.process_key(window.is_key_down(Key::Key6), Key::Key6, cia1);
-        self.process_key(window.is_key_down(Key::Key7), Key::Key7, cia1);
-        self.process_key(window.is_key_down(Key::Key8), Key::Key8, cia1);
-        self.process_key(window.is_key_down(Key::Key9), Key::Key9, cia1);
-
-        self.process_key(window.is_key_down(Key::A), Key::A, cia1);
-        self.process_key(window.is_key_down(Key::B), Key::B, cia1);
-        self.process_key(window.is_key_down(Key::C), Key::C, cia1);
-        self.process_key(window.is_key_down(Key::D), Key::D, cia1);
-        self.process_key(window.is_key_down(Key::E), Key::E, cia1);
-        self.process_key(window.is_key_down(Key::F), Key::F, cia1);
-        self.process_key(window.is_key_down(Key::G), Key::G, cia1);
-        self.process_key(window.is_key_down(Key::H), Key::H, cia1);
-        self.process_key(window.is_key_down(Key::I), Key::I, cia1);
-        self.process_key(window.is_key_down(Key::J), Key::J, cia1);
-        self.process_key(window.is_key_down(Key::K), Key::K, cia1);
-        self.process_key(window.is_key_down(Key::L), Key::L, cia1);
-        self.process_key(window.is_key_down(Key::M), Key::M, cia1);
-        self.process_key(window.is_key_down(Key::N), Key::N, cia1);
-        self.process_key(window.is_key_down(Key::O), Key::O, cia1);
-        self.process_key(window.is_key_down(Key::P), Key::P, cia1);
-        self.process_key(window.is_key_down(Key::Q), Key::Q, cia1);
-        self.process_key(window.is_key_down(Key::R), Key::R, cia1);
-        self.process_key(window.is_key_down(Key::S), Key::S, cia1);
-        self.process_key(window.is_key_down(Key::T), Key::T, cia1);
-        self.process_key(window.is_key_down(Key::U), Key::U, cia1);
-        self.process_key(window.is_key_down(Key::V), Key::V, cia1);
-        self.process_key(window.is_key_down(Key::W), Key::W, cia1);
-        self.process_key(window.is_key_down(Key::X), Key::X, cia1);
-        self.process_key(window.is_key_down(Key::Y), Key::Y, cia1);
-        self.process_key(window.is_key_down(Key::Z), Key::Z, cia1);
-        self.process_key(window.is_key_down(Key::F1), Key::F1, cia1);
-        self.process_key(window.is_key_down(Key::F2), Key::F2, cia1);
-        self.process_key(window.is_key_down(Key::F3), Key::F3, cia1);
-        self.process_key(window.is_key_down(Key::F4), Key::F4, cia1);
-        self.process_key(window.is_key_down(Key::F5), Key::F5, cia1);
-        self.process_key(window.is_key_down(Key::F6), Key::F6, cia1);
-        self.process_key(window.is_key_down(Key::F7), Key::F7, cia1);
-        self.process_key(window.is_key_down(Key::F8), Key::F8, cia1);
-
-        self.process_key(window.is_key_down(Key::Down),  Key::Down,  cia1);
-        self.process_key(window.is_key_down(Key::Up),    Key::Up,    cia1);
-        self.process_key(window.is_key_down(Key::Right), Key::Right, cia1);
-        self.process_key(window.is_key_down(Key::Left),  Key::Left,  cia1);
-        self.process_key(window.is_key_down(Key::Space),  Key::Space,  cia1);
-        self.process_key(window.is_key_down(Key::Comma),  Key::Comma,  cia1);
-        self.process_key(window.is_key_down(Key::Period), Key::Period, cia1);
-        self.process_key(window.is_key_down(Key::Slash),  Key::Slash,  cia1);
-        self.process_key(window.is_key_down(Key::Enter),  Key::Enter,  cia1);
-        self.process_key(window.is_key_down(Key::Backspace),  Key::Backspace,  cia1);
-        self.process_key(window.is_key_down(Key::Backquote),  Key::Backquote,  cia1);
-        self.process_key(window.is_key_down(Key::LeftShift),  Key::LeftShift,  cia1);
-        self.process_key(window.is_key_down(Key::RightShift), Key::RightShift, cia1);
-        self.process_key(window.is_key_down(Key::Escape),     Key::Escape,     cia1);
-        self.process_key(window.is_key_down(Key::Minus),  Key::Minus,  cia1);
-        self.process_key(window.is_key_down(Key::Equal),  Key::Equal,  cia1);
-        self.process_key(window.is_key_down(Key::Insert), Key::Insert, cia1);
-        self.process_key(window.is_key_down(Key::Home),   Key::Home,   cia1);
-        self.process_key(window.is_key_down(Key::LeftBracket),  Key::LeftBracket,  cia1);
-        self.process_key(window.is_key_down(Key::RightBracket), Key::RightBracket, cia1);
-        self.process_key(window.is_key_down(Key::Delete),     Key::Delete,     cia1);
-        self.process_key(window.is_key_down(Key::Semicolon),  Key::Semicolon,  cia1);
-        self.process_key(window.is_key_down(Key::Apostrophe), Key::Apostrophe, cia1);
-        self.process_key(window.is_key_down(Key::Backslash),  Key::Backslash,  cia1);
-        self.process_key(window.is_key_down(Key::Tab),        Key::Tab,        cia1);
-        self.process_key(window.is_key_down(Key::LeftCtrl),   Key::LeftCtrl,   cia1);
-
-        // joystick processing
-        self.process_joystick(window.is_key_down(Key::NumPad1), Key::NumPad1, cia1);
-        self.process_joystick(window.is_key_down(Key::NumPad2), Key::NumPad2, cia1);
-        self.process_joystick(window.is_key_down(Key::NumPad3), Key::NumPad3, cia1);
-        self.process_joystick(window.is_key_down(Key::NumPad4), Key::NumPad4, cia1);
-        self.process_joystick(window.is_key_down(Key::NumPad5), Key::NumPad5, cia1);
-        self.process_joystick(window.is_key_down(Key::NumPad6), Key::NumPad6, cia1);
-        self.process_joystick(window.is_key_down(Key::NumPad7), Key::NumPad7, cia1);
-        self.process_joystick(window.is_key_down(Key::NumPad8), Key::NumPad8, cia1);
-        self.process_joystick(window.is_key_down(Key::NumPad9), Key::NumPad9, cia1);
-        self.process_joystick(window.is_key_down(Key::RightCtrl), Key::RightCtrl, cia1);
-
-        // helper keys
-        // toggle between joystick ports 1 and 2
-        if window.is_key_pressed(Key::NumLock, KeyRepeat::No) {
-            self.joy_port1 = !self.joy_port1;
-
-            if self.joy_port1 {
-                cia1.borrow_mut().joystick_2 = 0xFF;
-            }
-            else {
-                cia1.borrow_mut().joystick_1 = 0xFF;
+    // port: 0 or 1 for control port 1 or 2
+    pub fn set_port_device(&mut self, port: usize, device: control_port::Device) {
+        self.ports[port].set_device(device);
+    }
+
+
+    // key state change sent by the UI thread
+    pub fn on_key(&mut self, keycode: Key, key_pressed: bool, cia1: &mut cia::CIA) {
+        // the num-pad turns paddles in the joystick port instead
+        let joy_port = if self.joy_port1 { 0 } else { 1 };
+        if self.ports[joy_port].on_key(keycode, key_pressed) {
+            return;
+        }
+
+        match keycode {
+            // joystick processing
+            Key::NumPad1 | Key::NumPad2 | Key::NumPad3 |
+            Key::NumPad4 | Key::NumPad5 | Key::NumPad6 |
+            Key::NumPad7 | Key::NumPad8 | Key::NumPad9 | Key::RightCtrl => {
+                self.process_joystick(key_pressed, keycode, cia1);
+            },
+            // helper keys
+            // toggle between joystick ports 1 and 2
+            Key::NumLock => {
+                if !key_pressed {
+                    return;
+                }
+
+                self.joy_port1 = !self.joy_port1;
+
+                if self.joy_port1 {
+                    cia1.joystick_2 = 0xFF;
+                }
+                else {
+                    cia1.joystick_1 = 0xFF;
+                }
+
+                println!("Using joystick in port {}", if self.joy_port1 { "1" } else { "2" });
+            },
+            // keyboard processing - keys without a C64 counterpart are ignored
+            _ => {
+                if self.keycode_to_c64(keycode) != 0xFF {
+                    self.process_key(key_pressed, keycode, cia1);
+                }
             }
-            
-            println!("Using joystick in port {}", if self.joy_port1 { "1" } else { "2" });
         }
     }
 
 
-    pub fn check_restore_key(&self, window: &Window) -> bool {
+    // the host mouse drives the devices in both ports
+    pub fn on_mouse_move(&mut self, x: f32, y: f32) {
+        for port in self.ports.iter_mut() {
+            port.on_mouse_move(x, y);
+        }
+    }
+
+
+    pub fn on_mouse_out(&mut self) {
+        for port in self.ports.iter_mut() {
+            port.on_mouse_out();
+        }
+    }
+
+
+    pub fn on_mouse_button(&mut self, button: MouseButton, down: bool) {
+        for port in self.ports.iter_mut() {
+            port.on_mouse_button(button, down);
+        }
+    }
+
+
+    // called every frame - hands the device state over to CIA1 and the SID
+    pub fn update_ports(&mut self, cia1: &mut cia::CIA, sound: &mut sound::Sound) {
+        for (i, port) in self.ports.iter_mut().enumerate() {
+            port.update();
+
+            let (x, y) = port.pots();
+            sound.set_pots(i, x, y);
+        }
+
+        cia1.device_1 = self.ports[0].lines();
+        cia1.device_2 = self.ports[1].lines();
+    }
+
+
+    pub fn check_restore_key(&self, keycode: Key) -> bool {
         // End will serve as the Restore key
-        window.is_key_pressed(Key::End, KeyRepeat::No)
+        self.has_keyboard && keycode == Key::End
     }
 
 
     // *** private functions *** //
 
-    fn process_key(&mut self, key_pressed: bool, keycode: Key, cia1: &mut cia::CIAShared) {   
+    fn process_key(&mut self, key_pressed: bool, keycode: Key, cia1: &mut cia::CIA) {   
+        if !self.has_keyboard {
+            return;
+        }
+
         if key_pressed {
             self.on_key_press(keycode, cia1);
         }
@@ -158,7 +148,7 @@ impl IO {
     }    
 
 
-    fn on_key_press(&mut self, keycode: Key, cia1: &mut cia::CIAShared) {
+    fn on_key_press(&mut self, keycode: Key, cia1: &mut cia::CIA) {
         let c64_keycode = self.keycode_to_c64(keycode);
 
         if self.keyboard_state[c64_keycode as usize] || c64_keycode == 0xFF
@@ -174,16 +164,16 @@ impl IO {
         // key is shifted?
         if (c64_keycode & 0x80) != 0
         {
-            cia1.borrow_mut().key_matrix[6] &= 0xEF;
-            cia1.borrow_mut().rev_matrix[4] &= 0xBF;
+            cia1.key_matrix[6] &= 0xEF;
+            cia1.rev_matrix[4] &= 0xBF;
         }
         
-        cia1.borrow_mut().key_matrix[c64_byte as usize] &= !(1 << c64_bit);
-        cia1.borrow_mut().rev_matrix[c64_bit as usize]  &= !(1 << c64_byte);
+        cia1.key_matrix[c64_byte as usize] &= !(1 << c64_bit);
+        cia1.rev_matrix[c64_bit as usize]  &= !(1 << c64_byte);
     }
 
 
-    fn on_key_release(&mut self, keycode: Key, cia1: &mut cia::CIAShared) {
+    fn on_key_release(&mut self, keycode: Key, cia1: &mut cia::CIA) {
         let c64_keycode = self.keycode_to_c64(keycode);
 
         if !self.keyboard_state[c64_keycode as usize] || c64_keycode == 0xFF {
@@ -197,16 +187,16 @@ impl IO {
         
         // key is shifted?
         if (c64_keycode & 0x80) != 0 {
-            cia1.borrow_mut().key_matrix[6] |= 0x10;
-            cia1.borrow_mut().rev_matrix[4] |= 0x40;
+            cia1.key_matrix[6] |= 0x10;
+            cia1.rev_matrix[4] |= 0x40;
         }
         
-        cia1.borrow_mut().key_matrix[c64_byte as usize] |= 1 << c64_bit;
-        cia1.borrow_mut().rev_matrix[c64_bit as usize]  |= 1 << c64_byte;
+        cia1.key_matrix[c64_byte as usize] |= 1 << c64_bit;
+        cia1.rev_matrix[c64_bit as usize]  |= 1 << c64_byte;
     }
 
 
-    fn process_joystick(&mut self, key_pressed: bool, keycode: Key, cia1: &mut cia::CIAShared) {
+    fn process_joystick(&mut self, key_pressed: bool, keycode: Key, cia1: &mut cia::CIA) {
         if key_pressed {
             self.on_joy_press(keycode, cia1);
         }
@@ -216,8 +206,8 @@ impl IO {
     }
 
 
-    fn on_joy_press(&mut self, keycode: Key, cia1: &mut cia::CIAShared) {
-        let mut joystate = if self.joy_port1 { cia1.borrow_mut().joystick_1 } else { cia1.borrow_mut().joystick_2 };
+    fn on_joy_press(&mut self, keycode: Key, cia1: &mut cia::CIA) {
+        let mut joystate = if self.joy_port1 { cia1.joystick_1 } else { cia1.joystick_2 };
 
         match keycode {
             // down-left
@@ -244,16 +234,16 @@ impl IO {
         }
 
         if self.joy_port1 {
-            cia1.borrow_mut().joystick_1 = joystate;
+            cia1.joystick_1 = joystate;
         }
         else {
-            cia1.borrow_mut().joystick_2 = joystate;
+            cia1.joystick_2 = joystate;
         }
     }
 
 
-    fn on_joy_release(&mut self, keycode: Key, cia1: &mut cia::CIAShared) {
-        let mut joystate = if self.joy_port1 { cia1.borrow_mut().joystick_1 } else { cia1.borrow_mut().joystick_2 };
+    fn on_joy_release(&mut self, keycode: Key, cia1: &mut cia::CIA) {
+        let mut joystate = if self.joy_port1 { cia1.joystick_1 } else { cia1.joystick_2 };
 
         if joystate == 0xFF {
             return;
@@ -284,12 +274,12 @@ impl IO {
         }
 
         if self.joy_port1 {
-            cia1.borrow_mut().joystick_1 = joystate;
-            cia1.borrow_mut().joystick_2 = 0xFF;
+            cia1.joystick_1 = joystate;
+            cia1.joystick_2 = 0xFF;
         }
         else {
-            cia1.borrow_mut().joystick_1 = 0xFF;
-            cia1.borrow_mut().joystick_2 = joystate;
+            cia1.joystick_1 = 0xFF;
+            cia1.joystick_2 = joystate;
         }
     }
 
@@ -0,0 +1,170 @@
+// emulation core: CPU, system bus and machine clock without any window or input handling
+// the whole machine is owned here and components get to each other through the bus,
+// so stepping a cycle doesn't need any shared references
+use c64::bus;
+use c64::cia;
+use c64::cpu;
+use c64::expansion;
+use c64::memory;
+use c64::model;
+use c64::roms;
+use c64::scheduler;
+use c64::sound;
+use c64::vic;
+
+
+pub struct Machine {
+    pub cpu: cpu::CPU,
+    pub bus: bus::Bus,
+    pub scheduler: scheduler::Scheduler, // machine clock and pending events
+    expansion_nmi: bool, // last state of expansion port NMI line (NMI is edge triggered)
+    tod_period: u32,     // cycles per TOD tick - CIA TOD input runs at mains frequency
+    cia_wake: [Option<scheduler::Cycle>; 2], // pending CIA1/CIA2 timer events
+}
+
+impl Machine {
+    pub fn new(roms: roms::RomSet, model: &model::MachineModel) -> Machine {
+        let vic_model  = model.vic_model;
+        let clock_freq = vic_model.clock_freq();
+        let mains_freq = if vic_model.is_ntsc() { 60 } else { 50 };
+
+        let bus = bus::Bus::new(memory::Memory::new(roms, model.ram_size, model.has_datasette),
+                                vic::VIC::new(vic_model),
+                                cia::CIA::new(true, model.cia_model),
+                                cia::CIA::new(false, model.cia_model),
+                                sound::Sound::new(model.sid_model, clock_freq));
+
+        Machine {
+            cpu: cpu::CPU::new(),
+            bus: bus,
+            scheduler: scheduler::Scheduler::new(),
+            expansion_nmi: false,
+            tod_period: clock_freq / mains_freq,
+            cia_wake: [None, None],
+        }
+    }
+
+
+    // plug a device (RAM expansion, cartridge) into the expansion port
+    pub fn attach_expansion(&mut self, device: Box<dyn expansion::ExpansionPort>) -> Result<(), String> {
+        self.bus.memory.attach_expansion(device)
+    }
+
+
+    pub fn reset(&mut self) {
+        self.bus.memory.reset();
+        self.cpu.reset(&mut self.bus);
+        self.bus.cia1.reset();
+        self.bus.cia2.reset();
+        self.bus.sound.reset();
+        self.bus.sound.select_pots(self.bus.cia1.pot_select());
+
+        // restart periodic events
+        self.scheduler.cancel(scheduler::Event::TodTick);
+        self.scheduler.cancel(scheduler::Event::Cia1Timer);
+        self.scheduler.cancel(scheduler::Event::Cia2Timer);
+        self.cia_wake = [None, None];
+
+        self.scheduler.schedule_in(self.tod_period as scheduler::Cycle, scheduler::Event::TodTick);
+    }
+
+
+    // emulate a single cycle - returns true on VBlank
+    pub fn step(&mut self) -> bool {
+        let mut should_trigger_vblank = false;
+        let cycle = self.scheduler.now();
+
+        {
+            let bus = &mut self.bus;
+            bus.cycle = cycle;
+
+            bus.vic.update(&mut bus.memory, &mut bus.lines, cycle, &mut should_trigger_vblank);
+            bus.sound.clock(cycle);
+
+            bus.cia1.process_irq(&mut bus.lines);
+            bus.cia2.process_irq(&mut bus.lines);
+            bus.cia1.update(&mut bus.lines);
+            bus.cia2.update(&mut bus.lines);
+
+            // expansion port: cartridge/RAM expansion interrupts and DMA
+            let exp_lines = bus.update_expansion();
+            bus.lines.exp_irq = exp_lines.irq;
+            if exp_lines.nmi && !self.expansion_nmi {
+                bus.lines.set_nmi(true);
+            }
+            self.expansion_nmi = exp_lines.nmi;
+
+            // CPU is halted while the expansion port device owns the bus
+            if !exp_lines.dma {
+                self.cpu.update(bus, cycle);
+            }
+        }
+
+        while let Some(event) = self.scheduler.pop_due() {
+            self.process_event(event);
+        }
+
+        self.sleep_cia(0, cycle);
+        self.sleep_cia(1, cycle);
+
+        self.scheduler.advance();
+        should_trigger_vblank
+    }
+
+
+    // *** private functions *** //
+
+    fn process_event(&mut self, event: scheduler::Event) {
+        match event {
+            scheduler::Event::TodTick => {
+                self.bus.cia1.count_tod(&mut self.bus.lines);
+                self.bus.cia2.count_tod(&mut self.bus.lines);
+                self.scheduler.schedule_in(self.tod_period as scheduler::Cycle, scheduler::Event::TodTick);
+            },
+            scheduler::Event::Cia1Timer => {
+                self.cia_wake[0] = None;
+                self.bus.cia1.wake(self.scheduler.now());
+            },
+            scheduler::Event::Cia2Timer => {
+                self.cia_wake[1] = None;
+                self.bus.cia2.wake(self.scheduler.now());
+            }
+        }
+    }
+
+
+    // a CIA whose timers are stopped or just counting down isn't clocked until the cycle
+    // before an underflow, or until the CPU accesses it
+    fn sleep_cia(&mut self, index: usize, cycle: scheduler::Cycle) {
+        let (cia, event) = if index == 0 { (&mut self.bus.cia1, scheduler::Event::Cia1Timer) }
+                           else          { (&mut self.bus.cia2, scheduler::Event::Cia2Timer) };
+
+        if cia.is_asleep() {
+            return;
+        }
+
+        let idle = match cia.idle_cycles() {
+            Some(idle) if idle > 0 => idle,
+            _ => return
+        };
+
+        cia.sleep(cycle + 1);
+
+        // an earlier pending event only wakes the CIA early - it goes back to sleep right away
+        if idle == scheduler::Cycle::max_value() {
+            return;
+        }
+
+        let wake_at = cycle + idle;
+        match self.cia_wake[index] {
+            Some(pending) if pending <= wake_at => (),
+            pending => {
+                if pending.is_some() {
+                    self.scheduler.cancel(event);
+                }
+                self.scheduler.schedule(wake_at, event);
+                self.cia_wake[index] = Some(wake_at);
+            }
+        }
+    }
+}
@@ -1,9 +1,6 @@
 // memory banks
-use std::cell::RefCell;
-use std::rc::Rc;
-use utils;
-
-pub type MemShared = Rc<RefCell<Memory>>;
+use c64::expansion::ExpansionPort;
+use c64::roms::RomSet;
 
 pub enum MemType {
     Ram,
@@ -32,18 +29,9 @@ impl MemBank {
         };
 
         match mem_bank.bank_type {
-            MemType::Basic   => {
-                mem_bank.data = utils::open_file("rom/basic.rom", 0);
-                mem_bank.offset = 0xA000;
-            },
-            MemType::Chargen => {
-                mem_bank.data = utils::open_file("rom/chargen.rom", 0);
-                mem_bank.offset = 0xD000;
-            },
-            MemType::Kernal  => {
-                mem_bank.data = utils::open_file("rom/kernal.rom", 0);
-                mem_bank.offset = 0xE000;
-            },
+            MemType::Basic   => mem_bank.offset = 0xA000,
+            MemType::Chargen => mem_bank.offset = 0xD000,
+            MemType::Kernal  => mem_bank.offset = 0xE000,
             MemType::Ram => {
                 mem_bank.data = Vec::<u8>::with_capacity(0x10000);
                 for _ in 0..0x10000 {
@@ -67,6 +55,14 @@ impl MemBank {
     }
 
 
+    // ROM bank with contents supplied by the ROM set
+    pub fn new_rom(mem_type: MemType, data: Vec<u8>) -> MemBank {
+        let mut mem_bank = MemBank::new(mem_type);
+        mem_bank.data = data;
+        mem_bank
+    }
+
+
     pub fn write(&mut self, addr: u16, val: u8) {
         match self.bank_type {
             MemType::Ram => self.data[(addr - self.offset) as usize] = val,
@@ -121,6 +117,9 @@ pub struct Memory {
     io:      MemBank,
     kernal:  MemBank,
 
+    // device plugged into the expansion port
+    expansion: Option<Box<dyn ExpansionPort>>,
+
     // bank switching flags
     pub exrom:      bool,
     pub game:       bool,
@@ -128,23 +127,38 @@ pub struct Memory {
     pub chargen_on: bool,
     pub io_on:      bool,
     pub kernal_on:  bool,
+    pub roml_on:    bool, // cartridge ROML visible at $8000-$9FFF
+    pub romh_on:    bool, // cartridge ROMH visible at $A000-$BFFF ($E000-$FFFF in Ultimax mode)
+    pub ultimax:    bool,
+
+    // RAM address lines: 0xFFFF for 64K, 0x07FF on the MAX Machine (2K mirrored at $0000-$0FFF)
+    pub ram_mask: u16,
+
+    // false on models without a cassette port (SX-64, C64GS, MAX Machine)
+    has_datasette: bool,
 }
 
 impl Memory {
-    pub fn new_shared() -> MemShared {
-        Rc::new(RefCell::new(Memory {
-            ram:     MemBank::new(MemType::Ram),     // 64k
-            basic:   MemBank::new(MemType::Basic),   // 8k
-            chargen: MemBank::new(MemType::Chargen), // 4k
-            io:      MemBank::new(MemType::Io),      // 4k (VIC, SID, CIA, Color RAM)
-            kernal:  MemBank::new(MemType::Kernal),  // 8k
+    pub fn new(roms: RomSet, ram_size: usize, has_datasette: bool) -> Memory {
+        Memory {
+            ram:     MemBank::new(MemType::Ram),                       // 64k
+            basic:   MemBank::new_rom(MemType::Basic, roms.basic),     // 8k
+            chargen: MemBank::new_rom(MemType::Chargen, roms.chargen), // 4k
+            io:      MemBank::new(MemType::Io),                        // 4k (VIC, SID, CIA, Color RAM)
+            kernal:  MemBank::new_rom(MemType::Kernal, roms.kernal),   // 8k
+            expansion: None,
             exrom:      true,
             game:       true,
             basic_on:   false,
             chargen_on: false,
             io_on:      false,
             kernal_on:  false,
-        }))
+            roml_on:    false,
+            romh_on:    false,
+            ultimax:    false,
+            ram_mask:   (ram_size - 1) as u16,
+            has_datasette: has_datasette,
+        }
     }
     
 
@@ -188,11 +202,116 @@ impl Memory {
     pub fn reset(&mut self) {
         self.write_byte(0x0000, 0xFF);
         self.write_byte(0x0001, 0x07); // enable kernal, chargen and basic ROMs
+
+        if let Some(ref mut device) = self.expansion {
+            device.reset();
+        }
+
+        self.sync_expansion_lines();
+    }
+
+
+    // the port has a single slot - a second device is refused
+    pub fn attach_expansion(&mut self, device: Box<dyn ExpansionPort>) -> Result<(), String> {
+        if let Some(ref old_device) = self.expansion {
+            return Err(format!("can't attach {}: {} is already in the expansion port", device.name(), old_device.name()));
+        }
+
+        println!("Attached {} to expansion port", device.name());
+        self.expansion = Some(device);
+        self.sync_expansion_lines();
+        Ok(())
+    }
+
+
+    // the device is taken out of the port while it's clocked, so that its DMA can go through the bus
+    pub fn take_expansion(&mut self) -> Option<Box<dyn ExpansionPort>> {
+        self.expansion.take()
+    }
+
+
+    pub fn return_expansion(&mut self, device: Box<dyn ExpansionPort>) {
+        self.expansion = Some(device);
+        self.sync_expansion_lines();
+    }
+
+
+    // persist expansion device state on shutdown
+    pub fn flush_expansion(&mut self) {
+        if let Some(ref mut device) = self.expansion {
+            device.flush();
+        }
+    }
+
+
+    // I/O1 and I/O2 read - None if no expansion device drives the bus at this address
+    pub fn read_io(&mut self, addr: u16) -> Option<u8> {
+        match self.expansion {
+            Some(ref mut device) => device.read_io(addr),
+            None => None
+        }
+    }
+
+
+    // I/O1 and I/O2 write - returns true if an expansion device claimed it
+    pub fn write_io(&mut self, addr: u16, value: u8) -> bool {
+        let claimed = match self.expansion {
+            Some(ref mut device) => device.write_io(addr, value),
+            None => false
+        };
+
+        // bank switching cartridges change GAME/EXROM through I/O writes
+        self.sync_expansion_lines();
+        claimed
+    }
+
+
+    // let the expansion device see a CPU write
+    pub fn snoop_write(&mut self, addr: u16, value: u8) {
+        if let Some(ref mut device) = self.expansion {
+            device.snoop_write(addr, value);
+        }
+    }
+
+
+    // ROMH as seen by the VIC in Ultimax mode - falls back to RAM if the cartridge doesn't drive the bus
+    pub fn read_romh(&mut self, addr: u16) -> u8 {
+        let byte = match self.expansion {
+            Some(ref mut device) => device.read_romh(addr),
+            None => None
+        };
+
+        match byte {
+            Some(value) => value,
+            None => self.ram.read(addr)
+        }
     }
 
     
     // Write a byte to memory - returns whether RAM was written (true) or RAM under ROM (false)
     pub fn write_byte(&mut self, addr: u16, value: u8) -> bool {
+        // cartridge ROM visible at this address? Let the cartridge know (RAM carts, flash)
+        if self.is_cart_rom(addr) {
+            if let Some(ref mut device) = self.expansion {
+                device.write_rom(addr, value);
+            }
+
+            // in Ultimax mode there's no RAM under cartridge ROM
+            if self.ultimax {
+                return false;
+            }
+        }
+
+        if self.is_unmapped(addr) {
+            return false;
+        }
+
+        // MAX Machine RAM is mirrored
+        if (addr & 0xF000) == 0 && addr > self.ram_mask {
+            self.ram.write(addr & self.ram_mask, value);
+            return true;
+        }
+
         // RAM under ROM written? Return false to let us know about it
         if self.get_bank(addr).read_only {
             self.ram.write(addr, value);
@@ -218,7 +337,35 @@ impl Memory {
         if addr == 0x0001 {
             let ddr = self.ram.read(0x0000);
             let pr  = self.ram.read(0x0001);
-            return (ddr & pr) | (!ddr & 0x17);
+            let value = (ddr & pr) | (!ddr & 0x17);
+
+            // no cassette port: sense always reads "no button pressed" and motor writes go nowhere
+            if !self.has_datasette {
+                return (value & !0x20) | 0x10;
+            }
+
+            return value;
+        }
+
+        if self.is_cart_rom(addr) {
+            let is_roml = addr < 0xA000;
+            let byte = match self.expansion {
+                Some(ref mut device) => if is_roml { device.read_roml(addr) } else { device.read_romh(addr) },
+                None => None
+            };
+
+            if let Some(value) = byte {
+                return value;
+            }
+        }
+
+        // nothing drives the bus - approximate the floating value
+        if self.is_unmapped(addr) {
+            return 0xFF;
+        }
+
+        if (addr & 0xF000) == 0 {
+            return self.ram.read(addr & self.ram_mask);
         }
         
         self.get_bank(addr).read(addr)
@@ -227,17 +374,58 @@ impl Memory {
 
     // Read a word from memory (stored in little endian)
     pub fn read_word_le(&mut self, addr: u16) -> u16 {
-        let bank = self.get_bank(addr);
-        let value_be: u16 = ((bank.read(addr) as u16) << 8 & 0xFF00) |
-                            ((bank.read(addr + 0x0001) as u16) & 0x00FF);
-
-        let value_le: u16 = ((value_be << 8) & 0xFF00) | ((value_be >> 8) & 0x00FF);
-        value_le
+        // go through read_byte so that vectors in cartridge ROM are visible
+        let lo = self.read_byte(addr) as u16;
+        let hi = self.read_byte(addr.wrapping_add(0x0001)) as u16;
+        (hi << 8) | lo
     }
 
 
     // *** private functions *** //
 
+    // is cartridge ROML/ROMH mapped at this address?
+    fn is_cart_rom(&self, addr: u16) -> bool {
+        match addr {
+            0x8000..=0x9FFF => self.roml_on,
+            0xA000..=0xBFFF => self.romh_on && !self.ultimax,
+            0xE000..=0xFFFF => self.romh_on && self.ultimax,
+            _               => false
+        }
+    }
+
+
+    // only the MAX Machine has holes in its address space: outside of 2K RAM and I/O
+    // there's nothing but what the cartridge drives
+    fn is_unmapped(&self, addr: u16) -> bool {
+        if self.ram_mask == 0xFFFF {
+            return false;
+        }
+
+        match addr {
+            0x0000..=0x0FFF | 0xD000..=0xDFFF => false,
+            _ => true
+        }
+    }
+
+
+    // pick up GAME/EXROM changes from the expansion port device
+    fn sync_expansion_lines(&mut self) {
+        let (exrom, game) = match self.expansion {
+            Some(ref device) => (device.exrom(), device.game()),
+            None => (true, true)
+        };
+
+        // the MAX Machine has no ROMs of its own and always runs in Ultimax mode
+        let (exrom, game) = if self.ram_mask != 0xFFFF { (true, false) } else { (exrom, game) };
+
+        if exrom != self.exrom || game != self.game {
+            self.exrom = exrom;
+            self.game  = game;
+            self.update_memory_latch();
+        }
+    }
+
+
     // update status of memory bank latches
     fn update_memory_latch(&mut self) {
         let ddr = self.ram.read(0x0000);
@@ -248,14 +436,40 @@ impl Memory {
         self.io_on      = ((latch & 0x04) != 0) && ((latch & 0x03) != 0); // %1xx except %100
         self.basic_on   = (latch & 0x03) == 3;
         self.kernal_on  = (latch & 0x02) != 0; 
+
+        let loram = (latch & 0x01) != 0;
+        let hiram = (latch & 0x02) != 0;
         
         // binary logic is hard
-        if self.exrom && !self.game {
-            self.basic_on = false;
-            self.kernal_on = false;
-        }
-        if !self.exrom && !self.game {
-            self.basic_on = false;
+        match (self.exrom, self.game) {
+            // Ultimax: ROML at $8000, ROMH at $E000, I/O always on, no C64 ROMs
+            (true, false) => {
+                self.roml_on    = true;
+                self.romh_on    = true;
+                self.ultimax    = true;
+                self.basic_on   = false;
+                self.kernal_on  = false;
+                self.chargen_on = false;
+                self.io_on      = true;
+            },
+            // 8k cartridge: ROML at $8000
+            (false, true) => {
+                self.roml_on = loram && hiram;
+                self.romh_on = false;
+                self.ultimax = false;
+            },
+            // 16k cartridge: ROML at $8000, ROMH at $A000
+            (false, false) => {
+                self.roml_on  = loram && hiram;
+                self.romh_on  = hiram;
+                self.ultimax  = false;
+                self.basic_on = false;
+            },
+            (true, true) => {
+                self.roml_on = false;
+                self.romh_on = false;
+                self.ultimax = false;
+            }
         }
     }
 }
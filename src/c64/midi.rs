@@ -0,0 +1,415 @@
+// SID to MIDI conversion for transcribing tunes: follows the register writes of every voice and
+// writes a Standard MIDI File with one track per voice when done
+// gate edges become note on/off, the frequency register the pitch (small slides and vibrato as pitch bend),
+// the waveform the instrument and sustain level and volume the velocity
+use c64::scheduler::Cycle;
+use std::fs::File;
+use std::io::Write;
+
+const TICKS_PER_QUARTER: u32 = 480;
+const TEMPO: u32 = 500000;      // microseconds per quarter note (120 bpm) - 960 ticks per second
+const BEND_RANGE: f64 = 2.0;    // pitch bend range in semitones
+const NO_NOTE: u8 = 0xFF;
+
+// General MIDI programs for the waveforms
+const PROGRAM_TRIANGLE: u8 = 79;  // ocarina
+const PROGRAM_SAW:      u8 = 81;  // sawtooth lead
+const PROGRAM_PULSE:    u8 = 80;  // square lead
+const PROGRAM_NOISE:    u8 = 118; // synth drum
+
+
+struct Voice {
+    freq: u16,
+    control: u8,
+    sustain: u8,
+    note: u8,      // playing note, NO_NOTE if none
+    bend: u16,     // current pitch bend (0x2000 = center)
+    program: u8,
+    pitch_tick: Option<u32>, // time of a frequency change not converted yet
+    events: Vec<u8>,
+    last_tick: u32,
+}
+
+impl Voice {
+    fn new() -> Voice {
+        Voice {
+            freq: 0,
+            control: 0,
+            sustain: 0,
+            note: NO_NOTE,
+            bend: 0x2000,
+            program: 0xFF,
+            pitch_tick: None,
+            events: Vec::new(),
+            last_tick: 0,
+        }
+    }
+
+
+    fn event(&mut self, tick: u32, data: &[u8]) {
+        write_vlq(&mut self.events, tick - self.last_tick);
+        self.events.extend_from_slice(data);
+        self.last_tick = tick;
+    }
+}
+
+
+pub struct MidiExport {
+    filename: String,
+    file: File,
+    clock_freq: u32,
+    voices: Vec<Voice>, // 3 per SID
+    volumes: Vec<u8>,   // per SID
+    start_cycle: Cycle, // time zero of the file
+    last_cycle: Cycle,
+}
+
+impl MidiExport {
+    // the file is written when the export is dropped - time zero is start_cycle
+    pub fn create(filename: &str, clock_freq: u32, num_sids: usize, start_cycle: Cycle) -> Result<MidiExport, String> {
+        let file = File::create(filename).map_err(|e| format!("Couldn't create {}: {}", filename, e))?;
+
+        let mut export = MidiExport {
+            filename: String::from(filename),
+            file: file,
+            clock_freq: clock_freq,
+            voices: Vec::new(),
+            volumes: vec![0x0F; num_sids],
+            start_cycle: start_cycle,
+            last_cycle: start_cycle,
+        };
+
+        export.reset_voices(num_sids * 3);
+        Ok(export)
+    }
+
+
+    // move time zero to the given cycle (the tune starts there) and drop anything recorded before
+    pub fn restart(&mut self, cycle: Cycle) {
+        let num_voices = self.voices.len();
+        self.reset_voices(num_voices);
+        self.start_cycle = cycle;
+        self.last_cycle = cycle;
+    }
+
+
+    // register write to a SID - reg is 0x00-0x1F
+    pub fn write_register(&mut self, cycle: Cycle, sid: usize, reg: u8, value: u8) {
+        self.last_cycle = cycle;
+
+        if reg == 0x18 {
+            if sid < self.volumes.len() {
+                self.volumes[sid] = value & 0x0F;
+            }
+            return;
+        }
+
+        if reg > 0x14 {
+            return;
+        }
+
+        let index = sid * 3 + (reg / 7) as usize;
+        if index >= self.voices.len() {
+            return;
+        }
+
+        // the two frequency bytes are written one after the other, so pitch changes are
+        // converted once per frame - the half written value in between would be garbage
+        let tick = self.tick(cycle);
+        match reg % 7 {
+            0 => {
+                self.voices[index].freq = (self.voices[index].freq & 0xFF00) | value as u16;
+                self.voices[index].pitch_tick = Some(tick);
+            },
+            1 => {
+                self.voices[index].freq = (self.voices[index].freq & 0x00FF) | ((value as u16) << 8);
+                self.voices[index].pitch_tick = Some(tick);
+            },
+            4 => {
+                let gate_was = (self.voices[index].control & 1) != 0;
+                self.voices[index].control = value;
+                let gate = (value & 1) != 0;
+
+                if gate && !gate_was {
+                    self.voices[index].pitch_tick = None;
+                    self.note_on(index, tick, sid);
+                }
+                else if !gate && gate_was {
+                    self.update_pitch(index);
+                    self.note_off(index, tick);
+                }
+            },
+            6 => self.voices[index].sustain = value >> 4,
+            _ => (),
+        }
+    }
+
+
+    // called every frame: converts frequency changes and keeps track of the end of the file
+    pub fn advance(&mut self, cycle: Cycle) {
+        self.last_cycle = cycle;
+
+        for index in 0..self.voices.len() {
+            self.update_pitch(index);
+        }
+    }
+
+
+    // *** private functions *** //
+
+    fn reset_voices(&mut self, num_voices: usize) {
+        self.voices = (0..num_voices).map(|_| Voice::new()).collect();
+
+        // pitch bend range: RPN 0
+        for (channel, voice) in self.voices.iter_mut().enumerate() {
+            let ch = channel as u8;
+            voice.event(0, &[0xB0 | ch, 101, 0]);
+            voice.event(0, &[0xB0 | ch, 100, 0]);
+            voice.event(0, &[0xB0 | ch, 6, BEND_RANGE as u8]);
+            voice.event(0, &[0xB0 | ch, 38, 0]);
+        }
+    }
+
+
+    fn tick(&self, cycle: Cycle) -> u32 {
+        let ticks_per_second = TICKS_PER_QUARTER as f64 * 1e6 / TEMPO as f64;
+        (cycle.saturating_sub(self.start_cycle) as f64 * ticks_per_second / self.clock_freq as f64) as u32
+    }
+
+
+    // fractional MIDI note of the voice's frequency register - None if it's too low or high to play
+    fn pitch(&self, freq: u16) -> Option<f64> {
+        let hz = freq as f64 * self.clock_freq as f64 / (1u32 << 24) as f64;
+        if hz < 8.0 {
+            return None;
+        }
+
+        let note = 69.0 + 12.0 * (hz / 440.0).log2();
+        if note >= 0.0 && note <= 127.0 { Some(note) } else { None }
+    }
+
+
+    fn note_on(&mut self, index: usize, tick: u32, sid: usize) {
+        let waveform = self.voices[index].control >> 4;
+        let pitch = match self.pitch(self.voices[index].freq) {
+            Some(pitch) if waveform != 0 => pitch,
+            _ => return,
+        };
+
+        let program = if (waveform & 8) != 0 { PROGRAM_NOISE }
+                      else if (waveform & 4) != 0 { PROGRAM_PULSE }
+                      else if (waveform & 2) != 0 { PROGRAM_SAW }
+                      else { PROGRAM_TRIANGLE };
+
+        // louder with more sustain and volume, but never silent
+        let level = self.volumes[sid] as f64 / 15.0 * (0.5 + self.voices[index].sustain as f64 / 30.0);
+        let velocity = (1.0 + 126.0 * level).round() as u8;
+
+        let ch = index as u8;
+        let voice = &mut self.voices[index];
+
+        if voice.program != program {
+            voice.program = program;
+            voice.event(tick, &[0xC0 | ch, program]);
+        }
+
+        let note = pitch.round() as u8;
+        let bend = bend_value(pitch - note as f64);
+        if bend != voice.bend {
+            voice.bend = bend;
+            voice.event(tick, &[0xE0 | ch, (bend & 0x7F) as u8, (bend >> 7) as u8]);
+        }
+
+        voice.note = note;
+        voice.event(tick, &[0x90 | ch, note, velocity]);
+    }
+
+
+    fn note_off(&mut self, index: usize, tick: u32) {
+        let ch = index as u8;
+        let voice = &mut self.voices[index];
+
+        if voice.note != NO_NOTE {
+            voice.event(tick, &[0x80 | ch, voice.note, 0]);
+            voice.note = NO_NOTE;
+        }
+    }
+
+
+    // frequency change while a note plays: bend within range, otherwise move to the new note
+    fn update_pitch(&mut self, index: usize) {
+        let tick = match self.voices[index].pitch_tick.take() {
+            Some(tick) => tick,
+            None => return,
+        };
+
+        let note = self.voices[index].note;
+        if note == NO_NOTE {
+            return;
+        }
+
+        let pitch = match self.pitch(self.voices[index].freq) {
+            Some(pitch) => pitch,
+            None => return,
+        };
+
+        let offset = pitch - note as f64;
+        if offset.abs() > BEND_RANGE {
+            // too far for the bend range: retrigger at the new pitch
+            let sid = index / 3;
+            self.note_off(index, tick);
+            self.note_on(index, tick, sid);
+            return;
+        }
+
+        let ch = index as u8;
+        let voice = &mut self.voices[index];
+        let bend = bend_value(offset);
+
+        if bend != voice.bend {
+            voice.bend = bend;
+            voice.event(tick, &[0xE0 | ch, (bend & 0x7F) as u8, (bend >> 7) as u8]);
+        }
+    }
+
+
+    fn write_file(&mut self) -> Result<(), String> {
+        let end = self.tick(self.last_cycle);
+        for index in 0..self.voices.len() {
+            self.update_pitch(index);
+            self.note_off(index, end);
+        }
+
+        let mut data = Vec::new();
+        data.extend_from_slice(b"MThd");
+        data.extend_from_slice(&[0, 0, 0, 6, 0, 1]);                           // header length, format 1
+        push_u16(&mut data, self.voices.len() as u16 + 1);                    // tempo track + one per voice
+        push_u16(&mut data, TICKS_PER_QUARTER as u16);
+
+        let mut tempo = Vec::new();
+        tempo.extend_from_slice(&[0x00, 0xFF, 0x51, 0x03, (TEMPO >> 16) as u8, (TEMPO >> 8) as u8, TEMPO as u8]);
+        push_track(&mut data, &tempo, end);
+
+        for (index, voice) in self.voices.iter().enumerate() {
+            let name = format!("SID {} voice {}", index / 3 + 1, index % 3 + 1);
+            let mut track = vec![0x00, 0xFF, 0x03, name.len() as u8];
+            track.extend_from_slice(name.as_bytes());
+            track.extend_from_slice(&voice.events);
+            push_track(&mut data, &track, end - voice.last_tick);
+        }
+
+        self.file.write_all(&data).map_err(|e| e.to_string())
+    }
+}
+
+
+impl Drop for MidiExport {
+    fn drop(&mut self) {
+        match self.write_file() {
+            Ok(_)  => println!("Wrote {}: {} tracks, {:.1} seconds", self.filename, self.voices.len(),
+                              (self.last_cycle - self.start_cycle) as f64 / self.clock_freq as f64),
+            Err(e) => println!("Error writing {}: {}", self.filename, e),
+        }
+    }
+}
+
+
+// 14-bit pitch bend for an offset in semitones
+fn bend_value(offset: f64) -> u16 {
+    (8192.0 + offset / BEND_RANGE * 8192.0).round().max(0.0).min(16383.0) as u16
+}
+
+
+// track chunk: events followed by end of track after delta ticks
+fn push_track(data: &mut Vec<u8>, events: &[u8], delta: u32) {
+    let mut track = events.to_vec();
+    write_vlq(&mut track, delta);
+    track.extend_from_slice(&[0xFF, 0x2F, 0x00]);
+
+    data.extend_from_slice(b"MTrk");
+    let len = track.len() as u32;
+    data.extend_from_slice(&[(len >> 24) as u8, (len >> 16) as u8, (len >> 8) as u8, len as u8]);
+    data.extend_from_slice(&track);
+}
+
+
+fn push_u16(data: &mut Vec<u8>, value: u16) {
+    data.push((value >> 8) as u8);
+    data.push(value as u8);
+}
+
+
+// variable length quantity: 7 bits per byte, most significant first
+fn write_vlq(data: &mut Vec<u8>, value: u32) {
+    let mut bytes = [0u8; 5];
+    let mut n = 0;
+    let mut v = value;
+
+    loop {
+        bytes[n] = (v & 0x7F) as u8;
+        n += 1;
+        v >>= 7;
+        if v == 0 {
+            break;
+        }
+    }
+
+    for i in (0..n).rev() {
+        data.push(if i > 0 { bytes[i] | 0x80 } else { bytes[i] });
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs;
+
+    fn vlq(value: u32) -> Vec<u8> {
+        let mut data = Vec::new();
+        write_vlq(&mut data, value);
+        data
+    }
+
+    #[test]
+    fn vlq_boundaries() {
+        assert_eq!(vlq(0), vec![0x00]);
+        assert_eq!(vlq(0x7F), vec![0x7F]);
+        assert_eq!(vlq(0x80), vec![0x81, 0x00]);
+        assert_eq!(vlq(0x3FFF), vec![0xFF, 0x7F]);
+        assert_eq!(vlq(0x4000), vec![0x81, 0x80, 0x00]);
+    }
+
+    #[test]
+    fn track_length_covers_delta_and_end_of_track() {
+        let mut data = Vec::new();
+        push_track(&mut data, &[0x00, 0x90, 60, 100], 0x80);
+
+        assert_eq!(&data[..4], b"MTrk");
+        assert_eq!(&data[4..8], &[0, 0, 0, 9]);
+        assert_eq!(&data[8..], &[0x00, 0x90, 60, 100, 0x81, 0x00, 0xFF, 0x2F, 0x00]);
+    }
+
+    #[test]
+    fn notes_follow_gate_edges() {
+        let filename = env::temp_dir().join("rust64_midi_gate_test.mid");
+        {
+            // 1 MHz clock: 960 ticks per million cycles
+            let mut export = MidiExport::create(filename.to_str().unwrap(), 1000000, 1, 0).unwrap();
+            export.write_register(0, 0, 0x00, 0xD6); // 440 Hz: $1CD6
+            export.write_register(0, 0, 0x01, 0x1C);
+            let setup = export.voices[0].events.len();
+
+            export.write_register(1000000, 0, 0x04, 0x11); // triangle, gate on
+            export.write_register(1500000, 0, 0x04, 0x11); // gate still on - no new note
+            assert_eq!(&export.voices[0].events[setup..], &[0x87, 0x40, 0xC0, PROGRAM_TRIANGLE, 0x00, 0x90, 69, 64]);
+
+            export.write_register(2000000, 0, 0x04, 0x10); // gate off
+            export.write_register(2500000, 0, 0x04, 0x10);
+            assert_eq!(&export.voices[0].events[setup + 8..], &[0x87, 0x40, 0x80, 69, 0]);
+        }
+
+        fs::remove_file(&filename).unwrap();
+    }
+}
@@ -1,126 +1,318 @@
 // main module for C64 updates
 extern crate minifb;
 
+pub mod bus;
+pub mod cia;
+pub mod control_port;
 pub mod cpu;
+pub mod machine;
 pub mod memory;
+pub mod midi;
 pub mod opcodes;
+pub mod sid;
+pub mod sid_log;
+pub mod sound;
 pub mod vic;
 pub mod crt;
+pub mod expansion;
+pub mod frame;
+pub mod georam;
+pub mod reu;
+pub mod roms;
+pub mod model;
+pub mod psid;
+pub mod scheduler;
 
-mod cia;
 mod clock;
 mod io;
-mod sid;
 mod sid_tables;
 mod vic_tables;
 
+use audio;
 use debugger;
 use minifb::*;
+use std::path::Path;
+use std::sync::mpsc::{Receiver, Sender, TryRecvError};
+use std::thread;
+use std::time::Duration;
 use utils;
 
+extern crate time;
+
 
 pub const SCREEN_WIDTH:  usize = 384; // extend 20 pixels left and right for the borders
 pub const SCREEN_HEIGHT: usize = 272; // extend 36 pixels top and down for the borders
 
-// PAL clock frequency in Hz
-const CLOCK_FREQ: f64 = 1.5 * 985248.0;
+// emulation speed range and the steps taken with NumPad +/-
+pub const MIN_SPEED: u32 = 10;
+pub const MAX_SPEED: u32 = 1000;
+const SPEED_STEPS: [u32; 12] = [10, 25, 50, 75, 100, 125, 150, 200, 300, 400, 500, 1000];
+
+
+// messages from the UI thread to the emulation core
+pub enum UiEvent {
+    KeyDown(Key),
+    KeyUp(Key),
+    MouseMove(f32, f32), // position in frame pixels
+    MouseOut,            // mouse left the frame
+    MouseButton(MouseButton, bool),
+    DebuggerKey(Key),    // key pressed in the debug window
+    Quit,
+}
 
 
+// emulation core - runs on its own thread, produces frames and audio and receives input from the UI
 pub struct C64 {
-    pub main_window: minifb::Window,
     pub file_to_load: String,
-    pub crt_to_load: String,
-    memory: memory::MemShared,
-    io:     io::IO,
-    clock:  clock::Clock,
-    cpu:  cpu::CPUShared,
-    cia1: cia::CIAShared,
-    cia2: cia::CIAShared,
-    vic:  vic::VICShared,
-    sid:  sid::SIDShared,
+    machine: machine::Machine,
+    io:      io::IO,
+    clock:   clock::Clock,
+    frames:  frame::FrameWriter, // finished frames for the UI
+    events:  Receiver<UiEvent>,
+    titles:  Sender<String>,     // window title updates for the UI
 
     debugger: Option<debugger::Debugger>,
-    powered_on: bool,
     boot_complete: bool,
-    cycle_count: u32,
+
+    // SID player mode
+    tune: Option<psid::Psid>,
+    song: u16,           // current song (1-based)
+    tune_pending: bool,  // start the tune once the kernal has booted
+
+    // audio recordings started with the hotkey use the format given on the command line
+    record_channels: u16,
+    record_rate: u32,
+    clock_freq: u32,
+    duration: Option<scheduler::Cycle>,   // emulated time to run for (--duration)
+    stop_cycle: Option<scheduler::Cycle>, // quit at this cycle - counted from when the tune starts
+    tune_started: bool,                   // a tune was installed since power-on
+
+    // speed control
+    paused: bool,
+    frame_advance: bool, // run until the next frame while paused
+    load_error: Option<String>,
+
+    // speed and FPS measurement shown in the window title
+    stats_time:   f64,
+    stats_cycles: scheduler::Cycle,
+    stats_frames: u32,
 }
 
 impl C64 {
-    pub fn new(window_scale: Scale, debugger_on: bool, prg_to_load: &str, crt_to_load: &str) -> C64 {
-        let memory = memory::Memory::new_shared();
-        let vic    = vic::VIC::new_shared();
-        let cia1   = cia::CIA::new_shared(true);
-        let cia2   = cia::CIA::new_shared(false);
-        let cpu    = cpu::CPU::new_shared();
-        let sid    = sid::SID::new_shared();
+    pub fn new(roms: roms::RomSet, model: &model::MachineModel, debugger: Option<debugger::Debugger>, prg_to_load: &str, crt_to_load: &str,
+               audio_backend: &audio::Backend, frames: frame::FrameWriter, events: Receiver<UiEvent>, titles: Sender<String>) -> C64 {
+        let clock_freq = model.vic_model.clock_freq();
 
         let mut c64 = C64 {
-            main_window: Window::new("Rust64", SCREEN_WIDTH, SCREEN_HEIGHT, WindowOptions { scale: window_scale, ..Default::default() }).unwrap(),
             file_to_load: String::from(prg_to_load),
-            crt_to_load: String::from(crt_to_load),
-            memory: memory.clone(), // shared system memory (RAM, ROM, IO registers)
-            io:     io::IO::new(),
-            clock:  clock::Clock::new(CLOCK_FREQ),
-            cpu:  cpu.clone(),
-            cia1: cia1.clone(),
-            cia2: cia2.clone(),
-            vic:  vic.clone(),
-            sid:  sid.clone(),
-            debugger: if debugger_on { Some(debugger::Debugger::new()) } else { None },
-            powered_on: false,
+            machine: machine::Machine::new(roms, model),
+            io:      io::IO::new(model.has_keyboard),
+            clock:   clock::Clock::new(clock_freq as f64),
+            frames:  frames,
+            events:  events,
+            titles:  titles,
+            debugger: debugger,
             boot_complete: false,
-            cycle_count: 0,
+            tune: None,
+            song: 0,
+            tune_pending: false,
+            record_channels: audio::CHANNELS as u16,
+            record_rate: audio::SAMPLE_RATE,
+            clock_freq: clock_freq,
+            duration: None,
+            stop_cycle: None,
+            tune_started: false,
+            paused: false,
+            frame_advance: false,
+            load_error: None,
+            stats_time: time::precise_time_s(),
+            stats_cycles: 0,
+            stats_frames: 0,
         };
 
-        c64.main_window.set_position(75, 20);
+        // missing sound card isn't fatal - the machine just runs silent
+        if let Err(e) = c64.machine.bus.sound.open_audio(audio_backend) {
+            println!("Couldn't open audio output: {} - sound is disabled", e);
+        }
 
-        // cyclic dependencies are not possible in Rust (yet?), so we have
-        // to resort to setting references manually
-        c64.cia1.borrow_mut().set_references(memory.clone(), cpu.clone(), vic.clone());
-        c64.cia2.borrow_mut().set_references(memory.clone(), cpu.clone(), vic.clone());
-        c64.vic.borrow_mut().set_references(memory.clone(), cpu.clone());
-        c64.sid.borrow_mut().set_references(memory.clone());
-        c64.cpu.borrow_mut().set_references(memory.clone(), vic.clone(), cia1.clone(), cia2.clone(), sid.clone());
+        // cartridge has to be in the expansion port before the first reset so
+        // that the kernal can detect it (or the CPU fetches an Ultimax reset vector)
+        // a broken image is reported and the machine starts without it
+        if crt_to_load.len() > 0 {
+            let cartridge = crt::Crt::from_filename(crt_to_load).and_then(|crt| {
+                println!("{:?}", crt);
+                for diag in crt.validate().iter() {
+                    println!("{}: {}", crt_to_load, diag);
+                }
+                crt.into_cartridge()
+            }).map_err(|e| e.to_string());
 
-        drop(memory);
-        drop(cia1);
-        drop(cia2);
-        drop(vic);
-        drop(cpu);
-        drop(sid);
+            if let Err(e) = cartridge.and_then(|cartridge| c64.attach_expansion(Box::new(cartridge))) {
+                println!("Couldn't load cartridge {}: {}", crt_to_load, e);
+                c64.load_error = Some(format!("couldn't load cartridge: {}", e));
+                c64.update_title(0.0, 0.0);
+            }
+        }
 
         c64
     }
 
 
+    // plug a device (RAM expansion, cartridge) into the expansion port
+    pub fn attach_expansion(&mut self, device: Box<dyn expansion::ExpansionPort>) -> std::result::Result<(), String> {
+        self.machine.attach_expansion(device)
+    }
+
+
+    // called once the UI is gone
+    pub fn shutdown(&mut self) {
+        self.machine.bus.memory.flush_expansion();
+    }
+
+
+    // emulation speed in percent of the real machine (MIN_SPEED - MAX_SPEED)
+    pub fn set_speed(&mut self, percent: u32) {
+        self.clock.set_speed(percent);
+    }
+
+
+    // unlimited speed - audio is paused since it can't keep up
+    pub fn set_warp(&mut self, warp: bool) {
+        self.clock.set_warp(warp);
+    }
+
+
+    // 8580 digiboost - makes $D418 volume digis audible on the new SID
+    pub fn set_digiboost(&mut self, digiboost: bool) {
+        self.machine.bus.sound.set_digiboost(digiboost);
+    }
+
+
+    // additional SID chip - address checked with sound::parse_address
+    pub fn add_sid(&mut self, address: u16, model: sid::SIDModel) {
+        self.machine.bus.sound.add_sid(address, model);
+    }
+
+
+    // stereo position of each SID: -100 (left) to 100 (right)
+    pub fn set_sid_panning(&mut self, pans: &[i32]) {
+        self.machine.bus.sound.set_panning(pans);
+    }
+
+
+    // play a PSID/RSID tune after booting - song is 1-based
+    pub fn load_sid(&mut self, tune: psid::Psid, song: u16) {
+        self.tune = Some(tune);
+        self.song = song;
+        self.tune_pending = true;
+        self.update_title(0.0, 0.0);
+    }
+
+
+    // log SID register writes (CSV) and/or the registers after every frame (dump)
+    pub fn log_sid(&mut self, csv_file: Option<&str>, dump_file: Option<&str>) {
+        if let Err(e) = self.machine.bus.sound.start_log(csv_file, dump_file) {
+            println!("Couldn't log SID writes: {}", e);
+        }
+        self.update_title(0.0, 0.0);
+    }
+
+
+    // channels: 1 or 2, sample_rate: 44100 or 48000
+    pub fn set_record_format(&mut self, channels: u16, sample_rate: u32) {
+        self.record_channels = channels;
+        self.record_rate = sample_rate;
+    }
+
+
+    // write the audio output to a WAV file, in emulated time - independent of the live output
+    pub fn record_audio(&mut self, filename: &str) {
+        if let Err(e) = self.machine.bus.sound.start_recording(filename, self.record_channels, self.record_rate) {
+            println!("Couldn't record audio: {}", e);
+        }
+        self.update_title(0.0, 0.0);
+    }
+
+
+    // plug paddles or a mouse into control port 1 or 2 (port: 0 or 1)
+    pub fn set_port_device(&mut self, port: usize, device: control_port::Device) {
+        self.io.set_port_device(port, device);
+        println!("Control port {}: {}", port + 1, device.name());
+    }
+
+
+    // convert the SID playback to a MIDI file, written on quit - add extra SIDs first
+    pub fn export_midi(&mut self, filename: &str) {
+        if let Err(e) = self.machine.bus.sound.start_midi(filename) {
+            println!("Couldn't export MIDI: {}", e);
+        }
+    }
+
+
+    // quit after running for the given emulated time, for batch conversions - with a tune
+    // loaded the time counts from when it starts playing, not from power-on
+    pub fn set_duration(&mut self, seconds: f64) {
+        let duration = (seconds * self.clock_freq as f64) as scheduler::Cycle;
+        self.duration = Some(duration);
+
+        if self.tune.is_none() {
+            self.stop_cycle = Some(self.machine.scheduler.now() + duration);
+        }
+    }
+
+
     pub fn reset(&mut self) {
-        self.memory.borrow_mut().reset();
-        self.cpu.borrow_mut().reset();
-        self.cia1.borrow_mut().reset();
-        self.cia2.borrow_mut().reset();
-        self.sid.borrow_mut().reset();
+        self.machine.reset();
+        self.tune_pending = self.tune.is_some();
     }
 
 
-    pub fn run(&mut self) {
-        // attempt to load a program supplied with command line
-        if !self.powered_on {
-            // $FCE2 is the power-on reset routine, which searches for and starts
-            // a cartridge amongst other things. The cartridge must be loaded here
-            self.powered_on = self.cpu.borrow_mut().pc == 0xFCE2;
-            if self.powered_on {
-                let crt_file = &self.crt_to_load.to_owned()[..];
-                if crt_file.len() > 0 {
-                    let crt = crt::Crt::from_filename(crt_file).unwrap();
-                    println!("{:?}", crt);
-                    crt.load_into_memory(self.memory.borrow_mut());
-                }
+    // emulate a frame - returns false once the UI has quit or the duration is over
+    pub fn run(&mut self) -> bool {
+        if !self.process_events() {
+            return false;
+        }
+
+        if let Some(stop_cycle) = self.stop_cycle {
+            if self.machine.scheduler.now() >= stop_cycle {
+                return false;
             }
         }
 
+        // paused: keep handling input, but don't emulate anything
+        if self.paused && !self.frame_advance {
+            self.machine.bus.sound.pause_audio();
+            thread::sleep(Duration::from_millis(10));
+            return true;
+        }
+
+        // emulate a whole frame, then wait for real time to catch up
+        let first_cycle = self.machine.scheduler.now();
+        while !self.step() {}
+
+        // update audio output - live playback can't follow warp mode
+        let cycles = (self.machine.scheduler.now() - first_cycle) as u32;
+        if self.clock.is_warp() {
+            self.machine.bus.sound.pause_audio();
+        }
+        else {
+            self.machine.bus.sound.update_audio();
+        }
+
+        self.machine.bus.sound.advance_audio();
+        self.clock.sync(cycles);
+        true
+    }
+
+
+    // *** private functions *** //
+
+    // emulate a single cycle - returns true on VBlank
+    fn step(&mut self) -> bool {
+        // attempt to load a program supplied with command line
         if !self.boot_complete {
             // $A480 is the BASIC warm start sequence - safe to assume we can load a cmdline program now
-            self.boot_complete = self.cpu.borrow_mut().pc == 0xA480;
+            self.boot_complete = self.machine.cpu.pc == 0xA480;
 
             if self.boot_complete {
                 let prg_file = &self.file_to_load.to_owned()[..];
@@ -131,63 +323,236 @@ impl C64 {
             }
         }
 
-        // main C64 update - use the clock to time all the operations
-        if self.clock.tick() {
-            let mut should_trigger_vblank = false;
+        // the tune driver takes over the CPU from the BASIC main loop, between two instructions
+        if self.tune_pending && self.machine.cpu.pc == 0xA480 {
+            if let cpu::CPUState::FetchOp = self.machine.cpu.state {
+                self.tune_pending = false;
 
-            if self.vic.borrow_mut().update(self.cycle_count, &mut should_trigger_vblank) {
-                self.sid.borrow_mut().update();
-            }
+                let installed = match self.tune {
+                    Some(ref tune) => tune.install(&mut self.machine, self.song),
+                    None => Ok(()),
+                };
 
-            self.cia1.borrow_mut().process_irq();
-            self.cia2.borrow_mut().process_irq();
-            self.cia1.borrow_mut().update();
-            self.cia2.borrow_mut().update();
-
-            self.cpu.borrow_mut().update(self.cycle_count);
-
-            // update the debugger window if it exists
-            match self.debugger {
-                Some(ref mut dbg) => {
-                    dbg.update_vic_window(&mut self.vic);
-                    if should_trigger_vblank {
-                        dbg.render(&mut self.cpu, &mut self.memory);
-                    }
-                },
-                None => (),
-            }
+                if let Err(e) = installed {
+                    println!("Couldn't start the tune: {}", e);
+                    self.load_error = Some(format!("couldn't start the tune: {}", e));
+                    self.update_title(0.0, 0.0);
+                }
 
-            // redraw the screen and process input on VBlank
-            if should_trigger_vblank {
-                let _ = self.main_window.update_with_buffer(&self.vic.borrow_mut().window_buffer, SCREEN_WIDTH, SCREEN_HEIGHT);
-                self.io.update(&self.main_window, &mut self.cia1);
-                self.cia1.borrow_mut().count_tod();
-                self.cia2.borrow_mut().count_tod();
+                // the kernal boot isn't part of the tune - --duration and MIDI time start here
+                if !self.tune_started {
+                    self.tune_started = true;
 
-                if self.io.check_restore_key(&self.main_window) {
-                    self.cpu.borrow_mut().set_nmi(true);
+                    let now = self.machine.scheduler.now();
+                    self.stop_cycle = self.duration.map(|duration| now + duration);
+                    self.machine.bus.sound.restart_midi(now);
                 }
             }
+        }
+
+        let should_trigger_vblank = self.machine.step();
 
-            // process special keys: console ASM output and reset switch
-            if self.main_window.is_key_pressed(Key::F11, KeyRepeat::No) {
-                let di = self.cpu.borrow_mut().debug_instr;
-                self.cpu.borrow_mut().debug_instr = !di;
+        // update the debugger window if it exists
+        match self.debugger {
+            Some(ref mut dbg) => {
+                dbg.update_vic_window(&self.machine.bus.vic);
+                if should_trigger_vblank {
+                    dbg.render(&self.machine.cpu, &mut self.machine.bus);
+                }
+            },
+            None => (),
+        }
+
+        // hand the finished frame over to the UI on VBlank
+        if should_trigger_vblank {
+            self.machine.bus.sound.log_frame();
+            self.io.update_ports(&mut self.machine.bus.cia1, &mut self.machine.bus.sound);
+            self.frames.publish(&self.machine.bus.vic.window_buffer);
+
+            self.frame_advance = false;
+            self.stats_frames += 1;
+            self.update_stats();
+        }
+
+        should_trigger_vblank
+    }
+
+
+    // input from the UI thread - returns false once the UI has quit
+    fn process_events(&mut self) -> bool {
+        loop {
+            match self.events.try_recv() {
+                Ok(UiEvent::KeyDown(key)) => self.on_key_down(key),
+                Ok(UiEvent::KeyUp(key))   => self.io.on_key(key, false, &mut self.machine.bus.cia1),
+                Ok(UiEvent::MouseMove(x, y)) => self.io.on_mouse_move(x, y),
+                Ok(UiEvent::MouseOut)        => self.io.on_mouse_out(),
+                Ok(UiEvent::MouseButton(button, down)) => self.io.on_mouse_button(button, down),
+                Ok(UiEvent::DebuggerKey(key)) => self.on_debugger_key(key),
+                Ok(UiEvent::Quit) | Err(TryRecvError::Disconnected) => return false,
+                Err(TryRecvError::Empty) => return true,
             }
+        }
+    }
+
+
+    fn on_debugger_key(&mut self, key: Key) {
+        if let Some(ref mut dbg) = self.debugger {
+            dbg.on_key(key);
 
-            if self.main_window.is_key_pressed(Key::F12, KeyRepeat::No) {
-                self.reset();
+            // nothing is rendered while paused - redraw so the new view shows up
+            if self.paused {
+                dbg.render(&self.machine.cpu, &mut self.machine.bus);
             }
+        }
+    }
 
-            self.cycle_count += 1;
+
+    fn on_key_down(&mut self, key: Key) {
+        self.io.on_key(key, true, &mut self.machine.bus.cia1);
+
+        if self.io.check_restore_key(key) {
+            self.machine.bus.lines.set_nmi(true);
         }
 
-        // update SDL2 audio buffers
-        self.sid.borrow_mut().update_audio();
+        match key {
+            // console ASM output and reset switch
+            Key::F11 => {
+                let di = self.machine.cpu.debug_instr;
+                self.machine.cpu.debug_instr = !di;
+            },
+            Key::F12 => self.reset(),
+            Key::PageUp | Key::PageDown => self.change_song(key == Key::PageUp),
+            _ => self.process_speed_key(key)
+        }
     }
 
 
-    // *** private functions *** //
+    // pause, frame advance, speed and warp mode keys
+    fn process_speed_key(&mut self, key: Key) {
+        let speed = self.clock.speed();
+
+        match key {
+            Key::F9 => {
+                self.paused = !self.paused;
+                self.clock.resync();
+                self.stats_time   = time::precise_time_s();
+                self.stats_cycles = self.machine.scheduler.now();
+                self.stats_frames = 0;
+                self.update_title(0.0, 0.0);
+            },
+            Key::F10 => {
+                if self.paused {
+                    self.frame_advance = true;
+                }
+            },
+            Key::NumPadSlash => self.toggle_recording(),
+            Key::NumPadDot => self.toggle_sid_log(),
+            Key::NumPadAsterisk => {
+                let warp = !self.clock.is_warp();
+                self.clock.set_warp(warp);
+            },
+            Key::NumPadPlus => {
+                if let Some(&faster) = SPEED_STEPS.iter().find(|&&step| step > speed) {
+                    self.clock.set_speed(faster);
+                }
+            },
+            Key::NumPadMinus => {
+                if let Some(&slower) = SPEED_STEPS.iter().rev().find(|&&step| step < speed) {
+                    self.clock.set_speed(slower);
+                }
+            },
+            _ => ()
+        }
+    }
+
+
+    // start a new numbered recording in the working directory, or stop the current one
+    fn toggle_recording(&mut self) {
+        if self.machine.bus.sound.recording().is_some() {
+            self.machine.bus.sound.stop_recording();
+            self.update_title(0.0, 0.0);
+            return;
+        }
+
+        let filename = (1..).map(|n| format!("rust64-audio-{}.wav", n)).find(|name| !Path::new(name).exists()).unwrap();
+        self.record_audio(&filename);
+    }
+
+
+    // start logging to new numbered CSV and dump files in the working directory, or stop
+    fn toggle_sid_log(&mut self) {
+        if self.machine.bus.sound.logging() {
+            self.machine.bus.sound.stop_log();
+            self.update_title(0.0, 0.0);
+            return;
+        }
+
+        let n = (1..).find(|n| !Path::new(&format!("rust64-sid-{}.csv", n)).exists()).unwrap();
+        self.log_sid(Some(&format!("rust64-sid-{}.csv", n)), Some(&format!("rust64-sid-{}.dmp", n)));
+    }
+
+
+    // next/previous song of the tune - restarts the machine
+    fn change_song(&mut self, next: bool) {
+        let songs = match self.tune {
+            Some(ref tune) => tune.songs,
+            None => return,
+        };
+
+        self.song = if next { self.song % songs + 1 } else { (self.song + songs - 2) % songs + 1 };
+        self.reset();
+        self.update_title(0.0, 0.0);
+    }
+
+
+    // measure actual emulation speed and FPS once per second
+    fn update_stats(&mut self) {
+        let now = time::precise_time_s();
+        let elapsed = now - self.stats_time;
+
+        if elapsed >= 1.0 {
+            let cycles = self.machine.scheduler.now() - self.stats_cycles;
+            let speed = 100.0 * cycles as f64 / (self.clock_freq as f64 * elapsed);
+            let fps = self.stats_frames as f64 / elapsed;
+            self.update_title(speed, fps);
+
+            self.stats_time   = now;
+            self.stats_cycles = self.machine.scheduler.now();
+            self.stats_frames = 0;
+        }
+    }
+
+
+    fn update_title(&mut self, speed: f64, fps: f64) {
+        let mut title = String::from("Rust64");
+
+        if self.paused {
+            title.push_str(" - paused");
+        }
+        else if speed > 0.0 {
+            let mode = if self.clock.is_warp() { String::from("warp") } else { format!("{}%", self.clock.speed()) };
+            title.push_str(&format!(" - {:.0}% ({}) - {:.1} fps", speed, mode, fps));
+        }
+
+        if let Some(filename) = self.machine.bus.sound.recording() {
+            title.push_str(&format!(" - recording {}", filename));
+        }
+
+        if self.machine.bus.sound.logging() {
+            title.push_str(" - logging SID");
+        }
+
+        if let Some(ref tune) = self.tune {
+            title.push_str(&format!(" - {} / {} ({}) - song {}/{}", tune.name, tune.author, tune.released, self.song, tune.songs));
+        }
+
+        if let Some(ref e) = self.load_error {
+            title.push_str(&format!(" - {}", e));
+        }
+
+        let _ = self.titles.send(title);
+    }
+
 
     // load a *.prg file
     fn load_prg(&mut self, filename: &str) {
@@ -196,7 +561,7 @@ impl C64 {
         println!("Loading {} to start location at ${:04x} ({})", filename, start_address, start_address);
 
         for i in 2..(prg_data.len()) {
-            self.memory.borrow_mut().write_byte(start_address + (i as u16) - 2, prg_data[i]);
+            self.machine.bus.memory.write_byte(start_address + (i as u16) - 2, prg_data[i]);
         }
     }
 }
@@ -0,0 +1,90 @@
+// machine model presets - each C64 variant is a consistent bundle of chip revisions, ROMs and memory
+use c64::cia::CIAModel;
+use c64::sid::SIDModel;
+use c64::vic::VICModel;
+
+
+pub struct MachineModel {
+    pub name: &'static str,
+    pub vic_model: VICModel,
+    pub sid_model: SIDModel,
+    pub cia_model: CIAModel,
+    pub kernal: Option<&'static str>, // kernal image to use instead of kernal.rom
+    pub has_roms: bool,               // false: no BASIC, kernal and chargen at all
+    pub has_datasette: bool,
+    pub has_keyboard: bool,
+    pub ram_size: usize,
+}
+
+impl MachineModel {
+    pub fn from_name(name: &str) -> Option<MachineModel> {
+        match &name.to_lowercase()[..] {
+            "c64" => Some(MachineModel {
+                name: "C64",
+                vic_model: VICModel::MOS6569,
+                sid_model: SIDModel::MOS6581,
+                cia_model: CIAModel::MOS6526,
+                kernal: None,
+                has_roms: true,
+                has_datasette: true,
+                has_keyboard: true,
+                ram_size: 0x10000,
+            }),
+            "c64c" => Some(MachineModel {
+                name: "C64C",
+                vic_model: VICModel::MOS6569,
+                sid_model: SIDModel::MOS8580,
+                cia_model: CIAModel::MOS6526A,
+                kernal: None,
+                has_roms: true,
+                has_datasette: true,
+                has_keyboard: true,
+                ram_size: 0x10000,
+            }),
+            "sx64" => Some(MachineModel {
+                name: "SX-64",
+                vic_model: VICModel::MOS6569,
+                sid_model: SIDModel::MOS6581,
+                cia_model: CIAModel::MOS6526,
+                kernal: Some("kernal-sx64.rom"),
+                has_roms: true,
+                has_datasette: false,
+                has_keyboard: true,
+                ram_size: 0x10000,
+            }),
+            "c64gs" => Some(MachineModel {
+                name: "C64GS",
+                vic_model: VICModel::MOS6569,
+                sid_model: SIDModel::MOS8580,
+                cia_model: CIAModel::MOS6526A,
+                kernal: Some("kernal-gs.rom"),
+                has_roms: true,
+                has_datasette: false,
+                has_keyboard: false,
+                ram_size: 0x10000,
+            }),
+            "max" | "ultimax" => Some(MachineModel {
+                name: "MAX Machine",
+                vic_model: VICModel::MOS6567R8,
+                sid_model: SIDModel::MOS6581,
+                cia_model: CIAModel::MOS6526,
+                kernal: None,
+                has_roms: false,
+                has_datasette: false,
+                has_keyboard: true,
+                ram_size: 0x0800,
+            }),
+            _ => None
+        }
+    }
+
+
+    pub fn describe(&self) -> String {
+        format!("{}: {} VIC-II, {} SID, {} CIA, {}K RAM{}{}", self.name, self.vic_model.name(),
+                self.sid_model.name(),
+                match self.cia_model { CIAModel::MOS6526 => "6526", CIAModel::MOS6526A => "6526A" },
+                self.ram_size / 1024,
+                if self.has_datasette { "" } else { ", no datasette port" },
+                if self.has_keyboard  { "" } else { ", no keyboard" })
+    }
+}
@@ -11,6 +11,7 @@
 // ind = ($0000)              // indirect
 // rel = $0000                // relative to PC/IP
 
+use c64::bus::Bus;
 use c64::cpu;
 use std::fmt;
 
@@ -157,15 +158,15 @@ impl fmt::Display for Instruction {
 }
 
 
-pub fn fetch_operand_addr(cpu: &mut cpu::CPU) -> bool {
+pub fn fetch_operand_addr(cpu: &mut cpu::CPU, bus: &mut Bus) -> bool {
     match cpu.instruction.addr_mode {
         AddrMode::Absolute => {
             match cpu.instruction.cycles_to_fetch {
                 2 => {
-                    cpu.instruction.operand_addr = cpu.next_byte() as u16;
+                    cpu.instruction.operand_addr = cpu.next_byte(bus) as u16;
                 },
                 1 => {
-                    cpu.instruction.operand_addr = cpu.instruction.operand_addr | ((cpu.next_byte() as u16) << 8);
+                    cpu.instruction.operand_addr = cpu.instruction.operand_addr | ((cpu.next_byte(bus) as u16) << 8);
                 },
                 _ => panic!("Too many cycles for operand address fetch! ({}) ", cpu.instruction.cycles_to_fetch)
             }
@@ -173,11 +174,11 @@ pub fn fetch_operand_addr(cpu: &mut cpu::CPU) -> bool {
         AddrMode::AbsoluteIndexedX(extra_cycle) => {
             match cpu.instruction.cycles_to_fetch {
                 3 => {
-                    cpu.instruction.operand_addr = cpu.next_byte() as u16;
+                    cpu.instruction.operand_addr = cpu.next_byte(bus) as u16;
                 },
                 2 => {
                     let addr_lo = cpu.instruction.operand_addr;
-                    cpu.instruction.index_addr = cpu.next_byte() as u16;
+                    cpu.instruction.index_addr = cpu.next_byte(bus) as u16;
                     cpu.instruction.operand_addr = ((addr_lo + cpu.x as u16) & 0xFF) | (cpu.instruction.index_addr << 8);
                     // page crossed?
                     cpu.instruction.zp_crossed = addr_lo + (cpu.x as u16) >= 0x100;
@@ -199,10 +200,10 @@ pub fn fetch_operand_addr(cpu: &mut cpu::CPU) -> bool {
         AddrMode::AbsoluteIndexedY(extra_cycle) => {
             match cpu.instruction.cycles_to_fetch {
                 3 => {
-                    cpu.instruction.operand_addr = cpu.next_byte() as u16;
+                    cpu.instruction.operand_addr = cpu.next_byte(bus) as u16;
                 },
                 2 => {
-                    cpu.instruction.index_addr = cpu.next_byte() as u16;
+                    cpu.instruction.index_addr = cpu.next_byte(bus) as u16;
                     let addr_lo = cpu.instruction.operand_addr;
                     cpu.instruction.operand_addr = ((addr_lo + cpu.y as u16) & 0xFF) | (cpu.instruction.index_addr << 8);
                     // page crossed?
@@ -221,12 +222,12 @@ pub fn fetch_operand_addr(cpu: &mut cpu::CPU) -> bool {
             }
         },
         AddrMode::Zeropage => {
-            cpu.instruction.operand_addr = cpu.next_byte() as u16;
+            cpu.instruction.operand_addr = cpu.next_byte(bus) as u16;
         },
         AddrMode::ZeropageIndexedX => {
             match cpu.instruction.cycles_to_fetch {
                 2 => {
-                    cpu.instruction.operand_addr = cpu.next_byte() as u16;
+                    cpu.instruction.operand_addr = cpu.next_byte(bus) as u16;
                 },
                 1 => {
                     let x = cpu.x as u16;
@@ -239,7 +240,7 @@ pub fn fetch_operand_addr(cpu: &mut cpu::CPU) -> bool {
         AddrMode::ZeropageIndexedY => {
             match cpu.instruction.cycles_to_fetch {
                 2 => {
-                    cpu.instruction.operand_addr = cpu.next_byte() as u16;
+                    cpu.instruction.operand_addr = cpu.next_byte(bus) as u16;
                 },
                 1 => {
                     let y = cpu.y as u16;
@@ -252,18 +253,18 @@ pub fn fetch_operand_addr(cpu: &mut cpu::CPU) -> bool {
         AddrMode::IndexedIndirectX => {
             match cpu.instruction.cycles_to_fetch {
                 4 => {
-                    cpu.instruction.index_addr = cpu.next_byte() as u16;
+                    cpu.instruction.index_addr = cpu.next_byte(bus) as u16;
                 },
                 3 => {
                     cpu.instruction.index_addr = (cpu.instruction.index_addr + cpu.x as u16) & 0xFF;
                 },
                 2 => {
                     let idx_addr = cpu.instruction.index_addr;
-                    cpu.instruction.operand_addr =  cpu.read_byte(idx_addr) as u16;
+                    cpu.instruction.operand_addr =  bus.read_byte(idx_addr) as u16;
                 },
                 1 => {
                     let idx = cpu.instruction.index_addr;
-                    let hi = cpu.read_byte((idx + 1) & 0xFF) as u16;
+                    let hi = bus.read_byte((idx + 1) & 0xFF) as u16;
                     cpu.instruction.operand_addr = cpu.instruction.operand_addr | (hi << 8);
                 },
                 _ => panic!("Too many cycles for operand address fetch! ({}) ", cpu.instruction.cycles_to_fetch)
@@ -272,16 +273,16 @@ pub fn fetch_operand_addr(cpu: &mut cpu::CPU) -> bool {
         AddrMode::IndirectIndexedY(extra_cycle) => {
             match cpu.instruction.cycles_to_fetch {
                 4 => {
-                    cpu.instruction.index_addr = cpu.next_byte() as u16;
+                    cpu.instruction.index_addr = cpu.next_byte(bus) as u16;
                 },
                 3 => {
                     let base_addr = cpu.instruction.index_addr;
-                    cpu.instruction.operand_addr = cpu.read_byte(base_addr) as u16;
+                    cpu.instruction.operand_addr = bus.read_byte(base_addr) as u16;
                 },
                 2 => {
                     let idx = cpu.instruction.index_addr;
                     let opaddr = cpu.instruction.operand_addr;
-                    cpu.instruction.index_addr =  cpu.read_byte((idx + 1) & 0xFF ) as u16;
+                    cpu.instruction.index_addr =  bus.read_byte((idx + 1) & 0xFF ) as u16;
                     cpu.instruction.operand_addr = ((opaddr + cpu.y as u16) & 0x0FF) | (cpu.instruction.index_addr << 8);
                     // page crossed?
                     cpu.instruction.zp_crossed = opaddr + (cpu.y as u16) >= 0x100;
@@ -301,11 +302,11 @@ pub fn fetch_operand_addr(cpu: &mut cpu::CPU) -> bool {
         AddrMode::Indirect => {
             match cpu.instruction.cycles_to_fetch {
                 2 => {
-                    cpu.instruction.operand_addr = cpu.next_byte() as u16;
+                    cpu.instruction.operand_addr = cpu.next_byte(bus) as u16;
                 },
                 1 => {
-                    let addr = cpu.instruction.operand_addr | ((cpu.next_byte() as u16) << 8);
-                    cpu.instruction.operand_addr = cpu.read_word_le(addr);
+                    let addr = cpu.instruction.operand_addr | ((cpu.next_byte(bus) as u16) << 8);
+                    cpu.instruction.operand_addr = bus.read_word_le(addr);
                 },
                 _ => panic!("Too many cycles for operand address fetch! ({}) ", cpu.instruction.cycles_to_fetch)
             }
@@ -320,80 +321,80 @@ pub fn fetch_operand_addr(cpu: &mut cpu::CPU) -> bool {
 
 
 // runs the instruction at its current cycles
-pub fn run(cpu: &mut cpu::CPU) -> bool {
+pub fn run(cpu: &mut cpu::CPU, bus: &mut Bus) -> bool {
     match cpu.instruction.opcode {
         Op::LDA => {
-            if cpu.ba_low { return false; }
-            let na = cpu.get_operand();
+            if bus.lines.ba_low { return false; }
+            let na = cpu.get_operand(bus);
             cpu.a = na;
             cpu.set_zn_flags(na);
         },
         Op::LDX => {
-            if cpu.ba_low { return false; }
-            let nx = cpu.get_operand();
+            if bus.lines.ba_low { return false; }
+            let nx = cpu.get_operand(bus);
             cpu.x = nx;
             cpu.set_zn_flags(nx);
         },
         Op::LDY => {
-            if cpu.ba_low { return false; }
-            let ny = cpu.get_operand();
+            if bus.lines.ba_low { return false; }
+            let ny = cpu.get_operand(bus);
             cpu.y = ny;
             cpu.set_zn_flags(ny);
         },
         Op::STA => {
             let a = cpu.a;
-            cpu.set_operand(a);
+            cpu.set_operand(bus, a);
         },
         Op::STX => {
             let x = cpu.x;
-            cpu.set_operand(x);
+            cpu.set_operand(bus, x);
         },
         Op::STY => {
             let y = cpu.y;
-            cpu.set_operand(y);
+            cpu.set_operand(bus, y);
         },
         Op::TAX => {
-            if cpu.ba_low { return false; }
+            if bus.lines.ba_low { return false; }
             cpu.x = cpu.a;
             let x = cpu.x;
             cpu.set_zn_flags(x);
         },
         Op::TAY => {
-            if cpu.ba_low { return false; }
+            if bus.lines.ba_low { return false; }
             cpu.y = cpu.a;
             let y = cpu.y;
             cpu.set_zn_flags(y);
         },
         Op::TXA => {
-            if cpu.ba_low { return false; }
+            if bus.lines.ba_low { return false; }
             cpu.a = cpu.x;
             let a = cpu.a;
             cpu.set_zn_flags(a);
         },
         Op::TYA => {
-            if cpu.ba_low { return false; }
+            if bus.lines.ba_low { return false; }
             cpu.a = cpu.y;
             let a = cpu.a;
             cpu.set_zn_flags(a);
         },
         Op::TSX => {
-            if cpu.ba_low { return false; }
+            if bus.lines.ba_low { return false; }
             cpu.x = cpu.sp;
             let x = cpu.x;
             cpu.set_zn_flags(x);
         },
         Op::TXS => {
-            if cpu.ba_low { return false; }
+            if bus.lines.ba_low { return false; }
             cpu.sp = cpu.x;
         },
         Op::PHA => {
             match cpu.instruction.cycles_to_run {
                 2 => {
-                    if cpu.ba_low { return false; }
+                    if bus.lines.ba_low { return false; }
                 },
                 1 => {
                     let a = cpu.a;
-                    cpu.push_byte(a);
+                    cpu.push_byte(bus, a);
                 },
                 _ => panic!("Wrong number of cycles: {} {}", cpu.instruction, cpu.instruction.cycles_to_run)
             }
@@ -401,22 +402,22 @@ pub fn run(cpu: &mut cpu::CPU) -> bool {
         Op::PHP => {
             match cpu.instruction.cycles_to_run {
                 2 => {
-                    if cpu.ba_low { return false; }
+                    if bus.lines.ba_low { return false; }
                 },
                 1 => {
                     let p = cpu.p;
                     // TODO: break flag?
-                    cpu.push_byte(p);
+                    cpu.push_byte(bus, p);
                 },
                 _ => panic!("Wrong number of cycles: {} {}", cpu.instruction, cpu.instruction.cycles_to_run)
             }
         },
         Op::PLA => {
-            if cpu.ba_low { return false; }
+            if bus.lines.ba_low { return false; }
             match cpu.instruction.cycles_to_run {
                 3 | 2 => {},
                 1 => {
-                    let a = cpu.pop_byte();
+                    let a = cpu.pop_byte(bus);
                     cpu.a = a;
                     cpu.set_zn_flags(a);
                 },
@@ -424,73 +425,73 @@ pub fn run(cpu: &mut cpu::CPU) -> bool {
             }
         },
         Op::PLP => {
-            if cpu.ba_low { return false; }
+            if bus.lines.ba_low { return false; }
             match cpu.instruction.cycles_to_run {
                 3 | 2 => {},
                 1 => {
                     // TODO: opflags
-                    let p = cpu.pop_byte();
+                    let p = cpu.pop_byte(bus);
                     cpu.p = p;
                 },
                 _ => panic!("Wrong number of cycles: {} {}", cpu.instruction, cpu.instruction.cycles_to_run)
             }
         },
         Op::AND => {
-            if cpu.ba_low { return false; }
-            let v = cpu.get_operand();
+            if bus.lines.ba_low { return false; }
+            let v = cpu.get_operand(bus);
             let na = cpu.a & v;
             cpu.a = na;
             cpu.set_zn_flags(na);
         },
         Op::EOR => {
-            if cpu.ba_low { return false; }
-            let v = cpu.get_operand();
+            if bus.lines.ba_low { return false; }
+            let v = cpu.get_operand(bus);
             let na = cpu.a ^ v;
             cpu.a = na;
             cpu.set_zn_flags(na);
         },
         Op::ORA => {
-            if cpu.ba_low { return false; }
-            let v = cpu.get_operand();
+            if bus.lines.ba_low { return false; }
+            let v = cpu.get_operand(bus);
             let na = cpu.a | v;
             cpu.a = na;
             cpu.set_zn_flags(na);
         },
         Op::BIT => {
-            if cpu.ba_low { return false; }
-            let v = cpu.get_operand();
+            if bus.lines.ba_low { return false; }
+            let v = cpu.get_operand(bus);
             let a = cpu.a;
             cpu.set_status_flag(cpu::StatusFlag::Negative, (v as i8) < 0);
             cpu.set_status_flag(cpu::StatusFlag::Overflow, (v & 0x40) != 0);
             cpu.set_status_flag(cpu::StatusFlag::Zero,     (v & a)    == 0);
         },
         Op::ADC => {
-            if cpu.ba_low { return false; }
-            let v = cpu.get_operand();
+            if bus.lines.ba_low { return false; }
+            let v = cpu.get_operand(bus);
             cpu.adc(v);
         },
         Op::SBC => {
-            if cpu.ba_low { return false; }
-            let v = cpu.get_operand();
+            if bus.lines.ba_low { return false; }
+            let v = cpu.get_operand(bus);
             cpu.sbc(v);
         },
         Op::CMP => {
-            if cpu.ba_low { return false; }
-            let v = cpu.get_operand();
+            if bus.lines.ba_low { return false; }
+            let v = cpu.get_operand(bus);
             let res = cpu.a as i16 - v as i16;
             cpu.set_status_flag(cpu::StatusFlag::Carry, res >= 0);
             cpu.set_zn_flags(res as u8);
         },
         Op::CPX => {
-            if cpu.ba_low { return false; }
-            let v = cpu.get_operand();
+            if bus.lines.ba_low { return false; }
+            let v = cpu.get_operand(bus);
             let res = cpu.x as i16 - v as i16;
             cpu.set_status_flag(cpu::StatusFlag::Carry, res >= 0);
             cpu.set_zn_flags(res as u8);
         },
         Op::CPY => {
-            if cpu.ba_low { return false; }
-            let v = cpu.get_operand();
+            if bus.lines.ba_low { return false; }
+            let v = cpu.get_operand(bus);
             let res = cpu.y as i16 - v as i16;
             cpu.set_status_flag(cpu::StatusFlag::Carry, res >= 0);
             cpu.set_zn_flags(res as u8);
@@ -498,17 +499,17 @@ pub fn run(cpu: &mut cpu::CPU) -> bool {
         Op::INC => {
             let v = cpu.instruction.rmw_buffer.wrapping_add(0x01);
             let addr = cpu.instruction.operand_addr;
-            cpu.write_byte(addr, v);
+            bus.write_byte(addr, v);
             cpu.set_zn_flags(v);
         },
         Op::INX => {
-            if cpu.ba_low { return false; }
+            if bus.lines.ba_low { return false; }
             cpu.x = cpu.x.wrapping_add(0x01);
             let x = cpu.x;
             cpu.set_zn_flags(x);
         },
         Op::INY => {
-            if cpu.ba_low { return false; }
+            if bus.lines.ba_low { return false; }
             cpu.y = cpu.y.wrapping_add(0x01);
             let y = cpu.y;
             cpu.set_zn_flags(y);
@@ -516,83 +517,83 @@ pub fn run(cpu: &mut cpu::CPU) -> bool {
         Op::DEC => {
             let v = cpu.instruction.rmw_buffer.wrapping_sub(0x01);
             let addr = cpu.instruction.operand_addr;
-            cpu.write_byte(addr, v);
+            bus.write_byte(addr, v);
             cpu.set_zn_flags(v);
         },
         Op::DEX => {
-            if cpu.ba_low { return false; }
+            if bus.lines.ba_low { return false; }
             cpu.x = cpu.x.wrapping_sub(0x01);
             let x = cpu.x;
             cpu.set_zn_flags(x);
         },
         Op::DEY => {
-            if cpu.ba_low { return false; }
+            if bus.lines.ba_low { return false; }
             cpu.y = cpu.y.wrapping_sub(0x01);
             let y = cpu.y;
             cpu.set_zn_flags(y);
         },
         Op::ASL => {
-            if cpu.ba_low {
+            if bus.lines.ba_low {
                 match cpu.instruction.addr_mode {
                     AddrMode::Accumulator => return false,
                     _ => (),
                 }
             }
-            let v = cpu.get_operand();
+            let v = cpu.get_operand(bus);
             cpu.set_status_flag(cpu::StatusFlag::Carry, (v & 0x80) != 0);
             let res = v << 1;
-            cpu.set_operand(res);
+            cpu.set_operand(bus, res);
             cpu.set_zn_flags(res);
         },
         Op::LSR => {
-            if cpu.ba_low {
+            if bus.lines.ba_low {
                 match cpu.instruction.addr_mode {
                     AddrMode::Accumulator => return false,
                     _ => (),
                 }
             }
-            let v = cpu.get_operand();
+            let v = cpu.get_operand(bus);
             cpu.set_status_flag(cpu::StatusFlag::Carry, (v & 0x01) != 0);
             let res = v >> 1;
-            cpu.set_operand(res);
+            cpu.set_operand(bus, res);
             cpu.set_zn_flags(res);
         },
         Op::ROL => {
-            if cpu.ba_low {
+            if bus.lines.ba_low {
                 match cpu.instruction.addr_mode {
                     AddrMode::Accumulator => return false,
                     _ => (),
                 }
             }
             let c = cpu.get_status_flag(cpu::StatusFlag::Carry);
-            let v = cpu.get_operand();
+            let v = cpu.get_operand(bus);
             cpu.set_status_flag(cpu::StatusFlag::Carry, (v & 0x80) != 0);
             let mut res = v << 1;
             if c {
                 res |= 0x01;
             }
-            cpu.set_operand(res);
+            cpu.set_operand(bus, res);
             cpu.set_zn_flags(res);
         },
         Op::ROR => {
-            if cpu.ba_low {
+            if bus.lines.ba_low {
                 match cpu.instruction.addr_mode {
                     AddrMode::Accumulator => return false,
                     _ => (),
                 }
             }
             let c = cpu.get_status_flag(cpu::StatusFlag::Carry);
-            let v = cpu.get_operand();
+            let v = cpu.get_operand(bus);
             cpu.set_status_flag(cpu::StatusFlag::Carry, (v & 0x01) != 0);
             let mut res = v >> 1;
             if c {
                 res |= 0x80;
             }
-            cpu.set_operand(res);
+            cpu.set_operand(bus, res);
             cpu.set_zn_flags(res);
         },
         Op::JMP => { // TODO: is this ok?
-            if cpu.ba_low { return false; }
+            if bus.lines.ba_low { return false; }
             match cpu.instruction.cycles_to_run {
                 2 => {
                     //cpu.instruction.cycles_to_run -= 1;
@@ -611,26 +612,26 @@ pub fn run(cpu: &mut cpu::CPU) -> bool {
                 },
                 2 => {
                     let pc = cpu.pc - 0x0001;
-                    cpu.push_word(pc);
+                    cpu.push_word(bus, pc);
                 },
                 1  => {
-                    if cpu.ba_low { return false; }
+                    if bus.lines.ba_low { return false; }
                     cpu.pc = cpu.instruction.operand_addr;
                 },
                 _ => panic!("Wrong number of cycles: {} {} ", cpu.instruction, cpu.instruction.cycles_to_run)
             }
         },
         Op::RTS => {
-            if cpu.ba_low { return false; }
+            if bus.lines.ba_low { return false; }
 
             match cpu.instruction.cycles_to_run {
                 5 | 4 => {},
                 3 => {
-                    let pc_lo = cpu.pop_byte() as u16;
+                    let pc_lo = cpu.pop_byte(bus) as u16;
                     cpu.pc = pc_lo;
                 },
                 2 => {
-                    let pc_hi = cpu.pop_byte() as u16;
+                    let pc_hi = cpu.pop_byte(bus) as u16;
                     cpu.pc |= pc_hi << 8;
                 },
                 1  => {
@@ -646,7 +647,7 @@ pub fn run(cpu: &mut cpu::CPU) -> bool {
         Op::BCC => {
             let flag_condition = !cpu.get_status_flag(cpu::StatusFlag::Carry);
             let cycle = cpu.instruction.cycles_to_run;
-            if !cpu.branch(flag_condition, cycle) {
+            if !cpu.branch(bus, flag_condition, cycle) {
                 // ba_low is set - no cycle advancement
                 return false;
             }
@@ -654,7 +655,7 @@ pub fn run(cpu: &mut cpu::CPU) -> bool {
         Op::BCS => {
             let flag_condition = cpu.get_status_flag(cpu::StatusFlag::Carry);
             let cycle = cpu.instruction.cycles_to_run;
-            if !cpu.branch(flag_condition, cycle) {
+            if !cpu.branch(bus, flag_condition, cycle) {
                 // ba_low is set - no cycle advancement
                 return false;
             }
@@ -662,7 +663,7 @@ pub fn run(cpu: &mut cpu::CPU) -> bool {
         Op::BEQ => {
             let flag_condition = cpu.get_status_flag(cpu::StatusFlag::Zero);
             let cycle = cpu.instruction.cycles_to_run;
-            if !cpu.branch(flag_condition, cycle) {
+            if !cpu.branch(bus, flag_condition, cycle) {
                 // ba_low is set - no cycle advancement
                 return false;
             }
@@ -670,7 +671,7 @@ pub fn run(cpu: &mut cpu::CPU) -> bool {
         Op::BNE => {
             let flag_condition = !cpu.get_status_flag(cpu::StatusFlag::Zero);
             let cycle = cpu.instruction.cycles_to_run;
-            if !cpu.branch(flag_condition, cycle) {
+            if !cpu.branch(bus, flag_condition, cycle) {
                 // ba_low is set - no cycle advancement
                 return false;
             }
@@ -678,7 +679,7 @@ pub fn run(cpu: &mut cpu::CPU) -> bool {
         Op::BMI => {
             let flag_condition = cpu.get_status_flag(cpu::StatusFlag::Negative);
             let cycle = cpu.instruction.cycles_to_run;
-            if !cpu.branch(flag_condition, cycle) {
+            if !cpu.branch(bus, flag_condition, cycle) {
                 // ba_low is set - no cycle advancement
                 return false;
             }
@@ -686,7 +687,7 @@ pub fn run(cpu: &mut cpu::CPU) -> bool {
         Op::BPL => {
             let flag_condition = !cpu.get_status_flag(cpu::StatusFlag::Negative);
             let cycle = cpu.instruction.cycles_to_run;
-            if !cpu.branch(flag_condition, cycle) {
+            if !cpu.branch(bus, flag_condition, cycle) {
                 // ba_low is set - no cycle advancement
                 return false;
             }
@@ -694,7 +695,7 @@ pub fn run(cpu: &mut cpu::CPU) -> bool {
         Op::BVC => {
             let flag_condition = !cpu.get_status_flag(cpu::StatusFlag::Overflow);
             let cycle = cpu.instruction.cycles_to_run;
-            if !cpu.branch(flag_condition, cycle) {
+            if !cpu.branch(bus, flag_condition, cycle) {
                 // ba_low is set - no cycle advancement
                 return false;
             }
@@ -702,90 +703,90 @@ pub fn run(cpu: &mut cpu::CPU) -> bool {
         Op::BVS => {
             let flag_condition = cpu.get_status_flag(cpu::StatusFlag::Overflow);
             let cycle = cpu.instruction.cycles_to_run;
-            if !cpu.branch(flag_condition, cycle) {
+            if !cpu.branch(bus, flag_condition, cycle) {
                 // ba_low is set - no cycle advancement
                 return false;
             }
         },
         Op::CLC => {
-            if cpu.ba_low { return false; }
+            if bus.lines.ba_low { return false; }
             cpu.set_status_flag(cpu::StatusFlag::Carry, false);
         },
         Op::CLD => {
-            if cpu.ba_low { return false; }
+            if bus.lines.ba_low { return false; }
             cpu.set_status_flag(cpu::StatusFlag::DecimalMode, false);
         },
         Op::CLI => {
-            if cpu.ba_low { return false; }
+            if bus.lines.ba_low { return false; }
             cpu.set_status_flag(cpu::StatusFlag::InterruptDisable, false);
         },
         Op::CLV => {
-            if cpu.ba_low { return false; }
+            if bus.lines.ba_low { return false; }
             cpu.set_status_flag(cpu::StatusFlag::Overflow, false);
         },
         Op::SEC => {
-            if cpu.ba_low { return false; }
+            if bus.lines.ba_low { return false; }
             cpu.set_status_flag(cpu::StatusFlag::Carry, true);
         },
         Op::SED => {
-            if cpu.ba_low { return false; }
+            if bus.lines.ba_low { return false; }
             cpu.set_status_flag(cpu::StatusFlag::DecimalMode, true);
         },
         Op::SEI => {
-            if cpu.ba_low { return false; }
+            if bus.lines.ba_low { return false; }
             cpu.set_status_flag(cpu::StatusFlag::InterruptDisable, true);
         },
         Op::BRK => { // TODO: is this ok? do we have to break down new PC value to 2 cycles? read_word ok here?
             match cpu.instruction.cycles_to_run {
                 6 => {
-                    if cpu.ba_low { return false; }
+                    if bus.lines.ba_low { return false; }
                 },
                 5 => {
                     let pc = cpu.pc + 0x0001;
-                    cpu.push_byte(((pc >> 8) & 0xFF) as u8);
+                    cpu.push_byte(bus, ((pc >> 8) & 0xFF) as u8);
                 },
                 4 => {
                     let pc = cpu.pc + 0x0001;
-                    cpu.push_byte((pc & 0xFF) as u8);
+                    cpu.push_byte(bus, (pc & 0xFF) as u8);
                 },
                 3 => {
                     cpu.set_status_flag(cpu::StatusFlag::Break, true);
                     let p = cpu.p;
-                    cpu.push_byte(p);
+                    cpu.push_byte(bus, p);
                     cpu.set_status_flag(cpu::StatusFlag::InterruptDisable, true);
-                    if cpu.nmi {
+                    if bus.lines.nmi {
                         cpu.nmi_cycles_left = 7;
                         cpu.state = cpu::CPUState::ProcessNMI;
                     }
                 },
                 2 => {
-                    cpu.first_nmi_cycle += 1; // delay NMI
+                    bus.lines.first_nmi_cycle += 1; // delay NMI
                 },
                 1  => {
                     //println!("Received BRK instruction at ${:04X}", cpu.pc-1);
-                    cpu.pc = cpu.read_word_le(cpu::IRQ_VECTOR);
+                    cpu.pc = bus.read_word_le(cpu::IRQ_VECTOR);
                 },
                 _ => panic!("Wrong number of cycles: {} {} ", cpu.instruction, cpu.instruction.cycles_to_run)
             }
         },
         Op::NOP => {
-            if cpu.ba_low { return false; }
+            if bus.lines.ba_low { return false; }
         },
         Op::RTI => { // TODO is this ok?
-            if cpu.ba_low { return false; }
+            if bus.lines.ba_low { return false; }
 
             match cpu.instruction.cycles_to_run {
                 5 | 4 => {},
                 3 => {
-                    let p = cpu.pop_byte();
+                    let p = cpu.pop_byte(bus);
                     cpu.p = p;
                 },
                 2 => {
-                    let pc_lo = cpu.pop_byte() as u16;
+                    let pc_lo = cpu.pop_byte(bus) as u16;
                     cpu.pc = pc_lo;
                 },
                 1  => {
-                    let pc_hi = cpu.pop_byte() as u16;
+                    let pc_hi = cpu.pop_byte(bus) as u16;
                     cpu.pc |= pc_hi << 8;
                 },
                 _ => panic!("Wrong number of cycles: {} {} ", cpu.instruction, cpu.instruction.cycles_to_run)
@@ -805,7 +806,7 @@ pub fn run(cpu: &mut cpu::CPU) -> bool {
             cpu.set_zn_flags(na);
         },
         Op::ANC => {
-            let v = cpu.get_operand();
+            let v = cpu.get_operand(bus);
             let na = cpu.a & v;
             cpu.set_zn_flags(na);
             let n = cpu.get_status_flag(cpu::StatusFlag::Negative);
@@ -847,13 +848,13 @@ pub fn run(cpu: &mut cpu::CPU) -> bool {
         },
         Op::SAX => {
             let v = cpu.a & cpu.x;
-            cpu.set_operand(v);
+            cpu.set_operand(bus, v);
         },
         Op::AHX => {
             let addr = cpu.instruction.operand_addr;
             let addr_hi = cpu.instruction.index_addr as u8;
             let y = cpu.y;
-            cpu.write_byte(addr, y & (addr_hi + 1));
+            bus.write_byte(addr, y & (addr_hi + 1));
         },
         Op::TAS => {
             let addr = cpu.instruction.operand_addr;
@@ -861,31 +862,31 @@ pub fn run(cpu: &mut cpu::CPU) -> bool {
             let a = cpu.a;
             let x = cpu.x;
             cpu.sp = a & x;
-            cpu.write_byte(addr, (a & x) & (addr_hi + 1));
+            bus.write_byte(addr, (a & x) & (addr_hi + 1));
         },
         Op::SHY => {
             let addr = cpu.instruction.operand_addr;
             let addr_hi = cpu.instruction.index_addr as u8;
             let a = cpu.a;
             let x = cpu.x;
-            cpu.write_byte(addr, a & x & (addr_hi + 1));
+            bus.write_byte(addr, a & x & (addr_hi + 1));
         },
         Op::SHX => {
             let addr = cpu.instruction.operand_addr;
             let addr_hi = cpu.instruction.index_addr as u8;
             let x = cpu.x;
-            cpu.write_byte(addr, x & (addr_hi + 1));
+            bus.write_byte(addr, x & (addr_hi + 1));
         },
         Op::LAX => {
-            if cpu.ba_low { return false; }
-            let nv = cpu.get_operand();
+            if bus.lines.ba_low { return false; }
+            let nv = cpu.get_operand(bus);
             cpu.a = nv;
             cpu.x = nv;
             cpu.set_zn_flags(nv);
         }, 
         Op::DCP => {
             let v = cpu.instruction.rmw_buffer.wrapping_sub(0x01);
-            cpu.set_operand(v);
+            cpu.set_operand(bus, v);
             let diff = cpu.a as i16 - v as i16;
             cpu.set_zn_flags(diff as u8);
             cpu.set_status_flag(cpu::StatusFlag::Carry, diff >= 0);
@@ -893,7 +894,7 @@ pub fn run(cpu: &mut cpu::CPU) -> bool {
         Op::ISC => {
             cpu.instruction.rmw_buffer += 1;
             let v = cpu.instruction.rmw_buffer;
-            cpu.set_operand(v);
+            cpu.set_operand(bus, v);
             cpu.sbc(v);
         },
         _ => panic!("Unknown instruction: {} at ${:04X}", cpu.instruction, cpu.pc)
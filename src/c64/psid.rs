@@ -0,0 +1,470 @@
+// PSID/RSID music files: a C64 program plus a header telling a player how to start it
+// PSID tunes are started by a small driver that calls the init routine and then the play routine
+// from a raster (VBI) or CIA timer interrupt, RSID tunes run on a booted machine and take care of their own interrupts
+use c64::cpu;
+use c64::machine;
+use c64::memory;
+use c64::sid::SIDModel;
+use c64::vic::VICModel;
+use std::fs::File;
+use std::io::Read;
+
+const V1_HEADER_LEN: usize = 0x76;
+const V2_HEADER_LEN: usize = 0x7C;
+
+// header flags (v2+)
+const FLAG_BASIC: u16 = 0x02; // RSID: start the tune with RUN
+
+// free memory used for the driver unless the tune occupies it: the tape buffer
+const DRIVER_ADDRESS: u16 = 0x0334;
+const DRIVER_SPACE:   u16 = 0x00C8;
+
+// raster line of the VBI interrupt
+const VBI_LINE: u8 = 0x00;
+
+
+pub struct Psid {
+    pub rsid: bool,
+    pub version: u16,
+    pub load_address: u16,
+    pub init_address: u16,
+    pub play_address: u16, // 0: init sets up its own interrupt
+    pub songs: u16,
+    pub start_song: u16,   // 1-based
+    pub name: String,
+    pub author: String,
+    pub released: String,
+    speed: u32,            // bit per song: 0 = VBI, 1 = CIA timer
+    flags: u16,
+    start_page: u8,        // free memory for the driver: 0 = anywhere outside the tune, 0xFF = none
+    page_length: u8,
+    sid_bytes: [u8; 2],    // second and third SID address: $Dxx0 as xx
+    data: Vec<u8>,
+}
+
+impl Psid {
+    pub fn from_filename(filename: &str) -> Result<Psid, String> {
+        let mut data = Vec::new();
+        File::open(filename).and_then(|mut file| file.read_to_end(&mut data))
+                            .map_err(|e| format!("Couldn't read {}: {}", filename, e))?;
+
+        Psid::from_bytes(&data).map_err(|e| format!("{}: {}", filename, e))
+    }
+
+
+    pub fn from_bytes(file: &[u8]) -> Result<Psid, String> {
+        if file.len() < V1_HEADER_LEN || (&file[..4] != b"PSID" && &file[..4] != b"RSID") {
+            return Err(String::from("not a PSID or RSID file"));
+        }
+
+        let rsid = &file[..4] == b"RSID";
+        let version = read_word_be(file, 0x04);
+        let data_offset = read_word_be(file, 0x06) as usize;
+
+        if version < 1 || version > 4 || (rsid && version < 2) {
+            return Err(format!("unsupported version {}", version));
+        }
+        if data_offset < (if version == 1 { V1_HEADER_LEN } else { V2_HEADER_LEN }) || data_offset >= file.len() {
+            return Err(format!("invalid data offset ${:04X}", data_offset));
+        }
+
+        let mut load_address = read_word_be(file, 0x08);
+        let mut data = &file[data_offset..];
+
+        // a load address of zero means the data starts with one, like a .prg
+        if load_address == 0 {
+            if data.len() < 2 {
+                return Err(String::from("file ends inside the load address"));
+            }
+            load_address = data[0] as u16 | ((data[1] as u16) << 8);
+            data = &data[2..];
+        }
+
+        if load_address as usize + data.len() > 0x10000 {
+            return Err(format!("data at ${:04X} doesn't fit into memory ({} bytes)", load_address, data.len()));
+        }
+
+        let v2 = version >= 2 && file.len() >= V2_HEADER_LEN;
+
+        let mut psid = Psid {
+            rsid: rsid,
+            version: version,
+            load_address: load_address,
+            init_address: read_word_be(file, 0x0A),
+            play_address: read_word_be(file, 0x0C),
+            songs: read_word_be(file, 0x0E),
+            start_song: read_word_be(file, 0x10),
+            name: read_string(&file[0x16..0x36]),
+            author: read_string(&file[0x36..0x56]),
+            released: read_string(&file[0x56..0x76]),
+            speed: (read_word_be(file, 0x12) as u32) << 16 | read_word_be(file, 0x14) as u32,
+            flags: if v2 { read_word_be(file, 0x76) } else { 0 },
+            start_page: if v2 { file[0x78] } else { 0 },
+            page_length: if v2 { file[0x79] } else { 0 },
+            sid_bytes: [if version >= 3 { file[0x7A] } else { 0 }, if version >= 4 { file[0x7B] } else { 0 }],
+            data: data.to_vec(),
+        };
+
+        if psid.songs == 0 {
+            return Err(String::from("no songs in file"));
+        }
+
+        // init address 0 means the start of the data
+        if psid.init_address == 0 {
+            psid.init_address = psid.load_address;
+        }
+
+        if psid.start_song == 0 || psid.start_song > psid.songs {
+            psid.start_song = 1;
+        }
+
+        if psid.rsid && (psid.play_address != 0 || psid.speed != 0 || psid.load_address < 0x07E8) {
+            return Err(String::from("invalid RSID header (play address, speed or load address)"));
+        }
+
+        Ok(psid)
+    }
+
+
+    pub fn is_basic(&self) -> bool {
+        self.rsid && (self.flags & FLAG_BASIC) != 0
+    }
+
+
+    // video standard the tune was made for - None if it plays on both
+    pub fn vic_model(&self) -> Option<VICModel> {
+        match (self.flags >> 2) & 3 {
+            1 => Some(VICModel::MOS6569),
+            2 => Some(VICModel::MOS6567R8),
+            _ => None,
+        }
+    }
+
+
+    // SID revision for chip 0-2 - None if it plays on both (or the header doesn't say)
+    pub fn sid_model(&self, chip: usize) -> Option<SIDModel> {
+        let bits = match chip {
+            0 => (self.flags >> 4) & 3,
+            1 => (self.flags >> 6) & 3,
+            _ => (self.flags >> 8) & 3,
+        };
+
+        match bits {
+            1 => Some(SIDModel::MOS6581),
+            2 => Some(SIDModel::MOS8580),
+            // additional SIDs default to the model of the first one
+            _ if chip > 0 => self.sid_model(0),
+            _ => None,
+        }
+    }
+
+
+    // addresses of the second and third SID (v3/v4 headers)
+    pub fn extra_sids(&self) -> Vec<u16> {
+        let mut addresses = Vec::new();
+
+        for &byte in self.sid_bytes.iter() {
+            // only even values in $42-$7F and $E0-$FE are valid
+            if (byte & 1) == 0 && ((byte >= 0x42 && byte <= 0x7F) || byte >= 0xE0) {
+                addresses.push(0xD000 | ((byte as u16) << 4));
+            }
+            else {
+                break;
+            }
+        }
+
+        addresses
+    }
+
+
+    // play routine is called from a CIA timer interrupt (60Hz unless init changes the timer), not VBI
+    pub fn uses_cia(&self, song: u16) -> bool {
+        let bit = (song as u32 - 1).min(31);
+        self.rsid || (self.speed & (1 << bit)) != 0
+    }
+
+
+    pub fn describe(&self) -> String {
+        format!("{} v{}: {} by {} ({}), {} song(s), load ${:04X}-${:04X}, init ${:04X}, play ${:04X}",
+                if self.rsid { "RSID" } else { "PSID" }, self.version, self.name, self.author, self.released,
+                self.songs, self.load_address, self.end_address(), self.init_address, self.play_address)
+    }
+
+
+    // copy the tune into memory and start playing a song (1-based)
+    // called once the kernal has booted into the BASIC main loop
+    pub fn install(&self, machine: &mut machine::Machine, song: u16) -> Result<(), String> {
+        let address = if self.is_basic() { 0 } else { self.driver_address()? };
+
+        {
+            let ram = machine.bus.memory.get_ram_bank(memory::MemType::Ram);
+            for (i, &byte) in self.data.iter().enumerate() {
+                ram.write(self.load_address + i as u16, byte);
+            }
+        }
+
+        if self.is_basic() {
+            self.run_basic(machine, song);
+            return Ok(());
+        }
+
+        let driver = self.driver(address, song);
+
+        {
+            let ram = machine.bus.memory.get_ram_bank(memory::MemType::Ram);
+            for (i, &byte) in driver.iter().enumerate() {
+                ram.write(address + i as u16, byte);
+            }
+        }
+
+        machine.cpu.pc = address;
+        Ok(())
+    }
+
+
+    // *** private functions *** //
+
+    fn end_address(&self) -> u16 {
+        (self.load_address as usize + self.data.len()).saturating_sub(1) as u16
+    }
+
+
+    fn overlaps(&self, start: u16, len: u16) -> bool {
+        let end = start as usize + len as usize;
+        (start as usize) <= self.end_address() as usize && end > self.load_address as usize
+    }
+
+
+    // tape buffer unless the tune is there, otherwise the area the header reserves
+    // or the first free page behind the tune
+    fn driver_address(&self) -> Result<u16, String> {
+        if !self.overlaps(DRIVER_ADDRESS, DRIVER_SPACE) {
+            return Ok(DRIVER_ADDRESS);
+        }
+
+        if self.start_page != 0 && self.start_page != 0xFF && self.page_length > 0 {
+            return Ok((self.start_page as u16) << 8);
+        }
+
+        (0x04..0xD0).map(|page| page << 8).find(|&address| !self.overlaps(address, 0x100))
+                    .ok_or(format!("no free page for the player driver: the tune covers ${:04X}-${:04X}", self.load_address, self.end_address()))
+    }
+
+
+    // memory configuration while the tune runs: kernal ROM stays visible unless the tune covers it
+    fn bank(&self) -> u8 {
+        let top = self.end_address().max(self.init_address).max(self.play_address);
+
+        if top < 0xA000 { 0x37 } else if top < 0xD000 { 0x36 } else { 0x35 }
+    }
+
+
+    // init the song, then idle while an interrupt calls the play routine
+    fn driver(&self, address: u16, song: u16) -> Vec<u8> {
+        let bank = if self.rsid { 0x37 } else { self.bank() };
+        let kernal = bank != 0x35;
+        let play = !self.rsid && self.play_address != 0;
+        let vbi = play && !self.uses_cia(song);
+
+        let mut code = vec![
+            0x78,                   // SEI
+            0xA9, bank, 0x85, 0x01, // LDA #bank, STA $01
+        ];
+
+        if vbi {
+            code.extend_from_slice(&[
+                0xA9, 0x7F, 0x8D, 0x0D, 0xDC, // LDA #$7F, STA $DC0D - no CIA interrupts
+                0xAD, 0x0D, 0xDC,             // LDA $DC0D
+                0xA9, 0x01, 0x8D, 0x1A, 0xD0, // LDA #$01, STA $D01A - raster interrupt
+                0xA9, 0x1B, 0x8D, 0x11, 0xD0, // LDA #$1B, STA $D011
+                0xA9, VBI_LINE, 0x8D, 0x12, 0xD0, // LDA #line, STA $D012
+            ]);
+        }
+
+        // the interrupt handler follows the init call and idle loop - patched in below
+        let vector_pos = code.len();
+        if play {
+            let vector = if kernal { 0x0314 } else { cpu::IRQ_VECTOR };
+            code.extend_from_slice(&[
+                0xA9, 0x00, 0x8D, lo(vector), hi(vector),         // LDA #<irq, STA vector
+                0xA9, 0x00, 0x8D, lo(vector + 1), hi(vector + 1), // LDA #>irq, STA vector + 1
+            ]);
+        }
+
+        let loop_address = address + code.len() as u16 + 6;
+        code.extend_from_slice(&[
+            0xA9, (song - 1) as u8,                                   // LDA #song
+            0x20, lo(self.init_address), hi(self.init_address),       // JSR init
+            0x58,                                                     // CLI
+            0x4C, lo(loop_address), hi(loop_address),                 // JMP * - idle
+        ]);
+
+        if play {
+            let irq = address + code.len() as u16;
+            code[vector_pos + 1] = lo(irq);
+            code[vector_pos + 6] = hi(irq);
+
+            // the kernal saves the registers before jumping through $0314, a RAM vector at $FFFE has to do it
+            if !kernal {
+                code.extend_from_slice(&[0x48, 0x8A, 0x48, 0x98, 0x48]); // PHA, TXA, PHA, TYA, PHA
+            }
+
+            if vbi {
+                code.extend_from_slice(&[0xA9, 0x01, 0x8D, 0x19, 0xD0]); // LDA #$01, STA $D019 - acknowledge
+            }
+            else {
+                code.extend_from_slice(&[0xAD, 0x0D, 0xDC]);             // LDA $DC0D - acknowledge
+            }
+
+            code.extend_from_slice(&[0x20, lo(self.play_address), hi(self.play_address)]); // JSR play
+
+            if kernal {
+                code.extend_from_slice(&[0x4C, 0x81, 0xEA]);                   // JMP $EA81 - restore and RTI
+            }
+            else {
+                code.extend_from_slice(&[0x68, 0xA8, 0x68, 0xAA, 0x68, 0x40]); // PLA, TAY, PLA, TAX, PLA, RTI
+            }
+        }
+
+        code
+    }
+
+
+    // BASIC RSID: set up the program pointers like LOAD does and type RUN - the song number goes to $030C,
+    // where SYS picks up the accumulator
+    fn run_basic(&self, machine: &mut machine::Machine, song: u16) {
+        let end = self.end_address() + 1;
+        let ram = machine.bus.memory.get_ram_bank(memory::MemType::Ram);
+
+        for &pointer in [0x2D, 0x2F, 0x31].iter() {
+            ram.write(pointer, lo(end));
+            ram.write(pointer + 1, hi(end));
+        }
+        ram.write(0x030C, (song - 1) as u8);
+
+        for (i, &key) in b"RUN\r".iter().enumerate() {
+            ram.write(0x0277 + i as u16, key);
+        }
+        ram.write(0x00C6, 4);
+    }
+}
+
+
+fn read_word_be(data: &[u8], offset: usize) -> u16 {
+    ((data[offset] as u16) << 8) | data[offset + 1] as u16
+}
+
+
+// zero padded ISO-8859-1 text
+fn read_string(data: &[u8]) -> String {
+    data.iter().take_while(|&&byte| byte != 0).map(|&byte| byte as char).collect()
+}
+
+
+fn lo(word: u16) -> u8 {
+    (word & 0xFF) as u8
+}
+
+
+fn hi(word: u16) -> u8 {
+    (word >> 8) as u8
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // header with the given version, load address and data - one song, init at the load address
+    fn file(magic: &[u8], version: u16, load_address: u16, data: &[u8]) -> Vec<u8> {
+        let header_len = if version == 1 { V1_HEADER_LEN } else { V2_HEADER_LEN };
+        let mut file = vec![0u8; header_len];
+        file[..4].copy_from_slice(magic);
+        file[0x05] = version as u8;
+        file[0x07] = header_len as u8;
+        file[0x08] = hi(load_address);
+        file[0x09] = lo(load_address);
+        file[0x0F] = 1;
+        file[0x11] = 1;
+        file.extend_from_slice(data);
+        file
+    }
+
+    #[test]
+    fn v1_header_has_no_flags() {
+        let mut data = file(b"PSID", 1, 0x1000, &[0x60]);
+        data[0x16..0x1A].copy_from_slice(b"TUNE");
+
+        let psid = Psid::from_bytes(&data).unwrap();
+        assert_eq!(psid.version, 1);
+        assert_eq!(psid.name, "TUNE");
+        assert_eq!(psid.init_address, 0x1000);
+        assert_eq!(psid.vic_model(), None);
+        assert!(psid.sid_model(0).is_none());
+        assert!(psid.extra_sids().is_empty());
+    }
+
+    #[test]
+    fn v2_header_selects_machine() {
+        let mut data = file(b"PSID", 2, 0x1000, &[0x60]);
+        data[0x77] = 0x24; // PAL, 8580
+
+        let psid = Psid::from_bytes(&data).unwrap();
+        assert_eq!(psid.vic_model(), Some(VICModel::MOS6569));
+        assert!(psid.sid_model(0) == Some(SIDModel::MOS8580));
+        assert!(psid.sid_model(1) == Some(SIDModel::MOS8580));
+    }
+
+    #[test]
+    fn zero_load_address_is_taken_from_the_data() {
+        let psid = Psid::from_bytes(&file(b"PSID", 2, 0, &[0x00, 0x20, 0xEA, 0x60])).unwrap();
+        assert_eq!(psid.load_address, 0x2000);
+        assert_eq!(psid.init_address, 0x2000);
+        assert_eq!(psid.end_address(), 0x2001);
+
+        assert!(Psid::from_bytes(&file(b"PSID", 2, 0, &[0x00])).is_err());
+    }
+
+    #[test]
+    fn invalid_rsid_headers_are_refused() {
+        assert!(Psid::from_bytes(&file(b"RSID", 2, 0x0801, &[0x60])).is_ok());
+        assert!(Psid::from_bytes(&file(b"RSID", 1, 0x0801, &[0x60])).is_err());
+        assert!(Psid::from_bytes(&file(b"RSID", 2, 0x0400, &[0x60])).is_err());
+
+        let mut data = file(b"RSID", 2, 0x0801, &[0x60]);
+        data[0x0C] = 0x10; // play address
+        assert!(Psid::from_bytes(&data).is_err());
+    }
+
+    #[test]
+    fn extra_sids_stop_at_the_first_invalid_address() {
+        let mut data = file(b"PSID", 4, 0x1000, &[0x60]);
+        data[0x7A] = 0x42;
+        data[0x7B] = 0xE0;
+        assert_eq!(Psid::from_bytes(&data).unwrap().extra_sids(), vec![0xD420, 0xDE00]);
+
+        // odd, in the SID/VIC area or at $D800-$DDFF
+        for &byte in [0x43, 0x40, 0x80, 0xD0].iter() {
+            data[0x7A] = byte;
+            assert!(Psid::from_bytes(&data).unwrap().extra_sids().is_empty());
+        }
+
+        // the third SID is only given in v4 headers
+        let mut data = file(b"PSID", 3, 0x1000, &[0x60]);
+        data[0x7A] = 0x42;
+        data[0x7B] = 0xE0;
+        assert_eq!(Psid::from_bytes(&data).unwrap().extra_sids(), vec![0xD420]);
+    }
+
+    #[test]
+    fn driver_needs_a_free_page() {
+        let psid = Psid::from_bytes(&file(b"PSID", 2, 0x1000, &[0x60])).unwrap();
+        assert_eq!(psid.driver_address(), Ok(DRIVER_ADDRESS));
+
+        let psid = Psid::from_bytes(&file(b"PSID", 2, 0x0300, &[0; 0x200])).unwrap();
+        assert_eq!(psid.driver_address(), Ok(0x0500));
+
+        let psid = Psid::from_bytes(&file(b"PSID", 2, 0x0300, &vec![0; 0xCD00])).unwrap();
+        assert!(psid.driver_address().is_err());
+    }
+}
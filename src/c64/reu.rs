@@ -0,0 +1,261 @@
+// RAM Expansion Unit (1700/1764/1750 and larger clones)
+// registers at $DF00-$DF0A (mirrored every 32 bytes), transfers performed through DMA
+use c64::expansion::{DmaBus, ExpansionPort};
+
+pub const VALID_SIZES_KB: [usize; 8] = [128, 256, 512, 1024, 2048, 4096, 8192, 16384];
+
+// status register bits
+const STATUS_IRQ:          u8 = 0x80;
+const STATUS_END_OF_BLOCK: u8 = 0x40;
+const STATUS_VERIFY_ERROR: u8 = 0x20;
+const STATUS_256K_CHIPS:   u8 = 0x10;
+
+// command register bits
+const CMD_EXECUTE:     u8 = 0x80;
+const CMD_AUTOLOAD:    u8 = 0x20;
+const CMD_FF00_DISABLE: u8 = 0x10;
+
+
+pub struct REU {
+    ram: Vec<u8>,
+    status:    u8,
+    command:   u8,
+    c64_addr:  u16,
+    reu_addr:  u32, // 24 bit address: bank and offset
+    bank_mask: u8,  // bank register bits backed by the REU - 3 on the 17xx units, more on larger clones
+    length:    u16, // transfer length - 0 means 64k
+    irq_mask:  u8,
+    addr_ctrl: u8,  // bit 7: fix C64 address, bit 6: fix REU address
+
+    // register values restored after a transfer in autoload mode
+    c64_addr_latch: u16,
+    reu_addr_latch: u32,
+    length_latch:   u16,
+
+    transfer_active: bool,
+}
+
+impl REU {
+    pub fn new(size_kb: usize) -> Result<REU, String> {
+        if !VALID_SIZES_KB.contains(&size_kb) {
+            return Err(format!("Invalid REU size: {}K (valid sizes: 128, 256, 512 ... 16384)", size_kb));
+        }
+
+        let mut reu = REU {
+            ram: vec![0; size_kb * 1024],
+            status:    0,
+            command:   0,
+            c64_addr:  0,
+            reu_addr:  0,
+            bank_mask: ((size_kb / 64) - 1) as u8 | 0x07,
+            length:    0,
+            irq_mask:  0,
+            addr_ctrl: 0,
+            c64_addr_latch: 0,
+            reu_addr_latch: 0,
+            length_latch:   0,
+            transfer_active: false,
+        };
+
+        reu.reset();
+        Ok(reu)
+    }
+
+
+    // *** private functions *** //
+
+    fn start_transfer(&mut self) {
+        self.c64_addr_latch = self.c64_addr;
+        self.reu_addr_latch = self.reu_addr;
+        self.length_latch   = self.length;
+        self.transfer_active = true;
+    }
+
+
+    fn end_transfer(&mut self) {
+        self.transfer_active = false;
+        self.status |= STATUS_END_OF_BLOCK;
+        self.command = (self.command & !CMD_EXECUTE) | CMD_FF00_DISABLE;
+
+        if (self.command & CMD_AUTOLOAD) != 0 {
+            self.c64_addr = self.c64_addr_latch;
+            self.reu_addr = self.reu_addr_latch;
+            self.length   = self.length_latch;
+        }
+
+        self.update_irq();
+    }
+
+
+    fn update_irq(&mut self) {
+        if (self.irq_mask & 0x80) != 0 && (self.irq_mask & self.status & 0x60) != 0 {
+            self.status |= STATUS_IRQ;
+        }
+    }
+}
+
+impl ExpansionPort for REU {
+    fn name(&self) -> &str {
+        "REU"
+    }
+
+
+    fn read_io(&mut self, addr: u16) -> Option<u8> {
+        match addr {
+            0xDF00..=0xDFFF => {
+                let value = match addr & 0x1F {
+                    0x00 => {
+                        // interrupt, end of block and verify error flags are cleared on read
+                        let status = self.status;
+                        self.status &= !(STATUS_IRQ | STATUS_END_OF_BLOCK | STATUS_VERIFY_ERROR);
+                        status
+                    },
+                    0x01 => self.command,
+                    0x02 => self.c64_addr as u8,
+                    0x03 => (self.c64_addr >> 8) as u8,
+                    0x04 => self.reu_addr as u8,
+                    0x05 => (self.reu_addr >> 8) as u8,
+                    0x06 => (self.reu_addr >> 16) as u8 | !self.bank_mask,
+                    0x07 => self.length as u8,
+                    0x08 => (self.length >> 8) as u8,
+                    0x09 => self.irq_mask | 0x1F,
+                    0x0A => self.addr_ctrl | 0x3F,
+                    _    => 0xFF
+                };
+
+                Some(value)
+            },
+            _ => None
+        }
+    }
+
+
+    fn write_io(&mut self, addr: u16, value: u8) -> bool {
+        match addr {
+            0xDF00..=0xDFFF => {
+                match addr & 0x1F {
+                    0x01 => {
+                        self.command = value;
+                        if (value & (CMD_EXECUTE | CMD_FF00_DISABLE)) == (CMD_EXECUTE | CMD_FF00_DISABLE) {
+                            self.start_transfer();
+                        }
+                    },
+                    0x02 => self.c64_addr = (self.c64_addr & 0xFF00) | value as u16,
+                    0x03 => self.c64_addr = (self.c64_addr & 0x00FF) | ((value as u16) << 8),
+                    0x04 => self.reu_addr = (self.reu_addr & 0xFFFF00) | value as u32,
+                    0x05 => self.reu_addr = (self.reu_addr & 0xFF00FF) | ((value as u32) << 8),
+                    0x06 => self.reu_addr = (self.reu_addr & 0x00FFFF) | (((value & self.bank_mask) as u32) << 16),
+                    0x07 => self.length = (self.length & 0xFF00) | value as u16,
+                    0x08 => self.length = (self.length & 0x00FF) | ((value as u16) << 8),
+                    0x09 => {
+                        self.irq_mask = value & 0xE0;
+                        self.update_irq();
+                    },
+                    0x0A => self.addr_ctrl = value & 0xC0,
+                    _    => ()
+                }
+
+                true
+            },
+            _ => false
+        }
+    }
+
+
+    fn snoop_write(&mut self, addr: u16, _value: u8) {
+        // armed transfer waits for a write to $FF00
+        if addr == 0xFF00 && !self.transfer_active &&
+           (self.command & (CMD_EXECUTE | CMD_FF00_DISABLE)) == CMD_EXECUTE {
+            self.start_transfer();
+        }
+    }
+
+
+    fn irq(&self) -> bool {
+        (self.status & STATUS_IRQ) != 0
+    }
+
+
+    fn dma_active(&self) -> bool {
+        self.transfer_active
+    }
+
+
+    // one byte is transferred per cycle
+    fn dma_cycle(&mut self, bus: &mut dyn DmaBus) {
+        let c64_addr = self.c64_addr;
+        let reu_offset = (self.reu_addr as usize) % self.ram.len();
+
+        match self.command & 0x03 {
+            // stash: C64 -> REU
+            0 => self.ram[reu_offset] = bus.dma_read(c64_addr),
+            // fetch: REU -> C64
+            1 => bus.dma_write(c64_addr, self.ram[reu_offset]),
+            // swap
+            2 => {
+                let c64_byte = bus.dma_read(c64_addr);
+                bus.dma_write(c64_addr, self.ram[reu_offset]);
+                self.ram[reu_offset] = c64_byte;
+            },
+            // verify - stop on first mismatch
+            _ => {
+                if bus.dma_read(c64_addr) != self.ram[reu_offset] {
+                    self.status |= STATUS_VERIFY_ERROR;
+                }
+            }
+        }
+
+        if (self.addr_ctrl & 0x80) == 0 {
+            self.c64_addr = self.c64_addr.wrapping_add(1);
+        }
+
+        if (self.addr_ctrl & 0x40) == 0 {
+            self.reu_addr = (self.reu_addr + 1) & (((self.bank_mask as u32) << 16) | 0xFFFF);
+        }
+
+        if self.length == 1 || (self.status & STATUS_VERIFY_ERROR) != 0 {
+            self.end_transfer();
+        }
+        else {
+            self.length = self.length.wrapping_sub(1);
+        }
+    }
+
+
+    fn reset(&mut self) {
+        // RAM contents survive a reset, only the registers are cleared
+        self.status    = if self.ram.len() > 0x20000 { STATUS_256K_CHIPS } else { 0 };
+        self.command   = CMD_FF00_DISABLE;
+        self.c64_addr  = 0;
+        self.reu_addr  = 0;
+        self.length    = 0xFFFF;
+        self.irq_mask  = 0;
+        self.addr_ctrl = 0;
+        self.transfer_active = false;
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bank_register_shows_the_banks_of_the_configured_size() {
+        let mut reu = REU::new(512).unwrap();
+        reu.write_io(0xDF06, 0xFF);
+        assert_eq!(reu.read_io(0xDF06), Some(0xFF));
+        reu.write_io(0xDF06, 0x05);
+        assert_eq!(reu.read_io(0xDF06), Some(0xFD));
+
+        let mut reu = REU::new(2048).unwrap();
+        reu.write_io(0xDF06, 0x1D);
+        assert_eq!(reu.read_io(0xDF06), Some(0xFD));
+        reu.write_io(0xDF06, 0x25);
+        assert_eq!(reu.read_io(0xDF06), Some(0xE5));
+
+        let mut reu = REU::new(16384).unwrap();
+        reu.write_io(0xDF06, 0xA5);
+        assert_eq!(reu.read_io(0xDF06), Some(0xA5));
+    }
+}
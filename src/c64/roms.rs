@@ -0,0 +1,144 @@
+// system ROM set: BASIC, character generator and kernal images
+// ROMs are looked up in a ROM directory (by default "rom" in the working directory or next to the executable),
+// each of them can be replaced with a custom image (JiffyDOS kernal, localized chargen, SX-64 kernal etc.)
+use std::env;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+const BASIC_SIZE:   usize = 0x2000;
+const CHARGEN_SIZE: usize = 0x1000;
+const KERNAL_SIZE:  usize = 0x2000;
+
+// known ROM versions: (CRC32, description)
+const KNOWN_BASIC: [(u32, &'static str); 1] = [
+    (0xF833D117, "901226-01"),
+];
+
+const KNOWN_CHARGEN: [(u32, &'static str); 2] = [
+    (0xEC4272EE, "901225-01"),
+    (0x1604F6C1, "906143-02 (Japanese)"),
+];
+
+const KNOWN_KERNAL: [(u32, &'static str); 7] = [
+    (0xDCE782FA, "901227-01 (rev. 1)"),
+    (0xA5C687B3, "901227-02 (rev. 2)"),
+    (0xDBE3E7C7, "901227-03 (rev. 3)"),
+    (0x2C5965D4, "251104-04 (SX-64)"),
+    (0x789C8CC5, "901246-01 (4064/Educator 64)"),
+    (0x3A9EF6F1, "906145-02 (Japanese)"),
+    (0x2F79984C, "JiffyDOS 6.01"),
+];
+
+
+// where to find the ROMs - overrides are used instead of the files in the ROM directory
+#[derive(Default)]
+pub struct RomConfig {
+    pub rom_dir: Option<String>,
+    pub basic:   Option<String>,
+    pub chargen: Option<String>,
+    pub kernal:  Option<String>,
+}
+
+
+pub struct RomSet {
+    pub basic:   Vec<u8>,
+    pub chargen: Vec<u8>,
+    pub kernal:  Vec<u8>,
+}
+
+impl RomSet {
+    pub fn load(config: &RomConfig) -> Result<RomSet, String> {
+        let rom_dirs = match config.rom_dir {
+            Some(ref dir) => vec![PathBuf::from(dir)],
+            None => default_rom_dirs(),
+        };
+
+        Ok(RomSet {
+            basic:   load_rom("BASIC", "basic.rom", &config.basic, &rom_dirs, BASIC_SIZE, &KNOWN_BASIC)?,
+            chargen: load_rom("chargen", "chargen.rom", &config.chargen, &rom_dirs, CHARGEN_SIZE, &KNOWN_CHARGEN)?,
+            kernal:  load_rom("kernal", "kernal.rom", &config.kernal, &rom_dirs, KERNAL_SIZE, &KNOWN_KERNAL)?,
+        })
+    }
+
+
+    // empty ROM sockets for machines without system ROMs (MAX Machine)
+    pub fn unpopulated() -> RomSet {
+        RomSet {
+            basic:   vec![0xFF; BASIC_SIZE],
+            chargen: vec![0xFF; CHARGEN_SIZE],
+            kernal:  vec![0xFF; KERNAL_SIZE],
+        }
+    }
+}
+
+
+// *** private functions *** //
+
+fn default_rom_dirs() -> Vec<PathBuf> {
+    let mut dirs = vec![PathBuf::from("rom")];
+
+    if let Some(exe_dir) = env::current_exe().ok().and_then(|exe| exe.parent().map(|dir| dir.to_path_buf())) {
+        dirs.push(exe_dir.join("rom"));
+    }
+
+    dirs
+}
+
+
+fn load_rom(what: &str, default_name: &str, file_override: &Option<String>, rom_dirs: &[PathBuf],
+            size: usize, known: &[(u32, &str)]) -> Result<Vec<u8>, String> {
+    // overrides are tried as given first, then relative to the ROM directory
+    let file_name = file_override.as_ref().map(|s| &s[..]).unwrap_or(default_name);
+    let mut candidates = Vec::new();
+    if file_override.is_some() {
+        candidates.push(PathBuf::from(file_name));
+    }
+    for dir in rom_dirs.iter() {
+        candidates.push(dir.join(file_name));
+    }
+
+    let path = match candidates.iter().find(|path| path.is_file()) {
+        Some(path) => path,
+        None => {
+            let tried: Vec<String> = candidates.iter().map(|path| path.display().to_string()).collect();
+            return Err(format!("Couldn't find {} ROM (tried {}). Copy basic.rom, chargen.rom and kernal.rom \
+                                into a directory and pass it with --rom-dir DIR.", what, tried.join(", ")));
+        }
+    };
+
+    let data = read_file(path).map_err(|e| format!("Couldn't read {} ROM {}: {}", what, path.display(), e))?;
+    if data.len() != size {
+        return Err(format!("{} ROM {} is {} bytes, expected {}", what, path.display(), data.len(), size));
+    }
+
+    let crc = crc32(&data);
+    match known.iter().find(|&&(known_crc, _)| known_crc == crc) {
+        Some(&(_, version)) => println!("Read {}: {} ROM {}", path.display(), what, version),
+        None => println!("Read {}: unknown {} ROM (CRC32 {:08X}), assuming a custom image", path.display(), what, crc),
+    }
+
+    Ok(data)
+}
+
+
+fn read_file(path: &Path) -> Result<Vec<u8>, String> {
+    let mut file = File::open(path).map_err(|e| e.to_string())?;
+    let mut data = Vec::<u8>::new();
+    file.read_to_end(&mut data).map_err(|e| e.to_string())?;
+    Ok(data)
+}
+
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFFFFFFu32;
+
+    for &byte in data.iter() {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if (crc & 1) != 0 { (crc >> 1) ^ 0xEDB88320 } else { crc >> 1 };
+        }
+    }
+
+    !crc
+}
@@ -0,0 +1,146 @@
+// machine clock and event scheduler
+// components that only need to act at a known future cycle (TOD ticks, CIA timer underflows,
+// tape pulses, drive events) schedule an event instead of being polled every cycle
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+
+// cycle counter - 64 bits don't wrap around in any realistic session
+pub type Cycle = u64;
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum Event {
+    TodTick,   // CIA time of day input (mains frequency)
+    Cia1Timer, // CIA1 timer about to underflow - resume clocking it every cycle
+    Cia2Timer, // CIA2 timer about to underflow
+}
+
+
+pub struct Scheduler {
+    now: Cycle,
+    seq: u64, // keeps events scheduled for the same cycle in order
+    events: BinaryHeap<Reverse<(Cycle, u64, Event)>>,
+}
+
+impl Scheduler {
+    pub fn new() -> Scheduler {
+        Scheduler {
+            now: 0,
+            seq: 0,
+            events: BinaryHeap::new(),
+        }
+    }
+
+
+    // current machine cycle
+    pub fn now(&self) -> Cycle {
+        self.now
+    }
+
+
+    // move on to the next cycle
+    pub fn advance(&mut self) {
+        self.now += 1;
+    }
+
+
+    pub fn schedule(&mut self, at: Cycle, event: Event) {
+        self.seq += 1;
+        self.events.push(Reverse((at, self.seq, event)));
+    }
+
+
+    pub fn schedule_in(&mut self, delay: Cycle, event: Event) {
+        let at = self.now + delay;
+        self.schedule(at, event);
+    }
+
+
+    // drop all pending occurrences of an event
+    pub fn cancel(&mut self, event: Event) {
+        let events: Vec<_> = self.events.drain().filter(|&Reverse((_, _, e))| e != event).collect();
+        self.events = events.into_iter().collect();
+    }
+
+
+    // next event due at or before the current cycle
+    pub fn pop_due(&mut self) -> Option<Event> {
+        match self.events.peek() {
+            Some(&Reverse((at, _, _))) if at <= self.now => (),
+            _ => return None
+        }
+
+        self.events.pop().map(|Reverse((_, _, event))| event)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn due(scheduler: &mut Scheduler) -> Vec<Event> {
+        let mut events = Vec::new();
+        while let Some(event) = scheduler.pop_due() {
+            events.push(event);
+        }
+        events
+    }
+
+    #[test]
+    fn events_come_out_in_cycle_order() {
+        let mut scheduler = Scheduler::new();
+        scheduler.schedule(30, Event::TodTick);
+        scheduler.schedule(10, Event::Cia2Timer);
+        scheduler.schedule(20, Event::Cia1Timer);
+
+        for _ in 0..30 {
+            scheduler.advance();
+        }
+
+        assert_eq!(due(&mut scheduler), vec![Event::Cia2Timer, Event::Cia1Timer, Event::TodTick]);
+    }
+
+    #[test]
+    fn events_on_the_same_cycle_keep_scheduling_order() {
+        let mut scheduler = Scheduler::new();
+        scheduler.schedule(5, Event::TodTick);
+        scheduler.schedule(5, Event::Cia2Timer);
+        scheduler.schedule(5, Event::Cia1Timer);
+
+        for _ in 0..5 {
+            scheduler.advance();
+        }
+
+        assert_eq!(due(&mut scheduler), vec![Event::TodTick, Event::Cia2Timer, Event::Cia1Timer]);
+    }
+
+    #[test]
+    fn pop_due_waits_for_the_event_cycle() {
+        let mut scheduler = Scheduler::new();
+        scheduler.advance();
+        scheduler.schedule_in(2, Event::TodTick);
+
+        assert_eq!(scheduler.pop_due(), None);
+        scheduler.advance();
+        assert_eq!(scheduler.pop_due(), None);
+        scheduler.advance();
+        assert_eq!(scheduler.now(), 3);
+        assert_eq!(scheduler.pop_due(), Some(Event::TodTick));
+        assert_eq!(scheduler.pop_due(), None);
+    }
+
+    #[test]
+    fn cancel_drops_every_occurrence_of_an_event() {
+        let mut scheduler = Scheduler::new();
+        scheduler.schedule(1, Event::Cia1Timer);
+        scheduler.schedule(2, Event::TodTick);
+        scheduler.schedule(3, Event::Cia1Timer);
+        scheduler.cancel(Event::Cia1Timer);
+
+        for _ in 0..3 {
+            scheduler.advance();
+        }
+
+        assert_eq!(due(&mut scheduler), vec![Event::TodTick]);
+    }
+}
@@ -1,778 +1,698 @@
-// SID chip
-extern crate rand;
-#[cfg(not(target_os = "redox"))]
-extern crate sdl2;
-
-#[cfg(not(target_os = "redox"))]
-use self::sdl2::audio::{ AudioCallback, AudioSpecDesired };
-use c64::memory;
+// SID chip - clocked along with the rest of the machine, so register writes take effect at the exact cycle
+// the output is computed every cycle and mixed into the audio output by c64::sound
 use c64::sid_tables::*;
-use std::cell::RefCell;
 use std::f32;
-use std::rc::Rc;
-
-pub type SIDShared = Rc<RefCell<SID>>;
-
-const SAMPLE_FREQ: u32 = 44100;  // output frequency
-const SID_FREQ:    u32 = 985248; // SID frequency in Hz
-pub const SID_CYCLES:  u32 = SID_FREQ / SAMPLE_FREQ;  // SID clocks/sample frame
-const NUM_SAMPLES: usize = 624; // size of buffer for sampled voice
-
-
-enum WaveForm {
-    None,
-    Triangle,
-    Saw,
-    TriSaw,
-    Pulse,
-    TriPulse,
-    SawPulse,
-    TriSawPulse,
-    Noise
+
+// voice whose oscillator syncs and ring modulates each voice
+const SYNC_SOURCE: [usize; 3] = [2, 0, 1];
+
+// 8580 digiboost: a constant on the external audio input makes volume register writes audible again
+const DIGIBOOST_INPUT: i32 = -0x3000;
+
+// 6581 filter integrators saturate - the level where they start to distort
+const DISTORTION_6581: f32 = 16384.0;
+
+
+// SID chip revision
+#[derive(Clone, Copy, PartialEq)]
+pub enum SIDModel {
+    MOS6581, // original C64
+    MOS8580, // C64C and later - no DC offset on the output, so $D418 volume writes are (almost) silent
+}
+
+impl SIDModel {
+    pub fn from_name(name: &str) -> Option<SIDModel> {
+        match &name.to_lowercase()[..] {
+            "6581" | "old" => Some(SIDModel::MOS6581),
+            "8580" | "new" => Some(SIDModel::MOS8580),
+            _ => None
+        }
+    }
+
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            SIDModel::MOS6581 => "6581",
+            SIDModel::MOS8580 => "8580",
+        }
+    }
+
+
+    // waveform output level that is silent after the envelope
+    fn wave_zero(&self) -> i32 {
+        match *self {
+            SIDModel::MOS6581 => 0x380,
+            SIDModel::MOS8580 => 0x800,
+        }
+    }
+
+
+    // DC added by each voice regardless of its envelope - the 6581 voices have a large one,
+    // which is why volume register writes on a 6581 are audible
+    fn voice_dc(&self) -> i32 {
+        match *self {
+            SIDModel::MOS6581 => 0x800 * 0xFF,
+            SIDModel::MOS8580 => 0,
+        }
+    }
+
+
+    fn mixer_dc(&self) -> i32 {
+        match *self {
+            SIDModel::MOS6581 => (-0xFFF * 0xFF / 18) >> 7,
+            SIDModel::MOS8580 => 0,
+        }
+    }
+
+
+    // with no waveform selected, the waveform DAC keeps its last input for this many cycles,
+    // then loses one bit every few cycles (measured by the reSID-fp authors)
+    fn floating_output(&self) -> (u32, u32) {
+        match *self {
+            SIDModel::MOS6581 => (54000, 1400),
+            SIDModel::MOS8580 => (800000, 50000),
+        }
+    }
+}
+
+
+// combined waveform lookup tables, indexed by the upper 12 accumulator bits
+struct WaveTables {
+    tables: [Vec<u16>; 4], // tri+saw, pulse+tri, pulse+saw, pulse+tri+saw
+}
+
+impl WaveTables {
+    fn new(model: SIDModel) -> WaveTables {
+        let params = match model {
+            SIDModel::MOS6581 => WAVE_MODEL_6581,
+            SIDModel::MOS8580 => WAVE_MODEL_8580,
+        };
+
+        let build = |waveform: u8, i: usize| (0..0x1000).map(|acc| combined_waveform(waveform, acc, params[i])).collect();
+
+        WaveTables {
+            tables: [build(3, 0), build(5, 1), build(6, 2), build(7, 3)],
+        }
+    }
+
+
+    fn get(&self, waveform: u8, index: u32) -> u16 {
+        let table = match waveform {
+            0x3 => &self.tables[0],
+            0x5 => &self.tables[1],
+            0x6 => &self.tables[2],
+            _   => &self.tables[3],
+        };
+
+        table[index as usize]
+    }
 }
 
+
+#[derive(Clone, Copy, PartialEq)]
 enum VoiceState {
-    Idle,
     Attack,
-    Decay,
+    DecaySustain,
     Release
 }
 
-#[derive(PartialEq)]
-enum FilterType {
-    None,
-    Lowpass,
-    Bandpass,
-    LowBandpass,
-    Highpass,
-    Notch,
-    HighBandpass,
-    All
-}
 
-
-// single SID voice
+// single SID voice: oscillator, waveform generator and envelope
 struct SIDVoice {
-    wave: WaveForm,
-    state: VoiceState,
-    modulator: usize,   // number of voice that modulates this voice
-    modulatee: usize,   // number of voice that this voice modulates
-    wf_cnt: u32,        // waveform counter
-    wf_add: u32,        // value to add to wf_cnt each frame
+    accumulator: u32,    // 24-bit phase accumulator
+    shift_register: u32, // 23-bit noise LFSR
+    msb_rising: bool,    // accumulator MSB went high this cycle - syncs the next voice
     freq: u16,
-    pw_val: u16,        // pulse-width value
-    attack_add: u32,
-    decay_sub: u32,
-    release_sub: u32,
-    sustain_level: u32,
-    level: u32,
-    noise: u32,
+    pw: u16,             // 12-bit pulse width
+    waveform: u8,        // control register bits 4-7
+    last_output: u16,    // waveform DAC input - held while no waveform is selected
+    floating_cycles: u32,
+    state: VoiceState,
+    attack: u8,
+    decay: u8,
+    sustain: u8,
+    release: u8,
+    rate_counter: u16,   // 15-bit envelope prescaler
+    rate_period: u16,
+    exp_counter: u8,     // slows down decay and release at low levels
+    exp_period: u8,
+    envelope: u8,        // envelope level, readable through ENV3
+    hold_zero: bool,     // envelope counter is frozen at zero
     gate: bool,
     ring: bool,
     test: bool,
-    filter: bool,
     sync: bool,
-    mute: bool     // only voice 3 can be muted
 }
 
 impl SIDVoice {
     fn new() -> SIDVoice {
         SIDVoice {
-            wave: WaveForm::None,
-            state: VoiceState::Idle,
-            modulator: 0,
-            modulatee: 0,
-            wf_cnt: 0,
-            wf_add: 0,
+            accumulator: 0,
+            shift_register: 0x7FFFF8,
+            msb_rising: false,
             freq: 0,
-            pw_val: 0,
-            attack_add: EG_TABLE[0],
-            decay_sub: EG_TABLE[0],
-            release_sub: EG_TABLE[0],
-            sustain_level: 0,
-            level: 0,
-            noise: 0,
+            pw: 0,
+            waveform: 0,
+            last_output: 0,
+            floating_cycles: 0,
+            state: VoiceState::Release,
+            attack: 0,
+            decay: 0,
+            sustain: 0,
+            release: 0,
+            rate_counter: 0,
+            rate_period: EG_RATES[0],
+            exp_counter: 0,
+            exp_period: 1,
+            envelope: 0,
+            hold_zero: true,
             gate: false,
             ring: false,
             test: false,
-            filter: false,
             sync: false,
-            mute: false
         }
     }
 
 
     fn reset(&mut self) {
-        self.wave  = WaveForm::None;
-        self.state = VoiceState::Idle;
-        self.wf_cnt = 0;
-        self.wf_add = 0;
-        self.freq   = 0;
-        self.pw_val = 0;
-        self.attack_add  = EG_TABLE[0];
-        self.decay_sub   = EG_TABLE[0];
-        self.release_sub = EG_TABLE[0];
-        self.sustain_level = 0;
-        self.level = 0;
-        self.noise = 0;
-        self.gate = false;
-        self.ring = false;
-        self.test = false;
-        self.filter = false;
-        self.sync = false;
-        self.mute = false;
+        *self = SIDVoice::new();
     }
-}
 
 
-// the SID chip with associated SDL2 audio device
-pub struct SID {
-    mem_ref: Option<memory::MemShared>,
-    #[cfg(not(target_os = "redox"))]
-    audio_device: sdl2::audio::AudioDevice<SIDAudioDevice>,
-}
+    fn clock_oscillator(&mut self) {
+        // test bit holds the accumulator at zero
+        if self.test {
+            self.msb_rising = false;
+            return;
+        }
 
-#[cfg(not(target_os = "redox"))]
-impl SID {
-    pub fn new_shared() -> SIDShared {
-        let sdl_context = sdl2::init().unwrap();
-        let audio_subsystem = sdl_context.audio().unwrap();
-
-        let desired_spec = AudioSpecDesired {
-            freq: Some(44100),
-            channels: Some(1),  // mono
-            samples: Some(512), // default sample size
-        };
+        let prev = self.accumulator;
+        self.accumulator = (self.accumulator + self.freq as u32) & 0xFFFFFF;
+        self.msb_rising = (prev & 0x800000) == 0 && (self.accumulator & 0x800000) != 0;
 
-        Rc::new(RefCell::new(SID {
-            mem_ref: None,
-            audio_device: audio_subsystem.open_playback(None, &desired_spec, |spec| {
-                println!("{:?}", spec);
-                SIDAudioDevice::new()
-                }).unwrap()
-        }))
+        // noise is shifted whenever bit 19 goes high
+        if (prev & 0x080000) == 0 && (self.accumulator & 0x080000) != 0 {
+            let bit0 = ((self.shift_register >> 22) ^ (self.shift_register >> 17)) & 1;
+            self.shift_register = ((self.shift_register << 1) & 0x7FFFFF) | bit0;
+        }
     }
 
 
-    pub fn set_references(&mut self, memref: memory::MemShared) {
-        self.mem_ref = Some(memref);
-    }
+    fn clock_envelope(&mut self) {
+        // the rate counter only resets when it hits the period exactly - if the period was lowered
+        // below the current count, it runs all the way around the 15 bits first (the ADSR delay bug)
+        self.rate_counter += 1;
+        if (self.rate_counter & 0x8000) != 0 {
+            self.rate_counter = (self.rate_counter + 1) & 0x7FFF;
+        }
+        if self.rate_counter != self.rate_period {
+            return;
+        }
+        self.rate_counter = 0;
 
+        // attack is linear, decay and release step through the exponential counter
+        if self.state != VoiceState::Attack {
+            self.exp_counter += 1;
+            if self.exp_counter != self.exp_period {
+                return;
+            }
+        }
+        self.exp_counter = 0;
 
-    pub fn reset(&mut self) {
-        let mut lock = self.audio_device.lock();
-        (*lock).reset();
-    }
+        if self.hold_zero {
+            return;
+        }
 
+        match self.state {
+            VoiceState::Attack => {
+                self.envelope = self.envelope.wrapping_add(1);
+                if self.envelope == 0xFF {
+                    self.state = VoiceState::DecaySustain;
+                    self.rate_period = EG_RATES[self.decay as usize];
+                }
+            },
+            VoiceState::DecaySustain => {
+                if self.envelope != self.sustain * 0x11 {
+                    self.envelope = self.envelope.wrapping_sub(1);
+                }
+            },
+            VoiceState::Release => {
+                self.envelope = self.envelope.wrapping_sub(1);
+            },
+        }
 
-    pub fn update(&mut self) {
-        let mut lock = self.audio_device.lock();
-        (*lock).update();
+        self.exp_period = match self.envelope {
+            0xFF => 1,
+            0x5D => 2,
+            0x36 => 4,
+            0x1A => 8,
+            0x0E => 16,
+            0x06 => 30,
+            0x00 => { self.hold_zero = true; 1 },
+            _    => self.exp_period,
+        };
     }
 
 
-    pub fn read_register(&mut self, addr: u16) -> u8 {
-        let mut rval = 0;
+    // 12-bit waveform output - ring modulation needs the accumulator of the sync source
+    fn output(&self, source_accumulator: u32, waves: &WaveTables) -> u16 {
+        let acc = self.accumulator;
+        let pulse = if self.test || (acc >> 12) as u16 >= self.pw { 0xFFF } else { 0 };
 
-        match addr {
-            0xD419..=0xD41A => {
-                let mut lock = self.audio_device.lock();
-                rval = (*lock).read_register(addr);
-                as_ref!(self.mem_ref).get_ram_bank(memory::MemType::Io).write(addr, rval);
-            },
-            0xD41B..=0xD41C => {
-                let mut lock = self.audio_device.lock();
-                rval = (*lock).read_register(addr);
-                as_ref!(self.mem_ref).get_ram_bank(memory::MemType::Io).write(addr, rval);
+        // ring modulation replaces the triangle MSB
+        let tri_acc = if self.ring { acc ^ (source_accumulator & 0x800000) } else { acc };
+
+        match self.waveform {
+            0x0 => 0,
+            0x1 => {
+                let tri = if (tri_acc & 0x800000) != 0 { !tri_acc } else { tri_acc };
+                ((tri >> 11) & 0xFFF) as u16
             },
-            0xD420..=0xD7FF =>  { rval = self.read_register(0xD400 + (addr % 0x0020)); },
-            _               =>  {
-                as_ref!(self.mem_ref).get_ram_bank(memory::MemType::Io).write(addr, rval);
-            }
+            0x2 => (acc >> 12) as u16,
+            0x3 => waves.get(0x3, tri_acc >> 12),
+            0x4 => pulse,
+            0x5..=0x7 => waves.get(self.waveform, tri_acc >> 12) & pulse,
+            0x8 => self.noise(),
+            _   => 0, // noise combined with other waveforms locks up to zero
         }
-
-        rval
     }
 
 
-    pub fn write_register(&mut self, addr: u16, value: u8) {
-        let mut lock = self.audio_device.lock();
-        (*lock).write_register(addr, value);
-        as_ref!(self.mem_ref).get_ram_bank(memory::MemType::Io).write(addr, value);
-    }
+    // waveform output for this cycle - test bit and waveform 0 tricks used by sample players
+    // rely on the floating DAC input holding the level of the previous waveform
+    fn clock_output(&mut self, source_accumulator: u32, waves: &WaveTables, model: SIDModel) -> u16 {
+        if self.waveform != 0 {
+            self.last_output = self.output(source_accumulator, waves);
+            self.floating_cycles = 0;
+        }
+        else if self.last_output != 0 {
+            let (ttl, fade) = model.floating_output();
+            self.floating_cycles += 1;
 
+            if self.floating_cycles >= ttl && (self.floating_cycles - ttl) % fade == 0 {
+                self.last_output &= self.last_output >> 1;
+            }
+        }
 
-    pub fn update_audio(&mut self) {
-        self.audio_device.resume();
+        self.last_output
     }
-}
 
 
-#[cfg(target_os = "redox")]
-impl SID {
-    pub fn new_shared() -> SIDShared {
-        Rc::new(RefCell::new(SID {
-            mem_ref: None,
-        }))
+    // voice output after the envelope: 12-bit waveform x 8-bit envelope plus the chip's DC
+    fn envelope_output(&self, waveform: u16, model: SIDModel) -> i32 {
+        (waveform as i32 - model.wave_zero()) * self.envelope as i32 + model.voice_dc()
     }
 
 
-    pub fn set_references(&mut self, memref: memory::MemShared) {
-        self.mem_ref = Some(memref);
+    fn noise(&self) -> u16 {
+        let sr = self.shift_register;
+        (((sr & 0x400000) >> 11) | ((sr & 0x100000) >> 10) | ((sr & 0x010000) >> 7) | ((sr & 0x002000) >> 5) |
+         ((sr & 0x000800) >> 4)  | ((sr & 0x000080) >> 1)  | ((sr & 0x000010) << 1) | ((sr & 0x000004) << 2)) as u16
     }
 
 
-    pub fn reset(&mut self) {}
+    fn set_control_register(&mut self, value: u8) {
+        self.waveform = value >> 4;
 
+        let gate_on = (value & 1) != 0;
+        self.sync = (value & 2) != 0;
+        self.ring = (value & 4) != 0;
 
-    pub fn update(&mut self) {}
+        let test_on = (value & 8) != 0;
+        if test_on && !self.test {
+            self.accumulator = 0;
+            self.shift_register = 0x7FFFF8;
+        }
+        self.test = test_on;
 
+        if gate_on != self.gate {
+            if gate_on {
+                self.state = VoiceState::Attack;
+                self.rate_period = EG_RATES[self.attack as usize];
+                self.hold_zero = false;
+            }
+            else {
+                self.state = VoiceState::Release;
+                self.rate_period = EG_RATES[self.release as usize];
+            }
 
-    pub fn read_register(&mut self, addr: u16) -> u8 {
-        0
+            self.gate = gate_on;
+        }
     }
 
 
-    pub fn write_register(&mut self, addr: u16, value: u8) {}
+    fn set_attack_decay(&mut self, value: u8) {
+        self.attack = value >> 4;
+        self.decay  = value & 0x0F;
+
+        match self.state {
+            VoiceState::Attack       => self.rate_period = EG_RATES[self.attack as usize],
+            VoiceState::DecaySustain => self.rate_period = EG_RATES[self.decay as usize],
+            VoiceState::Release      => (),
+        }
+    }
 
 
-    pub fn update_audio(&mut self) {}
+    fn set_sustain_release(&mut self, value: u8) {
+        self.sustain = value >> 4;
+        self.release = value & 0x0F;
+
+        if self.state == VoiceState::Release {
+            self.rate_period = EG_RATES[self.release as usize];
+        }
+    }
 }
 
 
-// SDL2 audio device along with necessary SID parameters
-// this is where the actual SID calculations are being performed
-struct SIDAudioDevice {
-    last_sid_byte: u8,  // last byte read by the SID
-    volume: u8,
-    filter_type: FilterType,
-    filter_freq: u8,
-    filter_resonance: u8,
-
-    // IIR filter
-    iir_att: f32,
-    d1:  f32,
-    d2:  f32,
-    g1:  f32,
-    g2:  f32,
-    xn1: f32,
-    xn2: f32,
-    yn1: f32,
-    yn2: f32,
-
-    voices: Vec<SIDVoice>,
-    sample_buffer: [u8; NUM_SAMPLES],
-    sample_idx: usize
+// state variable filter, clocked every cycle
+struct Filter {
+    cutoff: u16,     // 11-bit cutoff register
+    resonance: u8,
+    routing: u8,     // voices (and external input) going through the filter
+    mode: u8,        // lowpass/bandpass/highpass
+    w0: f32,
+    inv_q: f32,
+    vhp: f32,
+    vbp: f32,
+    vlp: f32,
+    w0_table: Vec<f32>, // w0 for each cutoff register value
+    distortion: bool,
 }
 
-impl SIDAudioDevice {
-    pub fn new() -> SIDAudioDevice {
-        let mut sid_audio_device = SIDAudioDevice {
-            last_sid_byte: 0,
-            voices: vec![SIDVoice::new(), SIDVoice::new(), SIDVoice::new()],
-            volume: 0,
-            filter_type: FilterType::None,
-            filter_freq: 0,
-            filter_resonance: 0,
-            iir_att: 1.0,
-            d1:  0.0,
-            d2:  0.0,
-            g1:  0.0,
-            g2:  0.0,
-            xn1: 0.0,
-            xn2: 0.0,
-            yn1: 0.0,
-            yn2: 0.0,
-            sample_buffer: [0; NUM_SAMPLES],
-            sample_idx: 0
+impl Filter {
+    fn new(model: SIDModel, clock_freq: u32) -> Filter {
+        let points: &[(u16, f32)] = match model {
+            SIDModel::MOS6581 => &F0_POINTS_6581,
+            SIDModel::MOS8580 => &F0_POINTS_8580,
         };
 
-        // calculate triangle table values
-        unsafe {
-            for i in 0..0x1000 {
-                let val = ((i << 4) | (i >> 8)) as u16;
-                TRI_TABLE[i] = val;
-                TRI_TABLE[0x1FFF - i] = val;
-            }
-        }
+        let w0_table = (0..0x800).map(|fc| {
+            let freq = interpolate(points, fc as u16);
+            // keep the filter stable at the highest cutoff frequencies
+            (2.0 * f32::consts::PI * freq / clock_freq as f32).min(0.9)
+        }).collect();
+
+        let mut filter = Filter {
+            cutoff: 0,
+            resonance: 0,
+            routing: 0,
+            mode: 0,
+            w0: 0.0,
+            inv_q: 0.0,
+            vhp: 0.0,
+            vbp: 0.0,
+            vlp: 0.0,
+            w0_table: w0_table,
+            distortion: model == SIDModel::MOS6581,
+        };
+
+        filter.calculate();
+        filter
+    }
 
-        sid_audio_device.voices[0].modulator = 2;
-        sid_audio_device.voices[0].modulatee = 1;
-        sid_audio_device.voices[1].modulator = 0;
-        sid_audio_device.voices[1].modulatee = 2;
-        sid_audio_device.voices[2].modulator = 1;
-        sid_audio_device.voices[2].modulatee = 0;
 
-        sid_audio_device
+    fn reset(&mut self) {
+        self.cutoff = 0;
+        self.resonance = 0;
+        self.routing = 0;
+        self.mode = 0;
+        self.vhp = 0.0;
+        self.vbp = 0.0;
+        self.vlp = 0.0;
+        self.calculate();
     }
 
 
-    pub fn reset(&mut self) {
-        self.last_sid_byte = 0;
+    fn calculate(&mut self) {
+        self.w0 = self.w0_table[self.cutoff as usize];
+        self.inv_q = 1.0 / (0.707 + self.resonance as f32 / 15.0);
+    }
 
-        for i in 0..self.voices.len() {
-            self.voices[i].reset();
-        }
 
-        self.volume = 0;
-        self.filter_type = FilterType::None;
-        self.filter_freq = 0;
-        self.filter_resonance = 0;
-        self.iir_att = 1.0;
-        self.d1 = 0.0;
-        self.d2 = 0.0;
-        self.g1 = 0.0;
-        self.g2 = 0.0;
-        self.xn1 = 0.0;
-        self.xn2 = 0.0;
-        self.yn1 = 0.0;
-        self.yn2 = 0.0;
-        self.sample_idx = 0;
-        self.calculate_filter();
-
-        for i in 0..NUM_SAMPLES {
-            self.sample_buffer[i] = 0;
-        }
-    }
-
-
-    pub fn update(&mut self) {
-        let idx = self.sample_idx;
-        self.sample_buffer[idx] = self.volume;
-        self.sample_idx = (self.sample_idx + 1) % NUM_SAMPLES;
-    }
-
-
-    pub fn read_register(&mut self, addr: u16) -> u8 {
-        // most SID registers are write-only. The write to IO RAM is performed
-        // so that the debugger can print out the value fetched by the CPU
-        match addr {
-            0xD419..=0xD41A => {
-                self.last_sid_byte = 0;
-                let rval = 0xFF;
-                rval
-            },
-            0xD41B..=0xD41C => {
-                self.last_sid_byte = 0;
-                let rval = rand::random::<u8>();
-                rval
-            },
-            0xD420..=0xD7FF => self.read_register(0xD400 + (addr % 0x0020)),
-            _               =>  {
-                let rval = self.last_sid_byte;
-                rval
-            }
+    fn clock(&mut self, input: i32) -> i32 {
+        self.vbp -= self.w0 * self.vhp;
+        self.vlp -= self.w0 * self.vbp;
+
+        // 6581: loud signals overdrive the integrators
+        if self.distortion {
+            self.vbp = saturate(self.vbp);
+            self.vlp = saturate(self.vlp);
         }
+
+        self.vhp = self.vbp * self.inv_q - self.vlp - input as f32;
+
+        let mut output = 0.0;
+        if (self.mode & 1) != 0 { output += self.vlp; }
+        if (self.mode & 2) != 0 { output += self.vbp; }
+        if (self.mode & 4) != 0 { output += self.vhp; }
+        output as i32
     }
+}
 
 
-    pub fn write_register(&mut self, addr: u16, value: u8) {
-        self.last_sid_byte = value;
+pub struct SID {
+    model: SIDModel,
+    voices: [SIDVoice; 3],
+    waves: WaveTables,
+    filter: Filter,
+    ext_in: i32,        // external audio input
+    volume: u8,
+    voice3_off: bool,
+    last_sid_byte: u8,  // last byte written to the SID
+    pot_inputs: [u8; 2], // what the POTX/POTY pins are connected to (0xFF: nothing)
+    pots: [u8; 2],       // last POTX/POTY measurement
+    pot_counter: u16,
+}
 
-        match addr {
-            0xD400 => {
-                self.voices[0].freq = (self.voices[0].freq & 0xFF00) | value as u16;
-                self.voices[0].wf_add = SID_CYCLES * self.voices[0].freq as u32;
-            },
-            0xD401 => {
-                self.voices[0].freq = (self.voices[0].freq & 0x00FF) | ((value as u16) << 8);
-                self.voices[0].wf_add = SID_CYCLES * self.voices[0].freq as u32;
-            },
-            0xD402 => {
-                self.voices[0].pw_val = (self.voices[0].pw_val & 0x0F00) | value as u16;
-            },
-            0xD403 => {
-                self.voices[0].pw_val = (self.voices[0].pw_val & 0x00FF) | (((value as u16) & 0x000F) << 8);
-            },
-            0xD404 => {
-                self.set_control_register(0, value);
-            },
-            0xD405 => {
-                self.voices[0].attack_add = EG_TABLE[ (value >> 4) as usize ];
-                self.voices[0].decay_sub  = EG_TABLE[ (value & 0x0F) as usize ];
-            },
-            0xD406 => {
-                self.voices[0].sustain_level = 0x111111 * (value >> 4) as u32;
-                self.voices[0].release_sub   = EG_TABLE[ (value & 0x0F) as usize ];
-            },
-            0xD407 => {
-                self.voices[1].freq = (self.voices[1].freq & 0xFF00) | value as u16;
-                self.voices[1].wf_add = SID_CYCLES * self.voices[1].freq as u32;
-            },
-            0xD408 => {
-                self.voices[1].freq = (self.voices[1].freq & 0x00FF) | ((value as u16) << 8);
-                self.voices[1].wf_add = SID_CYCLES * self.voices[1].freq as u32;
-            },
-            0xD409 => {
-                self.voices[1].pw_val = (self.voices[1].pw_val & 0x0F00) | value as u16;
-            },
-            0xD40A => {
-                self.voices[1].pw_val = (self.voices[1].pw_val & 0x00FF) | (((value as u16) & 0x000F) << 8);
-            },
-            0xD40B => {
-                self.set_control_register(1, value);
-            },
-            0xD40C => {
-                self.voices[1].attack_add = EG_TABLE[ (value >> 4) as usize ];
-                self.voices[1].decay_sub  = EG_TABLE[ (value & 0x0F) as usize ];
-            },
-            0xD40D => {
-                self.voices[1].sustain_level = 0x111111 * (value >> 4) as u32;
-                self.voices[1].release_sub   = EG_TABLE[ (value & 0x0F) as usize ];
-            },
-            0xD40E => {
-                self.voices[2].freq = (self.voices[2].freq & 0xFF00) | value as u16;
-                self.voices[2].wf_add = SID_CYCLES * self.voices[2].freq as u32;
-            },
-            0xD40F => {
-                self.voices[2].freq = (self.voices[2].freq & 0x00FF) | ((value as u16) << 8);
-                self.voices[2].wf_add = SID_CYCLES * self.voices[2].freq as u32;
-            },
-            0xD410 => {
-                self.voices[2].pw_val = (self.voices[2].pw_val & 0x0F00) | value as u16;
-            },
-            0xD411 => {
-                self.voices[2].pw_val = (self.voices[2].pw_val & 0x00FF) | (((value as u16) & 0x000F) << 8);
-            },
-            0xD412 => {
-                self.set_control_register(2, value);
-            },
-            0xD413 => {
-                self.voices[2].attack_add = EG_TABLE[ (value >> 4) as usize ];
-                self.voices[2].decay_sub  = EG_TABLE[ (value & 0x0F) as usize ];
-            },
-            0xD414 => {
-                self.voices[2].sustain_level = 0x111111 * (value >> 4) as u32;
-                self.voices[2].release_sub   = EG_TABLE[ (value & 0x0F) as usize ];
-            },
-            0xD416 => {
-                if self.filter_freq != value {
-                    self.filter_freq = value;
-                    self.calculate_filter();
-                }
-            },
-            0xD417 => {
-                self.voices[0].filter = (value & 1) != 0;
-                self.voices[1].filter = (value & 2) != 0;
-                self.voices[2].filter = (value & 4) != 0;
-
-                if self.filter_resonance != (value >> 4) {
-                    self.filter_resonance = value >> 4;
-                    self.calculate_filter();
-                }
-            },
-            0xD418 => {
-                self.volume = value & 0x0F;
-                self.voices[2].mute = (value & 0x80) != 0;
-                let f_type = match (value >> 4) & 7 {
-                    0 => FilterType::None,
-                    1 => FilterType::Lowpass,
-                    2 => FilterType::Bandpass,
-                    3 => FilterType::LowBandpass,
-                    4 => FilterType::Highpass,
-                    5 => FilterType::Notch,
-                    6 => FilterType::HighBandpass,
-                    7 => FilterType::All,
-                    _ => panic!("Impossible filter combination!"),
-                };
-
-                if self.filter_type != f_type {
-                    self.filter_type = f_type;
-                    self.xn1 = 0.0;
-                    self.xn2 = 0.0;
-                    self.yn1 = 0.0;
-                    self.yn2 = 0.0;
-                    self.calculate_filter();
-                }
-            },
-            // $D41D-$D41F are unusable, so just ignore it
-            0xD420..=0xD7FF => self.write_register(0xD400 + (addr % 0x0020), value),
-            _               => (),
+impl SID {
+    // clock_freq: system clock in Hz - the SID is clocked once per cycle
+    pub fn new(model: SIDModel, clock_freq: u32) -> SID {
+        SID {
+            model: model,
+            voices: [SIDVoice::new(), SIDVoice::new(), SIDVoice::new()],
+            waves: WaveTables::new(model),
+            filter: Filter::new(model, clock_freq),
+            ext_in: 0,
+            volume: 0,
+            voice3_off: false,
+            last_sid_byte: 0,
+            pot_inputs: [0xFF; 2],
+            pots: [0xFF; 2],
+            pot_counter: 0,
         }
     }
 
-    // *** private functions *** //
 
-    fn lowpass_resonance(&self, f: f32) -> f32 {
-        let f2 = f * f;
-        let f3 = f2 * f;
-        let f4 = f3 * f;
-        227.755 - 1.7635 * f - 0.0176385 * f2 + 0.00333484 * f3 - 9.05683E-6 * f4
+    pub fn model(&self) -> SIDModel {
+        self.model
     }
 
 
-    fn highpass_resonance(&self, f: f32) -> f32 {
-        let f2 = f * f;
-        let f3 = f2 * f;
-        366.374 - 14.0052 * f + 0.603212 * f2 - 0.000880196 * f3
+    // 8580 only: make $D418 volume digis audible the way they are on a 6581
+    pub fn set_digiboost(&mut self, digiboost: bool) {
+        self.ext_in = if digiboost && self.model == SIDModel::MOS8580 { DIGIBOOST_INPUT } else { 0 };
     }
 
 
-    fn calculate_filter(&mut self) {
-        let f = self.filter_freq as f32;
-        let resonance: f32;
-        let mut arg: f32;
+    // value the next POTX/POTY measurement will return
+    pub fn set_pot_inputs(&mut self, x: u8, y: u8) {
+        self.pot_inputs = [x, y];
+    }
 
-        match self.filter_type {
-            FilterType::None => {
-                self.d1 = 0.0;
-                self.d2 = 0.0;
-                self.g1 = 0.0;
-                self.g2 = 0.0;
-                self.iir_att = 0.0;
-                return;
-            },
-            FilterType::All => {
-                self.d1 = 0.0;
-                self.d2 = 0.0;
-                self.g1 = 0.0;
-                self.g2 = 0.0;
-                self.iir_att = 1.0;
-                return;
-            }
-            FilterType::Lowpass | FilterType::LowBandpass => {
-               resonance = self.lowpass_resonance(f);
-            },
-            _ => {
-                resonance = self.highpass_resonance(f);
+
+    pub fn reset(&mut self) {
+        for voice in self.voices.iter_mut() {
+            voice.reset();
+        }
+
+        self.filter.reset();
+        self.volume = 0;
+        self.voice3_off = false;
+        self.last_sid_byte = 0;
+        self.pot_counter = 0;
+    }
+
+
+    // emulate a single cycle - returns the chip's audio output
+    pub fn clock(&mut self) -> i32 {
+        // POT inputs are measured in 512 cycle periods: the capacitors are discharged for 256 cycles,
+        // then the SID counts the cycles until they are charged again - the count is latched at the end
+        self.pot_counter = (self.pot_counter + 1) & 0x1FF;
+        if self.pot_counter == 0 {
+            self.pots = self.pot_inputs;
+        }
+
+        for voice in self.voices.iter_mut() {
+            voice.clock_oscillator();
+            voice.clock_envelope();
+        }
+
+        // hard sync: restart the oscillator when its sync source wraps around
+        for i in 0..3 {
+            if self.voices[i].sync && self.voices[SYNC_SOURCE[i]].msb_rising {
+                self.voices[i].accumulator = 0;
             }
         }
 
-        arg = resonance / ((SAMPLE_FREQ >> 1) as f32);
-        if arg > 0.99 { arg = 0.99; }
-        if arg < 0.01 { arg = 0.01; }
+        let mut unfiltered = 0;
+        let mut filtered = 0;
+
+        for i in 0..3 {
+            let source_accumulator = self.voices[SYNC_SOURCE[i]].accumulator;
+            let waveform = self.voices[i].clock_output(source_accumulator, &self.waves, self.model);
+            let output = self.voices[i].envelope_output(waveform, self.model) >> 7;
 
-        self.g2 = 0.55 + 1.2 * arg * arg - 1.2 * arg +  0.0133333333 * self.filter_resonance as f32;
-        self.g1 = -2.0 * self.g2.sqrt() * (f32::consts::PI * arg).cos();
+            if (self.filter.routing & (1 << i)) != 0 {
+                filtered += output;
+            }
+            else if i != 2 || !self.voice3_off {
+                unfiltered += output;
+            }
+        }
 
-        match self.filter_type {
-            FilterType::LowBandpass | FilterType::HighBandpass => self.g2 += 0.1,
-            _ => ()
+        if (self.filter.routing & 0x08) != 0 {
+            filtered += self.ext_in;
+        }
+        else {
+            unfiltered += self.ext_in;
         }
 
-        if self.g1.abs() >= (self.g2 + 1.0) {
-            if self.g1 > 0.0 { self.g1 = self.g2 + 0.99;    }
-            else             { self.g1 = -(self.g2 + 0.99); }
+        (unfiltered + self.filter.clock(filtered) + self.model.mixer_dc()) * self.volume as i32
+    }
+
+
+    // reg: register number (0x00-0x1F), as seen at $D400-$D41F on the first chip
+    pub fn read_register(&mut self, reg: u8) -> u8 {
+        // most SID registers are write-only
+        match reg {
+            0x19 => self.pots[0],
+            0x1A => self.pots[1],
+            0x1B => (self.voices[2].last_output >> 4) as u8,
+            0x1C => self.voices[2].envelope,
+            _ => self.last_sid_byte,
         }
+    }
 
-        match self.filter_type {
-            FilterType::LowBandpass | FilterType::Lowpass => {
-                self.d1 = 2.0;
-                self.d2 = 1.0;
-                self.iir_att = 0.25 * (1.0 + self.g1 + self.g2);
+
+    pub fn write_register(&mut self, reg: u8, value: u8) {
+        self.last_sid_byte = value;
+
+        match reg {
+            0x00..=0x14 => {
+                let voice = &mut self.voices[(reg / 7) as usize];
+
+                match reg % 7 {
+                    0 => voice.freq = (voice.freq & 0xFF00) | value as u16,
+                    1 => voice.freq = (voice.freq & 0x00FF) | ((value as u16) << 8),
+                    2 => voice.pw = (voice.pw & 0x0F00) | value as u16,
+                    3 => voice.pw = (voice.pw & 0x00FF) | (((value as u16) & 0x000F) << 8),
+                    4 => voice.set_control_register(value),
+                    5 => voice.set_attack_decay(value),
+                    _ => voice.set_sustain_release(value),
+                }
             },
-            FilterType::HighBandpass | FilterType::Highpass => {
-                self.d1 = -2.0;
-                self.d2 = 1.0;
-                self.iir_att = 0.25 * (1.0 - self.g1 + self.g2);
+            0x15 => {
+                self.filter.cutoff = (self.filter.cutoff & 0x7F8) | (value & 0x07) as u16;
+                self.filter.calculate();
             },
-            FilterType::Bandpass => {
-                self.d1 = 0.0;
-                self.d2 = -1.0;
-                self.iir_att = 0.25 * (1.0 + self.g1 + self.g2) * (1.0 + (f32::consts::PI * arg).cos()) / (f32::consts::PI * arg).sin();
+            0x16 => {
+                self.filter.cutoff = (self.filter.cutoff & 0x007) | ((value as u16) << 3);
+                self.filter.calculate();
             },
-            FilterType::Notch => {
-                self.d1 = -2.0 * (f32::consts::PI * arg).cos();
-                self.d2 = 1.0;
-                self.iir_att = 0.25 * (1.0 + self.g1 + self.g2) * (1.0 + (f32::consts::PI * arg).cos()) / (f32::consts::PI * arg).sin();
+            0x17 => {
+                self.filter.routing = value & 0x0F;
+                self.filter.resonance = value >> 4;
+                self.filter.calculate();
             },
-            _ => ()
+            0x18 => {
+                self.volume = value & 0x0F;
+                self.filter.mode = (value >> 4) & 0x07;
+                self.voice3_off = (value & 0x80) != 0;
+            },
+            // $D419-$D41F are read-only or unusable, so just ignore it
+            _ => (),
         }
     }
+}
 
 
-    fn set_control_register(&mut self, v_num: usize, value: u8) {
-        self.voices[v_num].wave = match (value >> 4) & 0x0F {
-            0 => WaveForm::None,
-            1 => WaveForm::Triangle,
-            2 => WaveForm::Saw,
-            3 => WaveForm::TriSaw,
-            4 => WaveForm::Pulse,
-            5 => WaveForm::TriPulse,
-            6 => WaveForm::SawPulse,
-            7 => WaveForm::TriSawPulse,
-            8 => WaveForm::Noise,
-            _ => panic!("Impossible waveform value!"),
-        };
-
-        let gate_on = (value & 1) != 0;
-        let sync_on = (value & 2) != 0;
-        let ring_on = (value & 4) != 0;
-        let test_on = (value & 8) != 0;
+// one combined waveform output: the selected waveforms short their output bits together, so each bit
+// ends up somewhere between its own value and the average of its neighbours (and the pulse level)
+fn combined_waveform(waveform: u8, acc: u32, params: (f32, f32, f32, f32)) -> u16 {
+    let (bias, pulse_strength, distance, saw_tri_mix) = params;
+    let mut bits = [0.0f32; 12];
 
-        if gate_on != self.voices[v_num].gate {
-            if gate_on {
-                self.voices[v_num].state = VoiceState::Attack;
-            }
-            else {
-                match self.voices[v_num].state {
-                    VoiceState::Idle => (),
-                    _                => self.voices[v_num].state = VoiceState::Release,
-                }
-            }
-
-            let modulator = self.voices[v_num].modulator;
-            self.voices[v_num].gate = gate_on;
-            self.voices[modulator].sync = sync_on;
-            self.voices[v_num].ring = ring_on;
-            self.voices[v_num].test = test_on;
+    for i in 0..12 {
+        bits[i] = ((acc >> i) & 1) as f32;
+    }
 
-            if test_on {
-                self.voices[v_num].wf_cnt = 0;
-            }
+    // triangle without saw: bits are shifted up and inverted in the falling half
+    if (waveform & 3) == 1 {
+        let top = (acc & 0x800) != 0;
+        for i in (1..12).rev() {
+            bits[i] = if top { 1.0 - bits[i - 1] } else { bits[i - 1] };
         }
+        bits[0] = 0.0;
     }
-}
 
-// SDL2 audio callback implementation - this is where the samples are being converted to output sound
-#[cfg(not(target_os = "redox"))]
-impl AudioCallback for SIDAudioDevice {
-    type Channel = i16;
-
-    fn callback(&mut self, out: &mut [i16]) {
-        let iir_att = self.iir_att;
-        let d1 = self.d1;
-        let d2 = self.d2;
-        let g1 = self.g1;
-        let g2 = self.g2;
-
-        let mut sample_count = (self.sample_idx + NUM_SAMPLES/2) << 16;
-
-        for x in out.iter_mut() {
-            let master_volume: u8 = self.sample_buffer[(sample_count >> 16) % NUM_SAMPLES];
-
-            sample_count += ((50 * NUM_SAMPLES/2) << 16) / SAMPLE_FREQ as usize;
-            let mut total_output: i32 = (SAMPLE_TABLE[master_volume as usize] as i32) << 8;
-            let mut total_output_filter: i32 = 0;
-
-            for i in 0..3 {
-                let envelope: f32;
-
-                match self.voices[i].state
-                {
-                    VoiceState::Attack => {
-                        self.voices[i].level = self.voices[i].level.wrapping_add(self.voices[i].attack_add);
-                        if self.voices[i].level > 0xFFFFFF {
-                            self.voices[i].level = 0xFFFFFF;
-                            self.voices[i].state = VoiceState::Decay;
-                        }
-                    },
-                    VoiceState::Decay => {
-                        if (self.voices[i].level <= self.voices[i].sustain_level) || (self.voices[i].level > 0xFFFFFF) {
-                            self.voices[i].level = self.voices[i].sustain_level;
-                        }
-                        else {
-                            self.voices[i].level = self.voices[i].level.wrapping_sub(self.voices[i].decay_sub >> EGDR_SHIFT[ (self.voices[i].level >> 16) as usize ]);
-                            if (self.voices[i].level <= self.voices[i].sustain_level) || (self.voices[i].level > 0xFFFFFF) {
-                                self.voices[i].level = self.voices[i].sustain_level;
-                            }
-                        }
-                    },
-                    VoiceState::Release => {
-                        self.voices[i].level = self.voices[i].level.wrapping_sub(self.voices[i].release_sub >> EGDR_SHIFT[ (self.voices[i].level >> 16) as usize ]);
-                        if self.voices[i].level > 0xFFFFFF {
-                            self.voices[i].level = 0;
-                            self.voices[i].state = VoiceState::Idle;
-                        }
-                    },
-                    VoiceState::Idle => {
-                        self.voices[i].level = 0;
-                    },
-                }
+    // triangle and saw
+    if (waveform & 3) == 3 {
+        bits[0] *= saw_tri_mix;
+        for i in 1..12 {
+            bits[i] = bits[i - 1] * (1.0 - saw_tri_mix) + bits[i] * saw_tri_mix;
+        }
+    }
 
-                envelope = ((self.voices[i].level as f32) * master_volume as f32) / (0xFFFFFF * 0xF) as f32;
-                let modulatee = self.voices[i].modulatee;
-                let modulator = self.voices[i].modulator;
+    let mut mixed = [0.0f32; 12];
+    for i in 0..12 {
+        let mut sum = 0.0;
+        let mut weights = 0.0;
 
-                if self.voices[i].mute {
-                    continue;
-                }
+        for j in 0..12 {
+            let weight = 1.0 / distance.powi((i as i32 - j as i32).abs());
+            sum += bits[j] * weight;
+            weights += weight;
+        }
 
-                if !self.voices[i].test {
-                    self.voices[i].wf_cnt += self.voices[i].wf_add;
-                }
+        if waveform > 4 {
+            let weight = 1.0 / distance.powi(12 - i as i32);
+            sum += pulse_strength * weight;
+            weights += weight;
+        }
 
-                if self.voices[i].sync && (self.voices[i].wf_cnt > 0x1000000) {
-                    self.voices[modulatee].wf_cnt = 0;
-                }
+        mixed[i] = (bits[i] + sum / weights) * 0.5;
+    }
 
-                self.voices[i].wf_cnt &= 0xFFFFFF;
-
-                let mut output: u16 = 0;
-                match self.voices[i].wave {
-                    WaveForm::Triangle => {
-                        unsafe {
-                            if self.voices[i].ring {
-                                output = TRI_TABLE[((self.voices[i].wf_cnt ^ (self.voices[modulator].wf_cnt & 0x800000)) >> 11) as usize];
-                            }
-                            else {
-                                output = TRI_TABLE[ (self.voices[i].wf_cnt >> 11) as usize ];
-                            }
-                        }
-                    },
-                    WaveForm::Saw => {
-                        output = (self.voices[i].wf_cnt >> 8) as u16;
-                    },
-                    WaveForm::Pulse => {
-                        if self.voices[i].wf_cnt > (self.voices[i].pw_val << 12) as u32 {
-                            output = 0xFFFF;
-                        }
-                    },
-                    WaveForm::TriSaw => {
-                        output = TRI_SAW_TABLE[ (self.voices[i].wf_cnt >> 16) as usize ];
-                    },
-                    WaveForm::TriPulse => {
-                        if self.voices[i].wf_cnt > (self.voices[i].pw_val << 12) as u32 {
-                            output = TRI_RECT_TABLE[ (self.voices[i].wf_cnt >> 16) as usize ];
-                        }
-                    },
-                    WaveForm::SawPulse => {
-                        if self.voices[i].wf_cnt > (self.voices[i].pw_val << 12) as u32 {
-                            output = SAW_RECT_TABLE[ (self.voices[i].wf_cnt >> 16) as usize ];
-                        }
-                    },
-                    WaveForm::TriSawPulse => {
-                        if self.voices[i].wf_cnt > (self.voices[i].pw_val << 12) as u32 {
-                            output = TRI_SAW_RECT_TABLE[ (self.voices[i].wf_cnt >> 16) as usize ];
-                        }
-                    },
-                    WaveForm::Noise => {
-                        if self.voices[i].wf_cnt > 0x100000 {
-                            let rnd_noise = rand::random::<u16>() << 8;
-                            self.voices[i].noise = rnd_noise as u32;
-                            output = rnd_noise;
-                            self.voices[i].wf_cnt &= 0xFFFFF;
-                        }
-                        else {
-                            output = self.voices[i].noise as u16;
-                        }
-                    },
-                    WaveForm::None => {
-                        output = 0x8000;
-                    }
-                }
+    let mut value = 0;
+    for i in 0..12 {
+        if mixed[i] > bias {
+            value |= 1 << i;
+        }
+    }
 
-                if self.voices[i].filter {
-                    total_output_filter += (envelope * ((output >> 3) ^ 0x8000) as f32) as i32;
-                }
-                else {
-                    total_output += (envelope * ((output >> 3) ^ 0x8000) as f32) as i32;
-                }
-            }
+    value
+}
 
-            // take filters into account
-            let xn = (total_output_filter * iir_att as i32) as f32;
-            let yn = xn + d1 * self.xn1 + d2 * self.xn2 - g1 * self.yn1 - g2 * self.yn2;
-            self.yn2 = self.yn1;
-            self.yn1 = yn;
-            self.xn2 = self.xn1;
-            self.xn1 = xn;
-            total_output_filter = yn as i32;
 
-            let sample_value = (((total_output + total_output_filter)) >> 2) as i16;
+// piecewise linear curve through (x, y) points
+fn interpolate(points: &[(u16, f32)], x: u16) -> f32 {
+    for pair in points.windows(2) {
+        let (x0, y0) = pair[0];
+        let (x1, y1) = pair[1];
 
-            // output the sample!
-            *x = sample_value;
+        if x >= x0 && x <= x1 {
+            if x1 == x0 {
+                return y1;
+            }
+            return y0 + (y1 - y0) * (x - x0) as f32 / (x1 - x0) as f32;
         }
     }
+
+    points[points.len() - 1].1
+}
+
+
+// soft limiting - linear for small signals
+fn saturate(x: f32) -> f32 {
+    x * DISTORTION_6581 / (DISTORTION_6581 + x.abs())
 }
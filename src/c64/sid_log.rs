@@ -0,0 +1,92 @@
+// SID register write log for debugging music players
+// CSV: one line per register write with the exact machine cycle, for every SID
+// dump: the 25 registers ($D400-$D418) of the first SID after every frame, as read by SID-to-tracker converters
+use c64::scheduler::Cycle;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+
+pub const DUMP_REGISTERS: usize = 25;
+
+
+pub struct SidLog {
+    csv:  Option<Output>,
+    dump: Option<Output>,
+    frames: u32,
+}
+
+impl SidLog {
+    pub fn create(csv_file: Option<&str>, dump_file: Option<&str>) -> Result<SidLog, String> {
+        let mut log = SidLog {
+            csv:  match csv_file { Some(filename) => Some(Output::create(filename)?), None => None },
+            dump: match dump_file { Some(filename) => Some(Output::create(filename)?), None => None },
+            frames: 0,
+        };
+
+        if let Some(ref mut csv) = log.csv {
+            csv.write(b"cycle,frame,sid,address,value\n");
+        }
+
+        Ok(log)
+    }
+
+
+    // names of the files written
+    pub fn describe(&self) -> String {
+        let names: Vec<&str> = self.csv.iter().chain(self.dump.iter()).map(|output| &output.filename[..]).collect();
+        names.join(", ")
+    }
+
+
+    pub fn log_write(&mut self, cycle: Cycle, sid: usize, addr: u16, value: u8) {
+        if let Some(ref mut csv) = self.csv {
+            let line = format!("{},{},{},{:04X},{:02X}\n", cycle, self.frames, sid, addr, value);
+            csv.write(line.as_bytes());
+        }
+    }
+
+
+    // registers of the first SID at the end of a frame
+    pub fn log_frame(&mut self, registers: &[u8; DUMP_REGISTERS]) {
+        if let Some(ref mut dump) = self.dump {
+            dump.write(registers);
+        }
+
+        self.frames += 1;
+    }
+}
+
+
+impl Drop for SidLog {
+    fn drop(&mut self) {
+        println!("Wrote SID log {}: {} frames", self.describe(), self.frames);
+    }
+}
+
+
+struct Output {
+    filename: String,
+    file: BufWriter<File>,
+    failed: bool, // stop reporting after the first write error
+}
+
+impl Output {
+    fn create(filename: &str) -> Result<Output, String> {
+        let file = File::create(filename).map_err(|e| format!("Couldn't create {}: {}", filename, e))?;
+
+        Ok(Output {
+            filename: String::from(filename),
+            file: BufWriter::new(file),
+            failed: false,
+        })
+    }
+
+
+    fn write(&mut self, data: &[u8]) {
+        if !self.failed {
+            if let Err(e) = self.file.write_all(data) {
+                println!("Error writing {}: {}", self.filename, e);
+                self.failed = true;
+            }
+        }
+    }
+}
@@ -0,0 +1,399 @@
+// SID chips in the I/O area and the stereo audio output they feed
+// the first SID sits at $D400 and is mirrored over $D400-$D7FF, up to two more can be added
+// at any free 32 byte slot in $D420-$D7FF or in the I/O1/I/O2 areas ($DE00/$DF00), like stereo SID cartridges
+use audio;
+use c64::memory;
+use c64::midi;
+use c64::scheduler::Cycle;
+use c64::sid;
+use c64::sid_log;
+use std::f32;
+use std::sync::{Arc, Mutex};
+
+
+pub const MAX_SIDS: usize = 3;
+
+const SAMPLE_FREQ: u32 = audio::SAMPLE_RATE; // output frequency
+const PREBUFFER: usize = 2048;  // frames kept ahead of the sound card (~46ms)
+const BATCH_SIZE: usize = 64;   // frames handed over to the audio output at once
+
+// external output stage: C64 audio output is AC coupled
+const DC_BLOCK_FREQ: f32 = 16.0;
+
+// panning used unless --sid-pan is given: a single SID in the middle, stereo SIDs left and right
+const DEFAULT_PAN: [[i32; MAX_SIDS]; MAX_SIDS] = [
+    [0, 0, 0],
+    [-100, 100, 0],
+    [-100, 100, 0],
+];
+
+
+// one SID chip and where it sits
+struct Slot {
+    sid: sid::SID,
+    address: u16,
+    pan: i32,        // -100 (left) to 100 (right)
+    gain: [f32; 2],  // left, right
+}
+
+
+pub struct Sound {
+    slots: Vec<Slot>,
+    clock_freq: u32,
+    stereo: bool,        // chips are panned differently - otherwise the right channel is a copy of the left
+    digiboost: bool,
+    dc_block: [f32; 2],  // output highpass state
+    dc_block_w0: f32,
+    resamplers: [audio::Resampler; 2],
+    samples: Vec<i16>,   // output not handed to the audio output yet
+    ring: Arc<Mutex<audio::SampleRing>>,
+    sink: Box<dyn audio::AudioSink>,
+    recorder: Option<audio::Recorder>,
+    cycle: Cycle,        // current machine cycle, for the write log
+    registers: [u8; sid_log::DUMP_REGISTERS], // last values written to the first SID
+    log: Option<sid_log::SidLog>,
+    pot_ports: [(u8, u8); 2], // POTX/POTY of the devices in control ports 1 and 2
+    pot_select: u8,           // control ports switched to the first SID's POT pins (bit 0: port 1, bit 1: port 2)
+    midi: Option<midi::MidiExport>,
+}
+
+impl Sound {
+    // clock_freq: system clock in Hz - the SIDs are clocked once per cycle
+    pub fn new(model: sid::SIDModel, clock_freq: u32) -> Sound {
+        let mut sound = Sound {
+            slots: Vec::new(),
+            clock_freq: clock_freq,
+            stereo: false,
+            digiboost: false,
+            dc_block: [0.0; 2],
+            dc_block_w0: 2.0 * f32::consts::PI * DC_BLOCK_FREQ / clock_freq as f32,
+            resamplers: [audio::Resampler::new(clock_freq, SAMPLE_FREQ), audio::Resampler::new(clock_freq, SAMPLE_FREQ)],
+            samples: Vec::with_capacity(BATCH_SIZE * audio::CHANNELS),
+            ring: Arc::new(Mutex::new(audio::SampleRing::new(PREBUFFER))),
+            sink: Box::new(audio::NullSink),
+            recorder: None,
+            cycle: 0,
+            registers: [0; sid_log::DUMP_REGISTERS],
+            log: None,
+            pot_ports: [(0xFF, 0xFF); 2],
+            pot_select: 0x03,
+            midi: None,
+        };
+
+        sound.add_sid(0xD400, model);
+        sound
+    }
+
+
+    // add another chip - the address has to be checked with parse_address first
+    pub fn add_sid(&mut self, address: u16, model: sid::SIDModel) {
+        if self.slots.len() >= MAX_SIDS {
+            return;
+        }
+
+        let mut sid = sid::SID::new(model, self.clock_freq);
+        sid.set_digiboost(self.digiboost);
+
+        self.slots.push(Slot {
+            sid: sid,
+            address: address,
+            pan: 0,
+            gain: [1.0; 2],
+        });
+
+        let pans = DEFAULT_PAN[self.slots.len() - 1];
+        self.set_panning(&pans[..self.slots.len()]);
+    }
+
+
+    // stereo position of each chip in order of addition: -100 (left) to 100 (right)
+    pub fn set_panning(&mut self, pans: &[i32]) {
+        for (slot, &pan) in self.slots.iter_mut().zip(pans.iter()) {
+            slot.pan = pan.max(-100).min(100);
+        }
+
+        // balance law: the far channel fades out, the near one stays at full level
+        for slot in self.slots.iter_mut() {
+            slot.gain = [(100 - slot.pan).min(100) as f32 / 100.0,
+                         (100 + slot.pan).min(100) as f32 / 100.0];
+        }
+
+        // leave headroom when several chips end up on the same channel
+        let total = (0..2).map(|ch| self.slots.iter().map(|slot| slot.gain[ch]).sum::<f32>()).fold(1.0, f32::max);
+        for slot in self.slots.iter_mut() {
+            slot.gain[0] /= total;
+            slot.gain[1] /= total;
+        }
+
+        self.stereo = self.slots.iter().any(|slot| slot.gain[0] != slot.gain[1]);
+    }
+
+
+    // 8580 only: make $D418 volume digis audible the way they are on a 6581
+    pub fn set_digiboost(&mut self, digiboost: bool) {
+        self.digiboost = digiboost;
+
+        for slot in self.slots.iter_mut() {
+            slot.sid.set_digiboost(digiboost);
+        }
+    }
+
+
+    // play the SID output through an audio backend (silent until then)
+    pub fn open_audio(&mut self, backend: &audio::Backend) -> Result<(), String> {
+        self.sink = audio::open(backend, self.ring.clone())?;
+        Ok(())
+    }
+
+
+    // write the output to a WAV file as well - channels: 1 or 2
+    pub fn start_recording(&mut self, filename: &str, channels: u16, sample_rate: u32) -> Result<(), String> {
+        self.recorder = Some(audio::Recorder::create(filename, channels, sample_rate, self.clock_freq)?);
+        println!("Recording audio to {}", filename);
+        Ok(())
+    }
+
+
+    pub fn stop_recording(&mut self) {
+        self.recorder = None;
+    }
+
+
+    pub fn recording(&self) -> Option<&str> {
+        self.recorder.as_ref().map(|recorder| recorder.filename())
+    }
+
+
+    // log register writes to a CSV file and/or per-frame register dumps
+    pub fn start_log(&mut self, csv_file: Option<&str>, dump_file: Option<&str>) -> Result<(), String> {
+        let log = sid_log::SidLog::create(csv_file, dump_file)?;
+        println!("Logging SID writes to {}", log.describe());
+        self.log = Some(log);
+        Ok(())
+    }
+
+
+    pub fn stop_log(&mut self) {
+        self.log = None;
+    }
+
+
+    pub fn logging(&self) -> bool {
+        self.log.is_some()
+    }
+
+
+    // convert what the SIDs play to a MIDI file, written when the export stops
+    pub fn start_midi(&mut self, filename: &str) -> Result<(), String> {
+        self.midi = Some(midi::MidiExport::create(filename, self.clock_freq, self.slots.len(), self.cycle)?);
+        println!("Exporting MIDI to {}", filename);
+        Ok(())
+    }
+
+
+    // the tune starts playing - MIDI time zero moves there
+    pub fn restart_midi(&mut self, cycle: Cycle) {
+        if let Some(ref mut midi) = self.midi {
+            midi.restart(cycle);
+        }
+    }
+
+
+    pub fn stop_midi(&mut self) {
+        self.midi = None;
+    }
+
+
+    // called on VBlank
+    pub fn log_frame(&mut self) {
+        if let Some(ref mut log) = self.log {
+            log.log_frame(&self.registers);
+        }
+
+        if let Some(ref mut midi) = self.midi {
+            midi.advance(self.cycle);
+        }
+    }
+
+
+    // POT values of the device in a control port (0 or 1)
+    pub fn set_pots(&mut self, port: usize, x: u8, y: u8) {
+        self.pot_ports[port] = (x, y);
+        self.update_pots();
+    }
+
+
+    // CIA1 port A bits 6-7 drive the analog switches that connect a control port to the POT pins
+    pub fn select_pots(&mut self, select: u8) {
+        self.pot_select = select;
+        self.update_pots();
+    }
+
+
+    pub fn reset(&mut self) {
+        for slot in self.slots.iter_mut() {
+            slot.sid.reset();
+        }
+
+        self.registers = [0; sid_log::DUMP_REGISTERS];
+
+        self.dc_block = [0.0; 2];
+        for resampler in self.resamplers.iter_mut() {
+            resampler.reset();
+        }
+    }
+
+
+    // emulate a single cycle
+    pub fn clock(&mut self, cycle: Cycle) {
+        self.cycle = cycle;
+        let mut mixed = [0.0f32; 2];
+
+        for slot in self.slots.iter_mut() {
+            let output = slot.sid.clock() as f32;
+            mixed[0] += output * slot.gain[0];
+            mixed[1] += output * slot.gain[1];
+        }
+
+        let channels = if self.stereo { 2 } else { 1 };
+        let mut output = [0i32; 2];
+
+        // AC coupled output
+        for ch in 0..channels {
+            let highpassed = mixed[ch] - self.dc_block[ch];
+            self.dc_block[ch] += self.dc_block_w0 * highpassed;
+            output[ch] = highpassed as i32 >> 4;
+        }
+
+        if !self.stereo {
+            output[1] = output[0];
+        }
+
+        if let Some(ref mut recorder) = self.recorder {
+            recorder.push(output[0], output[1]);
+        }
+
+        let mut frame = [0i16; 2];
+        let mut ready = false;
+
+        for ch in 0..channels {
+            if let Some(sample) = self.resamplers[ch].push(output[ch]) {
+                frame[ch] = sample;
+                ready = true;
+            }
+        }
+
+        if ready {
+            if !self.stereo {
+                frame[1] = frame[0];
+            }
+
+            self.samples.extend_from_slice(&frame);
+
+            if self.samples.len() >= BATCH_SIZE * audio::CHANNELS {
+                self.flush_samples();
+            }
+        }
+    }
+
+
+    // is a chip mapped in the I/O1/I/O2 area at this address?
+    pub fn maps(&self, addr: u16) -> bool {
+        self.slots.iter().skip(1).any(|slot| addr >= slot.address && addr < slot.address + 0x20)
+    }
+
+
+    pub fn read_register(&mut self, mem: &mut memory::Memory, addr: u16) -> u8 {
+        let index = self.slot_at(addr);
+        let reg = ((addr - self.slots[index].address) & 0x1F) as u8;
+        let rval = self.slots[index].sid.read_register(reg);
+
+        // the write to IO RAM is performed so that the debugger can print out the value fetched by the CPU
+        mem.get_ram_bank(memory::MemType::Io).write(addr, rval);
+        rval
+    }
+
+
+    pub fn write_register(&mut self, mem: &mut memory::Memory, addr: u16, value: u8) {
+        mem.get_ram_bank(memory::MemType::Io).write(addr, value);
+
+        let index = self.slot_at(addr);
+        let reg = ((addr - self.slots[index].address) & 0x1F) as u8;
+        self.slots[index].sid.write_register(reg, value);
+
+        if index == 0 && (reg as usize) < sid_log::DUMP_REGISTERS {
+            self.registers[reg as usize] = value;
+        }
+
+        if let Some(ref mut log) = self.log {
+            log.log_write(self.cycle, index, addr, value);
+        }
+
+        if let Some(ref mut midi) = self.midi {
+            midi.write_register(self.cycle, index, reg, value);
+        }
+    }
+
+
+    pub fn update_audio(&mut self) {
+        self.sink.resume();
+    }
+
+
+    // stop audio playback while paused or running in warp mode
+    pub fn pause_audio(&mut self) {
+        self.sink.pause();
+    }
+
+
+    // called once per frame - hands over the rest of the frame's output
+    pub fn advance_audio(&mut self) {
+        self.flush_samples();
+        self.sink.advance();
+
+        if let Some(ref mut recorder) = self.recorder {
+            recorder.flush();
+        }
+    }
+
+
+    // *** private functions *** //
+
+    // chip answering at an address - everything in $D400-$D7FF not taken by another chip mirrors the first one
+    fn slot_at(&self, addr: u16) -> usize {
+        self.slots.iter().skip(1).position(|slot| addr >= slot.address && addr < slot.address + 0x20).map_or(0, |i| i + 1)
+    }
+
+
+    // only the first chip is wired to the control ports - with both ports switched on,
+    // the paddles are in parallel and the lower resistance wins
+    fn update_pots(&mut self) {
+        let mut pots = (0xFF, 0xFF);
+
+        for port in 0..2 {
+            if (self.pot_select & (1 << port)) != 0 {
+                pots.0 = pots.0.min(self.pot_ports[port].0);
+                pots.1 = pots.1.min(self.pot_ports[port].1);
+            }
+        }
+
+        self.slots[0].sid.set_pot_inputs(pots.0, pots.1);
+    }
+
+
+    fn flush_samples(&mut self) {
+        self.ring.lock().unwrap().push(&self.samples);
+        self.samples.clear();
+    }
+}
+
+
+// address of an additional SID: hex, optionally with a $ or 0x prefix (e.g. d420, $de00)
+// valid are 32 byte slots in $D420-$D7E0 and $DE00-$DFE0
+pub fn parse_address(name: &str) -> Option<u16> {
+    let hex = name.trim_start_matches('$').trim_start_matches("0x");
+    let address = u16::from_str_radix(hex, 16).ok()?;
+
+    let in_range = (address >= 0xD420 && address <= 0xD7E0) || (address >= 0xDE00 && address <= 0xDFE0);
+    if in_range && (address & 0x1F) == 0 { Some(address) } else { None }
+}
@@ -1,18 +1,16 @@
 // VIC-II chip
 use c64;
 use c64::memory;
+use c64::bus::Lines;
 use c64::cpu;
+use c64::scheduler::Cycle;
 use c64::vic_tables::*;
-use std::cell::RefCell;
-use std::rc::Rc;
 use utils;
 
-pub type VICShared = Rc<RefCell<VIC>>;
 
 const SKIP_FRAMES:     u16 = 2;
-const NUM_RASTERLINES: u16 = 312;  // number of rasterlines for PAL (0x138)
+const PAL_CYCLES_PER_LINE: u8 = 63; // the cycle sequence in update() follows the PAL line layout
 const FIRST_DISP_LINE: u16 = 0x10;
-const LAST_DISP_LINE:  u16 = 0x11f;
 const ROW25_YSTART: u16 = 0x33;
 const ROW25_YSTOP:  u16 = 0xFB;
 const ROW24_YSTART: u16 = 0x37;
@@ -23,6 +21,126 @@ const FIRST_BADLINE: u16 = 0x30;
 const LAST_BADLINE:  u16 = 0xF7;
 
 
+// VIC-II chip revisions - the crystal next to the VIC also determines the system clock
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum VICModel {
+    MOS6569,     // PAL:  63 cycles x 312 lines
+    MOS6569R1,   // early PAL: no lightpen retrigger, 5 luminance levels
+    MOS6567R8,   // NTSC: 65 cycles x 263 lines
+    MOS6567R56A, // early NTSC: 64 cycles x 262 lines
+}
+
+impl VICModel {
+    pub fn from_name(name: &str) -> Option<VICModel> {
+        match &name.to_lowercase()[..] {
+            "pal"  | "6569" => Some(VICModel::MOS6569),
+            "6569r1" => Some(VICModel::MOS6569R1),
+            "ntsc" | "6567" | "6567r8" => Some(VICModel::MOS6567R8),
+            "oldntsc" | "6567r56a" => Some(VICModel::MOS6567R56A),
+            _ => None
+        }
+    }
+
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            VICModel::MOS6569     => "6569",
+            VICModel::MOS6569R1   => "6569R1",
+            VICModel::MOS6567R8   => "6567R8",
+            VICModel::MOS6567R56A => "6567R56A",
+        }
+    }
+
+
+    pub fn cycles_per_line(&self) -> u8 {
+        match *self {
+            VICModel::MOS6569   | VICModel::MOS6569R1 => 63,
+            VICModel::MOS6567R8   => 65,
+            VICModel::MOS6567R56A => 64,
+        }
+    }
+
+
+    pub fn num_rasterlines(&self) -> u16 {
+        match *self {
+            VICModel::MOS6569   | VICModel::MOS6569R1 => 312,
+            VICModel::MOS6567R8   => 263,
+            VICModel::MOS6567R56A => 262,
+        }
+    }
+
+
+    // system clock in Hz
+    pub fn clock_freq(&self) -> u32 {
+        if self.is_ntsc() { 1022727 } else { 985248 }
+    }
+
+
+    pub fn is_ntsc(&self) -> bool {
+        *self == VICModel::MOS6567R8 || *self == VICModel::MOS6567R56A
+    }
+
+
+    // width/height of a pixel on a TV set - the dot clock differs from the square pixel rate of the TV standard
+    pub fn pixel_aspect(&self) -> f64 {
+        if self.is_ntsc() { 0.75 } else { 0.9365 }
+    }
+
+
+    // lightpen input held low at the start of a frame triggers the lightpen again (not on 6569R1)
+    fn lp_retrigger(&self) -> bool {
+        *self != VICModel::MOS6569R1
+    }
+
+
+    // last rasterline that fits in the main window
+    fn last_disp_line(&self) -> u16 {
+        let last_line = FIRST_DISP_LINE + c64::SCREEN_HEIGHT as u16 - 1;
+        last_line.min(self.num_rasterlines() - 1)
+    }
+
+
+    // maps each cycle of a line (1-based) to the cycle of the PAL line layout handled in update().
+    // NTSC chips have extra idle cycles (mapped to 0) after cycle 57 which delay the fetches of sprites 0-2
+    fn cycle_table(&self) -> Vec<u8> {
+        let extra_cycles = self.cycles_per_line() - PAL_CYCLES_PER_LINE;
+
+        (0..self.cycles_per_line() + 1).map(|c| {
+            match c {
+                0..=57 => c,
+                c if c <= 57 + extra_cycles => 0,
+                c => c - extra_cycles,
+            }
+        }).collect()
+    }
+
+
+    fn palette(&self) -> [u32; 16] {
+        let mut palette = [0; 16];
+
+        for i in 0..16 {
+            palette[i] = utils::fetch_c64_color_rgba(i as u8);
+
+            // 6569R1 shares the chroma of later revisions, but has fewer luminance levels
+            if *self == VICModel::MOS6569R1 && LUMA[i] != 0 {
+                let rgb = palette[i];
+                let (r, g, b) = (((rgb >> 16) & 0xFF) as f32, ((rgb >> 8) & 0xFF) as f32, (rgb & 0xFF) as f32);
+                let y = 0.299 * r + 0.587 * g + 0.114 * b;
+                let (u, v) = (b - y, r - y);
+                let y = y * LUMA_R1[i] as f32 / LUMA[i] as f32;
+                let (r, b) = (v + y, u + y);
+                let g = (y - 0.299 * r - 0.114 * b) / 0.587;
+                let clamp = |c: f32| c.max(0.0).min(255.0).round() as u32;
+
+                palette[i] = (clamp(r) << 16) | (clamp(g) << 8) | clamp(b);
+            }
+        }
+
+        palette
+    }
+}
+
+
 pub struct VIC {
     pub window_buffer: Vec<u32>,
     pub last_byte: u8,   // last byte read by VIC
@@ -33,8 +151,9 @@ pub struct VIC {
     pub is_bad_line: bool,
     pub dbg_reg_changed: bool,  // has the VIC register changed? (use in visual debugger)
 
-    mem_ref: Option<memory::MemShared>,
-    cpu_ref: Option<cpu::CPUShared>,
+    model: VICModel,
+    cycle_table: Vec<u8>, // line cycle -> PAL layout cycle
+    palette: [u32; 16],
 
     irq_flag: u8,
     irq_mask: u8,
@@ -58,6 +177,7 @@ pub struct VIC {
     display_mode: u16,   // current display mode
     bad_lines_on: bool,
     lp_triggered: bool,  // lightpen irq triggered
+    lp_input_low: bool,  // lightpen input state
     mc: [u16; 8],        // sprite data counters
     mc_base: [u16; 8],   // sprite data counter bases
     display_state: bool, // true: display state; false: idle state
@@ -91,22 +211,23 @@ pub struct VIC {
     sprite_coll_buffer: [u8; c64::SCREEN_WIDTH],
     sprite_data: [[u8; 4]; 8],      // sprite data read
     sprite_draw_data: [[u8; 4]; 8], // sprite data for drawing
-    first_ba_cycle: u32,
+    first_ba_cycle: Cycle,
 }
 
 impl VIC {
-    pub fn new_shared() -> VICShared {
-        Rc::new(RefCell::new(VIC {
+    pub fn new(model: VICModel) -> VIC {
+        VIC {
             window_buffer: vec![0; c64::SCREEN_WIDTH * c64::SCREEN_HEIGHT],
             last_byte: 0,
-            raster_cnt: NUM_RASTERLINES - 1,
+            raster_cnt: model.num_rasterlines() - 1,
             raster_irq: 0,
             curr_cycle: 1,
             border_on:   false,
             is_bad_line: false,
             dbg_reg_changed: false,
-            mem_ref: None,
-            cpu_ref: None,
+            model: model,
+            cycle_table: model.cycle_table(),
+            palette: model.palette(),
             irq_flag: 0,
             irq_mask: 0,
             matrix_line: [0; 40],
@@ -126,6 +247,7 @@ impl VIC {
             display_mode: 0,
             bad_lines_on: false,
             lp_triggered: false,
+            lp_input_low: false,
             mc: [63; 8],
             mc_base: [0; 8],
             display_state:  false,
@@ -157,17 +279,11 @@ impl VIC {
             sprite_data:      [[0; 4]; 8],
             sprite_draw_data: [[0; 4]; 8],
             first_ba_cycle: 0
-        }))
-    }
-    
-
-    pub fn set_references(&mut self, memref: memory::MemShared, cpuref: cpu::CPUShared) {
-        self.mem_ref = Some(memref);
-        self.cpu_ref = Some(cpuref);
+        }
     }
     
 
-    pub fn read_register(&self, addr: u16) -> u8 {
+    pub fn read_register(&self, mem: &mut memory::Memory, addr: u16) -> u8 {
         match addr {
             0xD000..=0xD00F => {
                 let idx = ((addr & 0x000F) >> 1) as usize;
@@ -179,29 +295,29 @@ impl VIC {
                 }
             },
             0xD011 => {
-                let curr_val = as_ref!(self.mem_ref).get_ram_bank(memory::MemType::Io).read(addr);
+                let curr_val = mem.get_ram_bank(memory::MemType::Io).read(addr);
                 // bit 7 in $d011 is bit 8 of $d012
                 (curr_val & 0x7F) | ((self.raster_cnt & 0x100) >> 1) as u8
             },
             0xD012          => self.raster_cnt as u8,
             0xD019          => self.irq_flag | 0x70,
             0xD01A          => self.irq_mask | 0xF0,
-            0xD040..=0xD3FF => self.read_register(0xD000 + (addr % 0x0040)),
-            _               => as_ref!(self.mem_ref).get_ram_bank(memory::MemType::Io).read(addr)
+            0xD040..=0xD3FF => self.read_register(mem, 0xD000 + (addr % 0x0040)),
+            _               => mem.get_ram_bank(memory::MemType::Io).read(addr)
         }
     }
 
 
     // write to register - ignore callback to CPU
-    pub fn write_register_nc(&mut self, addr: u16, value: u8) {
+    pub fn write_register_nc(&mut self, mem: &mut memory::Memory, addr: u16, value: u8) {
         let mut ca = cpu::Callback::None;
-        self.write_register(addr, value, &mut ca);
+        self.write_register(mem, addr, value, &mut ca);
     }
    
 
     // write to register - perform callback action on CPU
-    pub fn write_register(&mut self, addr: u16, value: u8, on_vic_write: &mut cpu::Callback) {
-        self.dbg_check_regs(addr, value);
+    pub fn write_register(&mut self, mem: &mut memory::Memory, addr: u16, value: u8, on_vic_write: &mut cpu::Callback) {
+        self.dbg_check_regs(mem, addr, value);
         
         match addr {
             0xD000..=0xD00F => {
@@ -209,11 +325,11 @@ impl VIC {
                 
                 if (addr % 2) == 0 {
                     self.mx[idx] = (self.mx[idx] & 0xFF00) | value as u16;
-                    as_mut!(self.mem_ref).get_ram_bank(memory::MemType::Io).write(addr, self.mx[idx] as u8);
+                    mem.get_ram_bank(memory::MemType::Io).write(addr, self.mx[idx] as u8);
                 }
                 else {
                     self.my[idx] = value;
-                    as_mut!(self.mem_ref).get_ram_bank(memory::MemType::Io).write(addr, value);
+                    mem.get_ram_bank(memory::MemType::Io).write(addr, value);
                 }
             },
             0xD010 => {
@@ -230,7 +346,7 @@ impl VIC {
                     j <<= 1;
                 }
                 
-                as_mut!(self.mem_ref).get_ram_bank(memory::MemType::Io).write(addr, value);
+                mem.get_ram_bank(memory::MemType::Io).write(addr, value);
             },
             0xD011 => {
                 self.y_scroll = (value & 7) as u16;
@@ -258,10 +374,10 @@ impl VIC {
                 self.is_bad_line = (self.raster_cnt >= FIRST_BADLINE) &&
                                    (self.raster_cnt <= LAST_BADLINE) &&
                                    ((self.raster_cnt & 7) == self.y_scroll) && self.bad_lines_on;
-                let ctrl2 = self.read_register(0xD016);
+                let ctrl2 = self.read_register(mem, 0xD016);
                 self.display_mode = (((value & 0x60) | (ctrl2 & 0x10)) >> 4) as u16;
                 
-                as_mut!(self.mem_ref).get_ram_bank(memory::MemType::Io).write(addr, value);
+                mem.get_ram_bank(memory::MemType::Io).write(addr, value);
             },
             0xD012 => {
                 let new_raster_irq = (self.raster_irq & 0xFF00) | value as u16;
@@ -271,25 +387,25 @@ impl VIC {
                 }
 
                 self.raster_irq = new_raster_irq;
-                as_mut!(self.mem_ref).get_ram_bank(memory::MemType::Io).write(addr, value);
+                mem.get_ram_bank(memory::MemType::Io).write(addr, value);
             },
             0xD016 => {
-                let ctrl1 = self.read_register(0xD011);
+                let ctrl1 = self.read_register(mem, 0xD011);
                 self.x_scroll = (value & 7) as u16;
                 self.display_mode = (((ctrl1 & 0x60) | (value & 0x10)) >> 4) as u16;
 
-                as_mut!(self.mem_ref).get_ram_bank(memory::MemType::Io).write(addr, value);
+                mem.get_ram_bank(memory::MemType::Io).write(addr, value);
             },
             0xD017 => {
                 self.sprite_y_exp |= !value;
-                as_mut!(self.mem_ref).get_ram_bank(memory::MemType::Io).write(addr, value);
+                mem.get_ram_bank(memory::MemType::Io).write(addr, value);
             },
             0xD018 => {
                 self.matrix_base = ((value & 0xF0) as u16) << 6;
                 self.char_base   = ((value & 0x0E) as u16) << 10;
                 self.bitmap_base = ((value & 0x08) as u16) << 10;
                 
-                as_mut!(self.mem_ref).get_ram_bank(memory::MemType::Io).write(addr, value);
+                mem.get_ram_bank(memory::MemType::Io).write(addr, value);
             },
             0xD019 => {
                 self.irq_flag = self.irq_flag & (!value & 0x0F);
@@ -298,11 +414,10 @@ impl VIC {
                     self.irq_flag |= 0x80;
                 }
                 else {
-                    // normally we'd dereference the cpu directly but in Rust
-                    // it's not possible due to RefCell already being borrowed (call by CPU)
+                    // the CPU IRQ line is cleared by the bus once the write completes
                     *on_vic_write = cpu::Callback::ClearVICIrq;
                 }
-                as_mut!(self.mem_ref).get_ram_bank(memory::MemType::Io).write(addr, value);
+                mem.get_ram_bank(memory::MemType::Io).write(addr, value);
             },
             0xD01A => {
                 self.irq_mask = value & 0x0F;
@@ -316,15 +431,22 @@ impl VIC {
                     *on_vic_write = cpu::Callback::ClearVICIrq;
                 }
 
-                as_mut!(self.mem_ref).get_ram_bank(memory::MemType::Io).write(addr, value);
+                mem.get_ram_bank(memory::MemType::Io).write(addr, value);
             },
-            0xD040..=0xD3FF => { self.write_register(0xD000 + (addr % 0x0040), value, on_vic_write); },
-            _ => as_mut!(self.mem_ref).get_ram_bank(memory::MemType::Io).write(addr, value),
+            0xD040..=0xD3FF => { self.write_register(mem, 0xD000 + (addr % 0x0040), value, on_vic_write); },
+            _ => mem.get_ram_bank(memory::MemType::Io).write(addr, value),
         }
     }
     
 
-    pub fn trigger_lp_irq(&mut self) {
+    // lightpen input changed (CIA1 port B bit 4)
+    pub fn set_lp_input(&mut self, mem: &mut memory::Memory, lines: &mut Lines, low: bool) {
+        self.lp_input_low = low;
+        self.trigger_lp_irq(mem, lines);
+    }
+
+
+    pub fn trigger_lp_irq(&mut self, mem: &mut memory::Memory, lines: &mut Lines) {
         // lightpen triggers only once per frame
         if !self.lp_triggered {
             self.lp_triggered = true;
@@ -335,19 +457,19 @@ impl VIC {
             self.irq_flag |= 0x08;
             if (self.irq_mask & 0x08) != 0 {
                 self.irq_flag |= 0x80;
-                as_mut!(self.cpu_ref).set_vic_irq(true);
+                lines.set_vic_irq(true);
             }
 
-            self.write_register_nc(0xD013, lpx as u8);
-            self.write_register_nc(0xD014, lpy as u8);
+            self.write_register_nc(mem, 0xD013, lpx as u8);
+            self.write_register_nc(mem, 0xD014, lpy as u8);
         }
     }
 
 
-    pub fn on_va_change(&mut self, new_va: u8) {
+    pub fn on_va_change(&mut self, mem: &mut memory::Memory, new_va: u8) {
         self.cia_vabase = (new_va as u16) << 14;
-        let vbase = self.read_register(0xD018);
-        self.write_register_nc(0xD018, vbase);
+        let vbase = self.read_register(mem, 0xD018);
+        self.write_register_nc(mem, 0xD018, vbase);
     }
 
 
@@ -365,15 +487,22 @@ impl VIC {
     }
 
 
-    pub fn read_byte(&mut self, addr: u16) -> u8 {
+    pub fn read_byte(&mut self, mem: &mut memory::Memory, addr: u16) -> u8 {
         let va = addr | self.cia_vabase;
 
-        if (va & 0x7000) == 0x1000 {
+        let ultimax = mem.ultimax;
+
+        if ultimax && (va & 0x3000) == 0x3000 {
+            // in Ultimax mode the VIC sees cartridge ROMH instead of RAM at $3000-$3FFF of each bank
+            self.last_byte = mem.read_romh(0xF000 | (va & 0x0FFF));
+        }
+        else if !ultimax && (va & 0x7000) == 0x1000 {
             let addr = 0xD000 + (va & 0x0FFF);
-            self.last_byte = as_mut!(self.mem_ref).get_rom_bank(memory::MemType::Chargen).read(addr);
+            self.last_byte = mem.get_rom_bank(memory::MemType::Chargen).read(addr);
         }
         else {
-            self.last_byte = as_mut!(self.mem_ref).get_ram_bank(memory::MemType::Ram).read(va);
+            let ram_mask = mem.ram_mask;
+            self.last_byte = mem.get_ram_bank(memory::MemType::Ram).read(va & ram_mask);
         }
 
         self.last_byte
@@ -382,16 +511,19 @@ impl VIC {
 
     // *** main VIC-II loop ***
     // returns true if VBlank is to be triggered
-    pub fn update(&mut self, c64_cycle_cnt: u32, should_trigger_vblank: &mut bool) -> bool {
+    pub fn update(&mut self, mem: &mut memory::Memory, lines: &mut Lines, c64_cycle_cnt: Cycle, should_trigger_vblank: &mut bool) -> bool {
         let mut mask: u8;
         let mut line_finished = false;
         self.dbg_reg_changed = false;
 
-        match self.curr_cycle {
+        let extra_cycles = self.model.cycles_per_line() - PAL_CYCLES_PER_LINE;
+        let cycle = self.cycle_table[self.curr_cycle as usize];
+
+        match cycle {
             // fetch sprite pointer 3, inc raster counter, trigger raster irq,
             // test for bad line, reset BA if sprites 3 and 4 are off, read data of sprite 3
             1 => {
-                if self.raster_cnt == (NUM_RASTERLINES - 1) {
+                if self.raster_cnt == (self.model.num_rasterlines() - 1) {
                     self.trigger_vblank = true;
                 }
                 else {
@@ -399,13 +531,13 @@ impl VIC {
 
                     if self.raster_cnt == self.raster_irq {
                         match self.raster_irq() {
-                            cpu::Callback::TriggerVICIrq => as_mut!(self.cpu_ref).set_vic_irq(true),
+                            cpu::Callback::TriggerVICIrq => lines.set_vic_irq(true),
                             _ => (),
                         }
                     }
                     
                     if self.raster_cnt == 0x30 {
-                        self.bad_lines_on = (self.read_register(0xD011) & 0x10) != 0;
+                        self.bad_lines_on = (self.read_register(mem, 0xD011) & 0x10) != 0;
                     }
 
                     self.is_bad_line = (self.raster_cnt >= FIRST_BADLINE) &&
@@ -414,17 +546,17 @@ impl VIC {
                                         self.bad_lines_on;
 
                     self.draw_this_line = (self.raster_cnt >= FIRST_DISP_LINE) &&
-                                          (self.raster_cnt <= LAST_DISP_LINE ) && !self.frame_skipped;
+                                          (self.raster_cnt <= self.model.last_disp_line()) && !self.frame_skipped;
                 }
 
                 self.border_on_sample[0] = self.border_on;
 
-                self.sprite_ptr_access(3);
-                self.sprite_data_access(3, 0);
+                self.sprite_ptr_access(mem, 3);
+                self.sprite_data_access(mem, 3, 0);
                 self.display_if_bad_line();
 
                 if (self.sprite_dma_on & 0x18) == 0 {
-                    as_ref!(self.cpu_ref).ba_low = false;
+                    lines.ba_low = false;
                 }
             },
             // set BA for sprite 5, read data of sprite 3
@@ -437,6 +569,10 @@ impl VIC {
                     self.lp_triggered = false;
                     self.trigger_vblank = false;
 
+                    if self.lp_input_low && self.model.lp_retrigger() {
+                        self.trigger_lp_irq(mem, lines);
+                    }
+
                     self.skip_cnt -= 1;
                     self.frame_skipped = self.skip_cnt == 0;
 
@@ -451,7 +587,7 @@ impl VIC {
                     
                     if self.raster_irq == 0 {
                         match self.raster_irq() {
-                            cpu::Callback::TriggerVICIrq => as_mut!(self.cpu_ref).set_vic_irq(true),
+                            cpu::Callback::TriggerVICIrq => lines.set_vic_irq(true),
                             _ => (),
                         }
                     }
@@ -461,119 +597,119 @@ impl VIC {
                 self.fg_mask_offset = 0;
                 self.fg_mask_buffer = [0; c64::SCREEN_WIDTH / 8];
                 
-                self.sprite_data_access(3, 1);
-                self.sprite_data_access(3, 2);
+                self.sprite_data_access(mem, 3, 1);
+                self.sprite_data_access(mem, 3, 2);
                 self.display_if_bad_line();
 
                 if (self.sprite_dma_on & 0x20) != 0 {
-                    self.set_ba_low(c64_cycle_cnt);
+                    self.set_ba_low(lines, c64_cycle_cnt);
                 }
             },
             // fetch sprite pointer 4, reset BA if sprite 4 and 5 are off
             3 => {
-                self.sprite_ptr_access(4);
-                self.sprite_data_access(4, 0);
+                self.sprite_ptr_access(mem, 4);
+                self.sprite_data_access(mem, 4, 0);
                 self.display_if_bad_line();
 
                 if (self.sprite_dma_on & 0x30) == 0 {
-                    as_mut!(self.cpu_ref).ba_low = false;
+                    lines.ba_low = false;
                 }
             },
             // set BA for sprite 6, read data of sprite 4
             4 => {
-                self.sprite_data_access(4, 1);
-                self.sprite_data_access(4, 2);
+                self.sprite_data_access(mem, 4, 1);
+                self.sprite_data_access(mem, 4, 2);
                 self.display_if_bad_line();
 
                 if (self.sprite_dma_on & 0x40) != 0 {
-                    self.set_ba_low(c64_cycle_cnt);
+                    self.set_ba_low(lines, c64_cycle_cnt);
                 }
             },
             // fetch sprite pointer 5, reset BA if sprite 5 and 6 are off
             5 => {
-                self.sprite_ptr_access(5);
-                self.sprite_data_access(5, 0);
+                self.sprite_ptr_access(mem, 5);
+                self.sprite_data_access(mem, 5, 0);
                 self.display_if_bad_line();
 
                 if (self.sprite_dma_on & 0x60) == 0 {
-                    as_mut!(self.cpu_ref).ba_low = false;
+                    lines.ba_low = false;
                 }
             },
             // set BA for sprite 7, read data of sprite 5
             6 => {
-                self.sprite_data_access(5, 1);
-                self.sprite_data_access(5, 2);
+                self.sprite_data_access(mem, 5, 1);
+                self.sprite_data_access(mem, 5, 2);
                 self.display_if_bad_line();
 
                 if (self.sprite_dma_on & 0x80) != 0 {
-                    self.set_ba_low(c64_cycle_cnt);
+                    self.set_ba_low(lines, c64_cycle_cnt);
                 }
             },
             // fetch sprite pointer 6, reset BA if sprite 6 and 7 are off
             7 => {
-                self.sprite_ptr_access(6);
-                self.sprite_data_access(6, 0);
+                self.sprite_ptr_access(mem, 6);
+                self.sprite_data_access(mem, 6, 0);
                 self.display_if_bad_line();
 
                 if (self.sprite_dma_on & 0xC0) == 0 {
-                    as_mut!(self.cpu_ref).ba_low = false;
+                    lines.ba_low = false;
                 }
             },
             // read data of sprite 6
             8 => {
-                self.sprite_data_access(6, 1);
-                self.sprite_data_access(6, 2);
+                self.sprite_data_access(mem, 6, 1);
+                self.sprite_data_access(mem, 6, 2);
                 self.display_if_bad_line();
             },
             // fetch sprite pointer 7, reset BA if sprite 7 are off
             9 => {
-                self.sprite_ptr_access(7);
-                self.sprite_data_access(7, 0);
+                self.sprite_ptr_access(mem, 7);
+                self.sprite_data_access(mem, 7, 0);
                 self.display_if_bad_line();
 
                 if (self.sprite_dma_on & 0x80) == 0 {
-                    as_mut!(self.cpu_ref).ba_low = false;
+                    lines.ba_low = false;
                 }
             },
             // read data of sprite 7
             10 => {
-                self.sprite_data_access(7, 1);
-                self.sprite_data_access(7, 2);
+                self.sprite_data_access(mem, 7, 1);
+                self.sprite_data_access(mem, 7, 2);
                 self.display_if_bad_line();
             },
             // refresh, reset BA
             11 => {
-                self.refresh_access();
+                self.refresh_access(mem);
                 self.display_if_bad_line();
-                as_mut!(self.cpu_ref).ba_low = false;
+                lines.ba_low = false;
             },
             // refresh, turn on matrix access if bad line
             12 => {
-                self.refresh_access();
-                self.fetch_if_bad_line(c64_cycle_cnt);
+                self.refresh_access(mem);
+                self.fetch_if_bad_line(lines, c64_cycle_cnt);
             },
             // refresh, turn on matrix access if bad line, reset raster_x, graphics display starts here
             13 => {
-                self.draw_background();
-                self.sample_border();
-                self.refresh_access();
-                self.fetch_if_bad_line(c64_cycle_cnt);
+                self.draw_background(mem);
+                self.sample_border(mem);
+                self.refresh_access(mem);
+                self.fetch_if_bad_line(lines, c64_cycle_cnt);
                 self.raster_x = 0xFFFC;
             },
             // refresh, reset video counter, turn on matrix access and reset row counter if bad line
             14 => {
-                self.draw_background();
-                self.sample_border();
-                self.refresh_access();
-                self.rc_if_bad_line(c64_cycle_cnt);
+                self.draw_background(mem);
+                self.sample_border(mem);
+                self.refresh_access(mem);
+                self.rc_if_bad_line(lines, c64_cycle_cnt);
                 self.video_cnt = self.video_cnt_base;
             },
             // refresh, matrix access, inc mc_base by if if y expansion is set
             15 => {
-                self.draw_background();
-                self.sample_border();
-                self.refresh_access();
-                self.fetch_if_bad_line(c64_cycle_cnt);
+                self.draw_background(mem);
+                self.sample_border(mem);
+                self.refresh_access(mem);
+                self.fetch_if_bad_line(lines, c64_cycle_cnt);
 
                 for i in 0..8 {
                     if (self.sprite_y_exp & (1 << i)) != 0 {
@@ -582,14 +718,14 @@ impl VIC {
                 }
                 
                 self.ml_idx = 0;
-                self.matrix_access(c64_cycle_cnt);
+                self.matrix_access(mem, lines, c64_cycle_cnt);
             },
             // graphics access, matrix access, inc mc_base by 1 if y expansion is set
             16 => {
-                self.draw_background();
-                self.sample_border();
-                self.graphics_access();
-                self.fetch_if_bad_line(c64_cycle_cnt);
+                self.draw_background(mem);
+                self.sample_border(mem);
+                self.graphics_access(mem);
+                self.fetch_if_bad_line(lines, c64_cycle_cnt);
 
                 mask = 1;
 
@@ -605,13 +741,13 @@ impl VIC {
                     mask <<= 1;
                 }
 
-                self.matrix_access(c64_cycle_cnt);
+                self.matrix_access(mem, lines, c64_cycle_cnt);
             },
             // graphics access, matrix access, turn off border in 40 column mode,
             // display window starts here
             17 => {
-                let ctrl1 = self.read_register(0xD011);
-                let ctrl2 = self.read_register(0xD016);
+                let ctrl1 = self.read_register(mem, 0xD011);
+                let ctrl2 = self.read_register(mem, 0xD016);
 
                 if (ctrl2 & 8) != 0 {
                     if self.raster_cnt == self.dy_stop {
@@ -639,17 +775,17 @@ impl VIC {
 
                 self.border_on_sample[1] = self.border_on;
 
-                self.draw_background();
-                self.draw_graphics();
-                self.sample_border();
-                self.graphics_access();
-                self.fetch_if_bad_line(c64_cycle_cnt);
-                self.matrix_access(c64_cycle_cnt);
+                self.draw_background(mem);
+                self.draw_graphics(mem);
+                self.sample_border(mem);
+                self.graphics_access(mem);
+                self.fetch_if_bad_line(lines, c64_cycle_cnt);
+                self.matrix_access(mem, lines, c64_cycle_cnt);
             },
             // turn off border in 38 column mode
             18 => {
-                let ctrl1 = self.read_register(0xD011);
-                let ctrl2 = self.read_register(0xD016);
+                let ctrl1 = self.read_register(mem, 0xD011);
+                let ctrl2 = self.read_register(mem, 0xD016);
 
                 if (ctrl2 & 8) == 0 {
                     if self.raster_cnt == self.dy_stop {
@@ -676,32 +812,32 @@ impl VIC {
                 }
 
                 self.border_on_sample[2] = self.border_on;
-                self.draw_graphics();
-                self.sample_border();
-                self.graphics_access();
-                self.fetch_if_bad_line(c64_cycle_cnt);
-                self.matrix_access(c64_cycle_cnt);
+                self.draw_graphics(mem);
+                self.sample_border(mem);
+                self.graphics_access(mem);
+                self.fetch_if_bad_line(lines, c64_cycle_cnt);
+                self.matrix_access(mem, lines, c64_cycle_cnt);
                 self.last_char_data = self.char_data;
             },
             // graphics and matrix access
             19..=54 => {
-                self.draw_graphics();
-                self.sample_border();
-                self.graphics_access();
-                self.fetch_if_bad_line(c64_cycle_cnt);
-                self.matrix_access(c64_cycle_cnt);
+                self.draw_graphics(mem);
+                self.sample_border(mem);
+                self.graphics_access(mem);
+                self.fetch_if_bad_line(lines, c64_cycle_cnt);
+                self.matrix_access(mem, lines, c64_cycle_cnt);
                 self.last_char_data = self.char_data;
             },
             // lastr graphics access, turn off matrix access,
             // turn on sprite DMA if y cooord is rightr and sprite enabled,
             // handle sprite y expansion, set BA for sprite 0
             55 => {
-                self.draw_graphics();
-                self.sample_border();
-                self.graphics_access();
+                self.draw_graphics(mem);
+                self.sample_border(mem);
+                self.graphics_access(mem);
                 self.display_if_bad_line();
 
-                let mye = self.read_register(0xD017);
+                let mye = self.read_register(mem, 0xD017);
                 
                 mask = 1;
                 for _ in 0..8 {
@@ -712,19 +848,12 @@ impl VIC {
                     mask <<= 1;
                 }
                 
-                self.check_sprite_dma();
-
-                if (self.sprite_dma_on & 0x01) != 0 {
-                    self.set_ba_low(c64_cycle_cnt);
-                }
-                else {
-                    as_mut!(self.cpu_ref).ba_low = false;
-                }
+                self.check_sprite_dma(mem);
             },
             // turn on border in 38 column mode, turn on sprite DMA if Y is right and sprite enabled,
             // set BA for sprite 0, display window ends here
             56 => {
-                let ctrl2 = self.read_register(0xD016);
+                let ctrl2 = self.read_register(mem, 0xD016);
 
                 if (ctrl2 & 8) == 0 {
                     self.border_on = true;
@@ -732,19 +861,15 @@ impl VIC {
 
                 self.border_on_sample[3] = self.border_on;
 
-                self.draw_graphics();
-                self.sample_border();
-                self.idle_access();
+                self.draw_graphics(mem);
+                self.sample_border(mem);
+                self.idle_access(mem);
                 self.display_if_bad_line();
-                self.check_sprite_dma();
-
-                if (self.sprite_dma_on & 0x01) != 0 {
-                    self.set_ba_low(c64_cycle_cnt);
-                }
+                self.check_sprite_dma(mem);
             },
             // turn on border in 40 column mode, set BA for sprite 1, paint sprites
             57 => {
-                let ctrl2 = self.read_register(0xD016);
+                let ctrl2 = self.read_register(mem, 0xD016);
 
                 if (ctrl2 & 8) != 0 {
                     self.border_on = true;
@@ -767,20 +892,16 @@ impl VIC {
                     mask <<= 1;
                 }
 
-                self.draw_background();
-                self.sample_border();
-                self.idle_access();
+                self.draw_background(mem);
+                self.sample_border(mem);
+                self.idle_access(mem);
                 self.display_if_bad_line();
-
-                if (self.sprite_dma_on & 0x02) != 0 {
-                    self.set_ba_low(c64_cycle_cnt);
-                }
             },
             // fetch sprite pointer 0, reset mc, turn on sprite display if needed,
             // turn off display if row_cnt == 7, read data of sprite 0
             58 => {
-                self.draw_background();
-                self.sample_border();
+                self.draw_background(mem);
+                self.sample_border(mem);
 
                 mask = 1;
 
@@ -795,8 +916,8 @@ impl VIC {
                     mask <<= 1;
                 }
 
-                self.sprite_ptr_access(0);
-                self.sprite_data_access(0, 0);
+                self.sprite_ptr_access(mem, 0);
+                self.sprite_data_access(mem, 0, 0);
 
                 if self.row_cnt == 7 {
                     self.video_cnt_base = self.video_cnt;
@@ -810,59 +931,59 @@ impl VIC {
             },
             // set BA for sprite 2, read data of sprite 0
             59 => {
-                self.draw_background();
-                self.sample_border();
-                self.sprite_data_access(0, 1);
-                self.sprite_data_access(0, 2);
+                self.draw_background(mem);
+                self.sample_border(mem);
+                self.sprite_data_access(mem, 0, 1);
+                self.sprite_data_access(mem, 0, 2);
                 self.display_if_bad_line();
 
                 if (self.sprite_dma_on & 0x04) != 0 {
-                    self.set_ba_low(c64_cycle_cnt);
+                    self.set_ba_low(lines, c64_cycle_cnt);
                 }
             },
             // fetch sprite pointer 1, reset BA if sprite 1 and 2 are off
             // graphics display ends here
             60 => {
-                self.draw_background();
-                self.sample_border();
+                self.draw_background(mem);
+                self.sample_border(mem);
 
                 if self.draw_this_line {
                     if self.sprite_draw != 0 {
-                        self.draw_sprites();
+                        self.draw_sprites(mem, lines);
                     }
 
                     // left border01
                     if self.border_on_sample[0] {
                         for i in 0..4 {
-                            let color_rgba = utils::fetch_c64_color_rgba(self.border_color_sample[i]);
+                            let color_rgba = self.color_rgba(self.border_color_sample[i]);
                             utils::memset8(&mut self.window_buffer, self.line_start_offset + i*8 as usize, color_rgba);
                         }
                     }
 
                     // top and bottom - first 8 pixels
                     if self.border_on_sample[1] {
-                        let color_rgba = utils::fetch_c64_color_rgba(self.border_color_sample[4]);
+                        let color_rgba = self.color_rgba(self.border_color_sample[4]);
                         utils::memset8(&mut self.window_buffer, self.line_start_offset + 4*8, color_rgba);
                     }
 
                     // top and bottom
                     if self.border_on_sample[2] {
                         for i in 5..43 {
-                            let color_rgba = utils::fetch_c64_color_rgba(self.border_color_sample[i]);
+                            let color_rgba = self.color_rgba(self.border_color_sample[i]);
                             utils::memset8(&mut self.window_buffer, self.line_start_offset + i*8, color_rgba);
                         }
                     }
 
                     // top and bottom - last 8 pixels
                     if self.border_on_sample[3] {
-                        let color_rgba = utils::fetch_c64_color_rgba(self.border_color_sample[43]);
+                        let color_rgba = self.color_rgba(self.border_color_sample[43]);
                         utils::memset8(&mut self.window_buffer, self.line_start_offset + 43*8, color_rgba);
                     }
 
                     // right border
                     if self.border_on_sample[4] {
                         for i in 44..c64::SCREEN_WIDTH/8 {
-                            let color_rgba = utils::fetch_c64_color_rgba(self.border_color_sample[i]);
+                            let color_rgba = self.color_rgba(self.border_color_sample[i]);
                             utils::memset8(&mut self.window_buffer, self.line_start_offset + i*8, color_rgba);
                         }
                     }
@@ -870,45 +991,45 @@ impl VIC {
                     self.line_start_offset += c64::SCREEN_WIDTH;
                 }
 
-                self.sprite_ptr_access(1);
-                self.sprite_data_access(1, 0);
+                self.sprite_ptr_access(mem, 1);
+                self.sprite_data_access(mem, 1, 0);
                 self.display_if_bad_line();
 
                 if (self.sprite_dma_on & 0x06) == 0 {
-                    as_ref!(self.cpu_ref).ba_low = false;
+                    lines.ba_low = false;
                 }
             },
             // set BA for sprite 3, read data of sprite 1
             61 => {
-                self.sprite_data_access(1, 1);
-                self.sprite_data_access(1, 2);
+                self.sprite_data_access(mem, 1, 1);
+                self.sprite_data_access(mem, 1, 2);
                 self.display_if_bad_line();
 
                 if (self.sprite_dma_on & 0x08) != 0 {
-                    self.set_ba_low(c64_cycle_cnt);
+                    self.set_ba_low(lines, c64_cycle_cnt);
                 }
             },
             // read sprite pointer 2, reset BA if sprite 2 and 3 are off, read data of sprite 2
             62 => {
-                self.sprite_ptr_access(2);
-                self.sprite_data_access(2, 0);
+                self.sprite_ptr_access(mem, 2);
+                self.sprite_data_access(mem, 2, 0);
                 self.display_if_bad_line();
 
                 if (self.sprite_dma_on & 0x0C) == 0 {
-                    as_ref!(self.cpu_ref).ba_low = false;
+                    lines.ba_low = false;
                 }
             },
             // set BA for sprite 4, read data of sprite 2
             63 => {
-                self.sprite_data_access(2, 1);
-                self.sprite_data_access(2, 2);
+                self.sprite_data_access(mem, 2, 1);
+                self.sprite_data_access(mem, 2, 2);
                 self.display_if_bad_line();
 
                 if self.raster_cnt == self.dy_stop {
                     self.ud_border_on = true;
                 }
                 else {
-                    let ctrl1 = self.read_register(0xD011);
+                    let ctrl1 = self.read_register(mem, 0xD011);
 
                     if ((ctrl1 & 0x10) != 0) && (self.raster_cnt == self.dy_start) {
                         self.ud_border_on = false;
@@ -916,14 +1037,30 @@ impl VIC {
                 }
                 
                 if (self.sprite_dma_on & 0x10) != 0 {
-                    self.set_ba_low(c64_cycle_cnt);
+                    self.set_ba_low(lines, c64_cycle_cnt);
                 }
 
                 line_finished = true;
             },
+            // extra NTSC cycles: idle access, nothing is drawn
+            0 => {
+                self.idle_access(mem);
+                self.display_if_bad_line();
+            },
             _ => (),
         }
 
+        // BA for sprites 0 and 1 goes low 3 cycles before their pointer fetch,
+        // end of bad line releases the bus in cycle 55
+        if self.curr_cycle >= 55 && self.curr_cycle <= 57 + extra_cycles {
+            match self.curr_cycle - extra_cycles {
+                55 | 56 if (self.sprite_dma_on & 0x01) != 0 => self.set_ba_low(lines, c64_cycle_cnt),
+                57 if (self.sprite_dma_on & 0x02) != 0 => self.set_ba_low(lines, c64_cycle_cnt),
+                _ if self.curr_cycle == 55 => lines.ba_low = false,
+                _ => ()
+            }
+        }
+
         // next cycle
         self.raster_x = self.raster_x.wrapping_add(0x08);
 
@@ -941,30 +1078,30 @@ impl VIC {
     // *** private functions *** //
 
     // check if register status has changed - used for visual debugger
-    fn dbg_check_regs(&mut self, addr: u16, value: u8) {
-        self.dbg_reg_changed = as_mut!(self.mem_ref).get_ram_bank(memory::MemType::Io).read(addr) != value;
+    fn dbg_check_regs(&mut self, mem: &mut memory::Memory, addr: u16, value: u8) {
+        self.dbg_reg_changed = mem.get_ram_bank(memory::MemType::Io).read(addr) != value;
     }
 
 
-    fn matrix_access(&mut self, c64_cycle_cnt: u32) {
-        if as_ref!(self.cpu_ref).ba_low {
-            if (c64_cycle_cnt - self.first_ba_cycle) < 3 {
+    fn matrix_access(&mut self, mem: &mut memory::Memory, lines: &mut Lines, c64_cycle_cnt: Cycle) {
+        if lines.ba_low {
+            if c64_cycle_cnt.saturating_sub(self.first_ba_cycle) < 3 {
                 self.color_line[self.ml_idx]  = 0xFF;
                 self.matrix_line[self.ml_idx] = 0xFF;
             }
             else {
                 let addr = (self.video_cnt & 0x03FF) | self.matrix_base;
-                self.matrix_line[self.ml_idx] = self.read_byte(addr);
+                self.matrix_line[self.ml_idx] = self.read_byte(mem, addr);
 
                 // assign value from color ram
-                self.color_line[self.ml_idx] = as_ref!(self.mem_ref).get_ram_bank(memory::MemType::Io).read(0xD800 + (addr & 0x03FF));
+                self.color_line[self.ml_idx] = mem.get_ram_bank(memory::MemType::Io).read(0xD800 + (addr & 0x03FF));
             }
         }
     }
 
 
-    fn graphics_access(&mut self) {
-        let ctrl1 = self.read_register(0xD011);
+    fn graphics_access(&mut self, mem: &mut memory::Memory) {
+        let ctrl1 = self.read_register(mem, 0xD011);
         
         if self.display_state {
             let mut addr: u16;
@@ -980,7 +1117,7 @@ impl VIC {
                 addr &= 0xF9FF;
             }
 
-            self.gfx_data = self.read_byte(addr);
+            self.gfx_data = self.read_byte(mem, addr);
             self.char_data = self.matrix_line[self.ml_idx];
             self.color_data = self.color_line[self.ml_idx];
 
@@ -989,14 +1126,14 @@ impl VIC {
         }
         else {
             // display is off
-            self.gfx_data = self.read_byte(if (ctrl1 & 0x40) != 0 { 0x39FF } else { 0x3FFF });
+            self.gfx_data = self.read_byte(mem, if (ctrl1 & 0x40) != 0 { 0x39FF } else { 0x3FFF });
             self.char_data = 0;
             self.color_data = 0;
         }
     }
 
 
-    fn draw_background(&mut self) {
+    fn draw_background(&mut self, mem: &mut memory::Memory) {
         let dst_color: u8;
 
         if !self.draw_this_line {
@@ -1006,7 +1143,7 @@ impl VIC {
         match self.display_mode {
             // standard text, multicolor text, multicolor bitmap
             0 | 1 | 3 => {
-                dst_color = self.read_register(0xD021);
+                dst_color = self.read_register(mem, 0xD021);
             },
             // standard bitmap
             2 => {
@@ -1016,36 +1153,36 @@ impl VIC {
             4 => {
                 if (self.last_char_data & 0x80) != 0 {
                     if (self.last_char_data & 0x40) != 0 {
-                        dst_color = self.read_register(0xD024);
+                        dst_color = self.read_register(mem, 0xD024);
                     }
                     else {
-                        dst_color = self.read_register(0xD023);
+                        dst_color = self.read_register(mem, 0xD023);
                     }
                 }
                 else {
                     if (self.last_char_data & 0x40) != 0 {
-                        dst_color = self.read_register(0xD022);
+                        dst_color = self.read_register(mem, 0xD022);
                     }
                     else {
-                        dst_color = self.read_register(0xD021);
+                        dst_color = self.read_register(mem, 0xD021);
                     }
                 }
             },
             _ => dst_color = 0,
         }
 
-        let color_rgba = utils::fetch_c64_color_rgba(dst_color);
+        let color_rgba = self.color_rgba(dst_color);
         utils::memset8(&mut self.window_buffer, self.screen_chunk_offset, color_rgba);
     }
     
 
-    fn draw_graphics(&mut self) {
+    fn draw_graphics(&mut self, mem: &mut memory::Memory) {
         if !self.draw_this_line {
             return;
         }
         
         if self.ud_border_on {
-            self.draw_background();
+            self.draw_background(mem);
             return;
         }
 
@@ -1053,20 +1190,20 @@ impl VIC {
 
         match self.display_mode {
             0 => { // standard text
-                dst_color[0] = self.read_register(0xD021);
+                dst_color[0] = self.read_register(mem, 0xD021);
                 dst_color[1] = self.color_data;
                 self.draw_std(&dst_color);
             },
             1 => { // multicolor text
                 if (self.color_data & 8) != 0 {
-                    dst_color[0] = self.read_register(0xD021);
-                    dst_color[1] = self.read_register(0xD022);
-                    dst_color[2] = self.read_register(0xD023);
+                    dst_color[0] = self.read_register(mem, 0xD021);
+                    dst_color[1] = self.read_register(mem, 0xD022);
+                    dst_color[2] = self.read_register(mem, 0xD023);
                     dst_color[3] = self.color_data & 7;
                     self.draw_multi(&dst_color);
                 }
                 else {
-                    dst_color[0] = self.read_register(0xD021);
+                    dst_color[0] = self.read_register(mem, 0xD021);
                     dst_color[1] = self.color_data;
                     self.draw_std(&dst_color);
                 }
@@ -1077,7 +1214,7 @@ impl VIC {
                 self.draw_std(&dst_color);
             },
             3 => { // multicolor bitmap
-                dst_color[0] = self.read_register(0xD021);
+                dst_color[0] = self.read_register(mem, 0xD021);
                 dst_color[1] = self.char_data >> 4;
                 dst_color[2] = self.char_data;
                 dst_color[3] = self.color_data;
@@ -1086,18 +1223,18 @@ impl VIC {
             4 => { // ECM text
                 if (self.char_data & 0x80) != 0 {
                     if (self.char_data & 0x40) != 0 {
-                        dst_color[0] = self.read_register(0xD024);
+                        dst_color[0] = self.read_register(mem, 0xD024);
                     }
                     else {
-                        dst_color[0] = self.read_register(0xD023);
+                        dst_color[0] = self.read_register(mem, 0xD023);
                     }
                 }
                 else {
                     if (self.char_data & 0x40) != 0 {
-                        dst_color[0] = self.read_register(0xD022);
+                        dst_color[0] = self.read_register(mem, 0xD022);
                     }
                     else {
-                        dst_color[0] = self.read_register(0xD021);
+                        dst_color[0] = self.read_register(mem, 0xD021);
                     }
                 }
 
@@ -1138,14 +1275,14 @@ impl VIC {
         self.fg_mask_buffer[self.fg_mask_offset + 1 ] |= self.gfx_data << (7 - self.x_scroll);
 
         let mut data = self.gfx_data;
-        self.window_buffer[screen_pos + 7] = utils::fetch_c64_color_rgba(color[(data & 1) as usize]); data >>= 1;
-        self.window_buffer[screen_pos + 6] = utils::fetch_c64_color_rgba(color[(data & 1) as usize]); data >>= 1;
-        self.window_buffer[screen_pos + 5] = utils::fetch_c64_color_rgba(color[(data & 1) as usize]); data >>= 1;
-        self.window_buffer[screen_pos + 4] = utils::fetch_c64_color_rgba(color[(data & 1) as usize]); data >>= 1;
-        self.window_buffer[screen_pos + 3] = utils::fetch_c64_color_rgba(color[(data & 1) as usize]); data >>= 1;
-        self.window_buffer[screen_pos + 2] = utils::fetch_c64_color_rgba(color[(data & 1) as usize]); data >>= 1;
-        self.window_buffer[screen_pos + 1] = utils::fetch_c64_color_rgba(color[(data & 1) as usize]); data >>= 1;
-        self.window_buffer[screen_pos    ] = utils::fetch_c64_color_rgba(color[data as usize]);
+        self.window_buffer[screen_pos + 7] = self.color_rgba(color[(data & 1) as usize]); data >>= 1;
+        self.window_buffer[screen_pos + 6] = self.color_rgba(color[(data & 1) as usize]); data >>= 1;
+        self.window_buffer[screen_pos + 5] = self.color_rgba(color[(data & 1) as usize]); data >>= 1;
+        self.window_buffer[screen_pos + 4] = self.color_rgba(color[(data & 1) as usize]); data >>= 1;
+        self.window_buffer[screen_pos + 3] = self.color_rgba(color[(data & 1) as usize]); data >>= 1;
+        self.window_buffer[screen_pos + 2] = self.color_rgba(color[(data & 1) as usize]); data >>= 1;
+        self.window_buffer[screen_pos + 1] = self.color_rgba(color[(data & 1) as usize]); data >>= 1;
+        self.window_buffer[screen_pos    ] = self.color_rgba(color[data as usize]);
     }
 
 
@@ -1156,18 +1293,18 @@ impl VIC {
         self.fg_mask_buffer[self.fg_mask_offset+1] |= ((((self.gfx_data & 0xAA) | (self.gfx_data & 0xAA) >> 1) as u16) << (8 - self.x_scroll)) as u8;
 
         let mut data = self.gfx_data;
-        self.window_buffer[screen_pos + 7] = utils::fetch_c64_color_rgba(color[(data & 3) as usize]); data >>= 2;
+        self.window_buffer[screen_pos + 7] = self.color_rgba(color[(data & 3) as usize]); data >>= 2;
         self.window_buffer[screen_pos + 6] = self.window_buffer[screen_pos + 7];
-        self.window_buffer[screen_pos + 5] = utils::fetch_c64_color_rgba(color[(data & 3) as usize]); data >>= 2;
+        self.window_buffer[screen_pos + 5] = self.color_rgba(color[(data & 3) as usize]); data >>= 2;
         self.window_buffer[screen_pos + 4] = self.window_buffer[screen_pos + 5];
-        self.window_buffer[screen_pos + 3] = utils::fetch_c64_color_rgba(color[(data & 3) as usize]); data >>= 2;
+        self.window_buffer[screen_pos + 3] = self.color_rgba(color[(data & 3) as usize]); data >>= 2;
         self.window_buffer[screen_pos + 2] = self.window_buffer[screen_pos + 3];
-        self.window_buffer[screen_pos + 1] = utils::fetch_c64_color_rgba(color[(data as usize)]);
+        self.window_buffer[screen_pos + 1] = self.color_rgba(color[(data as usize)]);
         self.window_buffer[screen_pos    ] = self.window_buffer[screen_pos + 1];
     }
     
 
-    fn draw_sprites(&mut self) {
+    fn draw_sprites(&mut self, mem: &mut memory::Memory, lines: &mut Lines) {
         let mut sbit = 1;
         let mut spr_coll = 0;
         let mut gfx_coll = 0;
@@ -1181,7 +1318,7 @@ impl VIC {
             if ((self.sprite_draw & sbit) != 0) && (self.mx[snum] < (c64::SCREEN_WIDTH as u16)-32) {
                 let p = self.line_start_offset as u32 + (self.mx[snum] + 8) as u32;
                 let q = self.mx[snum] + 8;
-                let color = self.read_register(0xD027 + snum as u16);
+                let color = self.read_register(mem, 0xD027 + snum as u16);
 
                 // fetch sprite data and mask
                 let mut sdata: u32 = ((self.sprite_draw_data[snum][0] as u32) << 24) | 
@@ -1201,7 +1338,7 @@ impl VIC {
                 }
 
                 // is sprite X-expanded?
-                let mxe = self.read_register(0xD01D);
+                let mxe = self.read_register(mem, 0xD01D);
                 if (mxe & sbit) != 0 {
                     if self.mx[snum] > ((c64::SCREEN_WIDTH as u16)-56) {
                         sbit <<= 1;
@@ -1227,7 +1364,7 @@ impl VIC {
                     }
 
                     // multicolor?
-                    let mmc = self.read_register(0xD01C);
+                    let mmc = self.read_register(mem, 0xD01C);
                     if (mmc & sbit) != 0 {
                         // expand sprite data
                         sdata_l = ((MULTI_EXP_TABLE[((sdata >> 24) & 0xFF) as usize] as u32) << 16) |
@@ -1244,7 +1381,7 @@ impl VIC {
                         if ((fg_mask & (plane0_l | plane1_l)) != 0) || ((fg_mask_r & (plane0_r | plane1_r)) != 0) {
                             gfx_coll |= sbit;
 
-                            let mdp = self.read_register(0xD01B);
+                            let mdp = self.read_register(mem, 0xD01B);
                             if (mdp & sbit) != 0 {
                                 plane0_l &= !fg_mask; // mask sprite if in background
                                 plane1_l &= !fg_mask;
@@ -1260,7 +1397,7 @@ impl VIC {
 
                             if (plane1_l & 0x80000000) != 0 {
                                 if (plane0_l & 0x80000000) != 0 {
-                                    col = self.read_register(0xD026);
+                                    col = self.read_register(mem, 0xD026);
                                 }
                                 else {
                                     col = color;
@@ -1268,7 +1405,7 @@ impl VIC {
                             }
                             else {
                                 if (plane0_l & 0x80000000) != 0 {
-                                   col = self.read_register(0xD025);
+                                   col = self.read_register(mem, 0xD025);
                                 }
                                 else {
                                     i += 1;
@@ -1281,7 +1418,7 @@ impl VIC {
                                 spr_coll |= self.sprite_coll_buffer[(q + i) as usize] | sbit;
                             }
                             else {
-                                self.window_buffer[(p + i as u32) as usize] = utils::fetch_c64_color_rgba(col);
+                                self.window_buffer[(p + i as u32) as usize] = self.color_rgba(col);
                                 self.sprite_coll_buffer[(q + i) as usize] = sbit;
                             }
 
@@ -1295,7 +1432,7 @@ impl VIC {
                             
                             if (plane1_r & 0x80000000) != 0 {
                                 if (plane0_r & 0x80000000) != 0 {
-                                    col = self.read_register(0xD026);
+                                    col = self.read_register(mem, 0xD026);
                                 }
                                 else {
                                     col = color;
@@ -1303,7 +1440,7 @@ impl VIC {
                             }
                             else {
                                 if (plane0_r & 0x80000000) != 0 {
-                                   col = self.read_register(0xD025);
+                                   col = self.read_register(mem, 0xD025);
                                 }
                                 else {
                                     i += 1;
@@ -1316,7 +1453,7 @@ impl VIC {
                                 spr_coll |= self.sprite_coll_buffer[(q + i) as usize] | sbit;
                             }
                             else {
-                                self.window_buffer[(p + i as u32) as usize] = utils::fetch_c64_color_rgba(col);
+                                self.window_buffer[(p + i as u32) as usize] = self.color_rgba(col);
                                 self.sprite_coll_buffer[(q + i) as usize] = sbit;
                             }
 
@@ -1336,7 +1473,7 @@ impl VIC {
                         if ((fg_mask & sdata_l) != 0) || ((fg_mask_r & sdata_r) != 0) {
                             gfx_coll |= sbit;
 
-                            let mdp = self.read_register(0xD01B);
+                            let mdp = self.read_register(mem, 0xD01B);
                             if (mdp & sbit) != 0 {
                                 sdata_l &= !fg_mask; // mask sprite if in background
                             }
@@ -1354,7 +1491,7 @@ impl VIC {
                                     spr_coll |= self.sprite_coll_buffer[(q + i) as usize] | sbit;
                                 }
                                 else { // draw pixel if no collision
-                                    self.window_buffer[(p + i as u32) as usize] = utils::fetch_c64_color_rgba(color);
+                                    self.window_buffer[(p + i as u32) as usize] = self.color_rgba(color);
                                     self.sprite_coll_buffer[(q + i) as usize] = sbit;
                                 }
                             }
@@ -1370,7 +1507,7 @@ impl VIC {
                                     spr_coll |= self.sprite_coll_buffer[(q + i) as usize] | sbit;
                                 }
                                 else { // draw pixel if no collision
-                                    self.window_buffer[(p + i as u32) as usize] = utils::fetch_c64_color_rgba(color);
+                                    self.window_buffer[(p + i as u32) as usize] = self.color_rgba(color);
                                     self.sprite_coll_buffer[(q + i) as usize] = sbit;
                                 }
                             }
@@ -1382,7 +1519,7 @@ impl VIC {
                 else {
                     // unexpanded
                     // multicolor?
-                    let mmc = self.read_register(0xD01C);
+                    let mmc = self.read_register(mem, 0xD01C);
                     if (mmc & sbit) != 0 {
                         // convert sprite chunky pixels to bitplanes
                         let mut plane0: u32 = (sdata & 0x55555555) | ((sdata & 0x55555555) << 1);
@@ -1392,7 +1529,7 @@ impl VIC {
                         if (fg_mask & (plane0 | plane1)) != 0 {
                             gfx_coll |= sbit;
                             
-                            let mdp = self.read_register(0xD01B);
+                            let mdp = self.read_register(mem, 0xD01B);
                             if (mdp & sbit) != 0 {
                                 plane0 &= !fg_mask; // mask sprite if in background
                                 plane1 &= !fg_mask;
@@ -1404,7 +1541,7 @@ impl VIC {
                             let col: u8;
                             if (plane1 & 0x80000000) != 0 {
                                 if (plane0 & 0x80000000) != 0 {
-                                    col = self.read_register(0xD026);
+                                    col = self.read_register(mem, 0xD026);
                                 }
                                 else {
                                     col = color;
@@ -1412,7 +1549,7 @@ impl VIC {
                             }
                             else {
                                 if (plane0 & 0x80000000) != 0 {
-                                    col = self.read_register(0xD025);
+                                    col = self.read_register(mem, 0xD025);
                                 }
                                 else {
                                     plane0 <<= 1;
@@ -1425,7 +1562,7 @@ impl VIC {
                                 spr_coll |= self.sprite_coll_buffer[(q + i) as usize] | sbit;
                             }
                             else {
-                                self.window_buffer[(p + i as u32) as usize] = utils::fetch_c64_color_rgba(col);
+                                self.window_buffer[(p + i as u32) as usize] = self.color_rgba(col);
                                 self.sprite_coll_buffer[(q + i) as usize] = sbit;
                             }
 
@@ -1439,7 +1576,7 @@ impl VIC {
                         if (fg_mask & sdata) != 0 {
                             gfx_coll |= sbit;
 
-                            let mdp = self.read_register(0xD01B);
+                            let mdp = self.read_register(mem, 0xD01B);
                             if (mdp & sbit) != 0 {
                                 sdata &= !fg_mask; // mask sprite if in background
                             }
@@ -1452,7 +1589,7 @@ impl VIC {
                                     spr_coll |= self.sprite_coll_buffer[(q + i) as usize] | sbit;
                                 }
                                 else { // draw pixel if no collision
-                                    self.window_buffer[(p + i as u32) as usize] = utils::fetch_c64_color_rgba(color);
+                                    self.window_buffer[(p + i as u32) as usize] = self.color_rgba(color);
                                     self.sprite_coll_buffer[(q + i) as usize] = sbit;
                                 }
                             }
@@ -1469,34 +1606,34 @@ impl VIC {
         // explicitly allows the VIC to perform writes there.
         
         // sprite-sprite collisions
-        let clx_spr = self.read_register(0xD01E) | spr_coll;
-        self.write_register_nc(0xD01E, clx_spr);
+        let clx_spr = self.read_register(mem, 0xD01E) | spr_coll;
+        self.write_register_nc(mem, 0xD01E, clx_spr);
         if clx_spr == 0 {
             self.irq_flag |= 0x04;
             if (self.irq_mask & 0x04) != 0 {
                 self.irq_flag |= 0x80;
-                as_mut!(self.cpu_ref).set_vic_irq(true);
+                lines.set_vic_irq(true);
             }
         }
         
         // sprite-background collisions
-        let clx_bgr = self.read_register(0xD01F) | gfx_coll;
-        self.write_register_nc(0xD01F, clx_bgr);
+        let clx_bgr = self.read_register(mem, 0xD01F) | gfx_coll;
+        self.write_register_nc(mem, 0xD01F, clx_bgr);
         if clx_bgr == 0 {
             self.irq_flag |= 0x02;
             if (self.irq_mask & 0x02) != 0 {
                 self.irq_flag |= 0x80;
-                as_mut!(self.cpu_ref).set_vic_irq(true);
+                lines.set_vic_irq(true);
             }
         }
     }
 
 
     // ***helper functions ***
-    fn set_ba_low(&mut self, c64_cycle_cnt: u32) {
-        if !as_mut!(self.cpu_ref).ba_low {
+    fn set_ba_low(&mut self, lines: &mut Lines, c64_cycle_cnt: Cycle) {
+        if !lines.ba_low {
             self.first_ba_cycle = c64_cycle_cnt;
-            as_mut!(self.cpu_ref).ba_low = true;
+            lines.ba_low = true;
         }   
     }
 
@@ -1508,39 +1645,39 @@ impl VIC {
     }
 
 
-    fn fetch_if_bad_line(&mut self, c64_cycle_cnt: u32) {
+    fn fetch_if_bad_line(&mut self, lines: &mut Lines, c64_cycle_cnt: Cycle) {
         if self.is_bad_line {
             self.display_state = true;
-            self.set_ba_low(c64_cycle_cnt);
+            self.set_ba_low(lines, c64_cycle_cnt);
         }
     }
 
 
-    fn rc_if_bad_line(&mut self, c64_cycle_cnt: u32) {
+    fn rc_if_bad_line(&mut self, lines: &mut Lines, c64_cycle_cnt: Cycle) {
         if self.is_bad_line {
             self.display_state = true;
             self.row_cnt = 0;
-            self.set_ba_low(c64_cycle_cnt);
+            self.set_ba_low(lines, c64_cycle_cnt);
         }
     }
 
 
-    fn idle_access(&mut self) {
-        self.read_byte(0x3FFF);
+    fn idle_access(&mut self, mem: &mut memory::Memory) {
+        self.read_byte(mem, 0x3FFF);
     }
 
 
-    fn refresh_access(&mut self){
+    fn refresh_access(&mut self, mem: &mut memory::Memory){
         let ref_cnt = self.refresh_cnt as u16;
-        self.read_byte(0x3F00 | ref_cnt);
+        self.read_byte(mem, 0x3F00 | ref_cnt);
         self.refresh_cnt = self.refresh_cnt.wrapping_sub(0x01);
     }
 
 
-    fn check_sprite_dma(&mut self){
+    fn check_sprite_dma(&mut self, mem: &mut memory::Memory){
         let mut mask = 1;
-        let me = self.read_register(0xD015);
-        let mye = self.read_register(0xD017);
+        let me = self.read_register(mem, 0xD015);
+        let mye = self.read_register(mem, 0xD017);
         for i in 0..8 {
             if ((me & mask) != 0) && ((self.raster_cnt & 0xFF) == self.my[i] as u16) {
                 self.sprite_dma_on |= mask;
@@ -1555,28 +1692,33 @@ impl VIC {
     }
 
 
-    fn sprite_ptr_access(&mut self, num: usize) {
+    fn sprite_ptr_access(&mut self, mem: &mut memory::Memory, num: usize) {
         let addr = self.matrix_base | 0x03F8 | num as u16;
-        self.sprite_ptr[num] = (self.read_byte(addr) as u16) << 6;
+        self.sprite_ptr[num] = (self.read_byte(mem, addr) as u16) << 6;
     }
 
 
-    fn sprite_data_access(&mut self, num: usize, bytenum: usize) {
+    fn sprite_data_access(&mut self, mem: &mut memory::Memory, num: usize, bytenum: usize) {
         if (self.sprite_dma_on & (1 << num as u8)) != 0 {
             let addr = self.mc[num] & 0x3F | self.sprite_ptr[num];
-            self.sprite_data[num][bytenum] = self.read_byte(addr);
+            self.sprite_data[num][bytenum] = self.read_byte(mem, addr);
             self.mc[num] += 1;
         }
         else if bytenum == 1 {
-            self.idle_access();
+            self.idle_access(mem);
         }
     }
 
 
-    fn sample_border(&mut self) {
+    fn color_rgba(&self, idx: u8) -> u32 {
+        self.palette[(idx & 0x0F) as usize]
+    }
+
+
+    fn sample_border(&mut self, mem: &mut memory::Memory) {
         if self.draw_this_line {
             if self.border_on {
-                self.border_color_sample[(self.curr_cycle-13) as usize] = self.read_register(0xD020);
+                self.border_color_sample[self.fg_mask_offset] = self.read_register(mem, 0xD020);
             }
             
             self.screen_chunk_offset += 8;
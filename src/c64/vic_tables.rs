@@ -69,3 +69,7 @@ pub const MULTI_EXP_TABLE: [u16; 256] = [
     0xFF00, 0xFF05, 0xFF0A, 0xFF0F, 0xFF50, 0xFF55, 0xFF5A, 0xFF5F,
     0xFFA0, 0xFFA5, 0xFFAA, 0xFFAF, 0xFFF0, 0xFFF5, 0xFFFA, 0xFFFF
         ];
+
+// color luminances of later VIC-II revisions and of the 6569R1 (0 - black, 32 - white)
+pub const LUMA:    [u8; 16] = [0, 32, 10, 20, 12, 16, 8, 24, 12, 8, 16, 10, 15, 24, 15, 20];
+pub const LUMA_R1: [u8; 16] = [0, 32,  8, 24, 16, 16, 8, 24, 16, 8, 16,  8, 16, 24, 16, 24];
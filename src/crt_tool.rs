@@ -0,0 +1,170 @@
+// "crt" subcommand: wrap raw cartridge binaries into .crt images, dump and validate existing images
+use c64::crt;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::Path;
+
+const USAGE: &'static str = "usage:
+    rust64 crt wrap <8k|16k|ultimax|easyflash> <input.bin> <output.crt> [--name NAME]
+    rust64 crt dump <input.crt> [output_dir]
+    rust64 crt check <input.crt>";
+
+
+pub fn run(args: &[String]) -> Result<(), String> {
+    match args.first().map(|s| &s[..]) {
+        Some("wrap")  => wrap(&args[1..]),
+        Some("dump")  => dump(&args[1..]),
+        Some("check") => check(&args[1..]),
+        _ => Err(USAGE.to_string())
+    }
+}
+
+
+// *** private functions *** //
+
+fn wrap(args: &[String]) -> Result<(), String> {
+    if args.len() < 3 {
+        return Err(USAGE.to_string());
+    }
+
+    let (cart_type, input, output) = (&args[0][..], &args[1][..], &args[2][..]);
+    let name = match args.get(3).map(|s| &s[..]) {
+        Some("--name") => args.get(4).cloned().ok_or("--name requires a cartridge name".to_string())?,
+        Some(arg)      => return Err(format!("unknown argument: {}", arg)),
+        None           => file_stem(output),
+    };
+
+    let data = read_file(input)?;
+    let size_error = |expected: &str| Err(format!("{} is {} bytes, {} cartridges need {}", input, data.len(), cart_type, expected));
+
+    let crt = match cart_type {
+        "8k" => {
+            if data.len() != 0x1000 && data.len() != 0x2000 {
+                return size_error("4096 or 8192 bytes");
+            }
+
+            let mut crt = crt::Crt::new(crt::HW_GENERIC, 0, 1, &name);
+            crt.add_chip(crt::ChipType::ROM, 0, 0x8000, data.clone())?;
+            crt
+        },
+        "16k" => {
+            if data.len() != 0x4000 {
+                return size_error("16384 bytes");
+            }
+
+            let mut crt = crt::Crt::new(crt::HW_GENERIC, 0, 0, &name);
+            crt.add_chip(crt::ChipType::ROM, 0, 0x8000, data.clone())?;
+            crt
+        },
+        "ultimax" => {
+            let mut crt = crt::Crt::new(crt::HW_GENERIC, 1, 0, &name);
+
+            match data.len() {
+                0x1000 => crt.add_chip(crt::ChipType::ROM, 0, 0xF000, data.clone())?,
+                0x2000 => crt.add_chip(crt::ChipType::ROM, 0, 0xE000, data.clone())?,
+                0x4000 => {
+                    crt.add_chip(crt::ChipType::ROM, 0, 0x8000, data[..0x2000].to_vec())?;
+                    crt.add_chip(crt::ChipType::ROM, 0, 0xE000, data[0x2000..].to_vec())?;
+                },
+                _ => return size_error("4096, 8192 or 16384 bytes"),
+            }
+
+            crt
+        },
+        "easyflash" => {
+            // raw image is a sequence of 16k banks: 8k ROML followed by 8k ROMH
+            println!("Note: EasyFlash images can be checked and dumped, but the emulator can't run them yet");
+
+            if data.is_empty() || data.len() > 0x100000 || (data.len() % 0x2000) != 0 {
+                return size_error("a multiple of 8192 bytes, up to 1048576");
+            }
+
+            let mut crt = crt::Crt::new(crt::HW_EASYFLASH, 1, 0, &name);
+
+            for (i, chunk) in data.chunks(0x2000).enumerate() {
+                // erased flash is not stored in the image
+                if chunk.iter().all(|&b| b == 0xFF) {
+                    continue;
+                }
+
+                let load_addr = if (i % 2) == 0 { 0x8000 } else { 0xA000 };
+                crt.add_chip(crt::ChipType::Flash, (i / 2) as u16, load_addr, chunk.to_vec())?;
+            }
+
+            crt
+        },
+        _ => return Err(format!("unknown cartridge type: {}\n{}", cart_type, USAGE)),
+    };
+
+    let errors = print_diagnostics(&crt);
+    if errors > 0 {
+        return Err(format!("refusing to write {} with {} error(s)", output, errors));
+    }
+
+    crt.save(output).map_err(|e| format!("Couldn't write {}: {}", output, e))?;
+    println!("Wrote {}: {} CHIP packet(s)", output, crt.chips().len());
+    Ok(())
+}
+
+
+fn dump(args: &[String]) -> Result<(), String> {
+    let input = &args.first().ok_or(USAGE.to_string())?[..];
+    let output_dir = args.get(1).map(|s| &s[..]).unwrap_or(".");
+
+    let crt = crt::Crt::from_bytes(&read_file(input)?).map_err(|e| format!("{}: {}", input, e))?;
+    println!("{:?}", crt);
+    print_diagnostics(&crt);
+
+    let stem = file_stem(input);
+
+    for chip in crt.chips() {
+        let filename = Path::new(output_dir).join(format!("{}_bank{:02}_{:04x}.bin", stem, chip.bank_number(), chip.load_addr()));
+        let mut file = File::create(&filename).map_err(|e| format!("Couldn't create {}: {}", filename.display(), e))?;
+        file.write_all(chip.data()).map_err(|e| format!("Error writing {}: {}", filename.display(), e))?;
+        println!("Wrote {}: {} bytes", filename.display(), chip.data().len());
+    }
+
+    Ok(())
+}
+
+
+fn check(args: &[String]) -> Result<(), String> {
+    let input = &args.first().ok_or(USAGE.to_string())?[..];
+    let crt = crt::Crt::from_bytes(&read_file(input)?).map_err(|e| format!("{}: {}", input, e))?;
+
+    println!("{}: {} ({}), {} CHIP packet(s)", input,
+             crt::hw_type_name(crt.hw_type()).unwrap_or("Unknown hardware type"), crt.hw_type(), crt.chips().len());
+
+    let errors = print_diagnostics(&crt);
+    if errors > 0 {
+        return Err(format!("{} has {} error(s)", input, errors));
+    }
+
+    println!("OK");
+    Ok(())
+}
+
+
+// print validation results, returns number of errors
+fn print_diagnostics(crt: &crt::Crt) -> usize {
+    let diags = crt.validate();
+
+    for diag in diags.iter() {
+        println!("{}", diag);
+    }
+
+    diags.iter().filter(|d| d.is_error).count()
+}
+
+
+fn read_file(filename: &str) -> Result<Vec<u8>, String> {
+    let mut file = File::open(filename).map_err(|e| format!("Couldn't open {}: {}", filename, e))?;
+    let mut data = Vec::<u8>::new();
+    file.read_to_end(&mut data).map_err(|e| format!("Error reading {}: {}", filename, e))?;
+    Ok(data)
+}
+
+
+fn file_stem(filename: &str) -> String {
+    Path::new(filename).file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or(String::from("cartridge"))
+}
@@ -1,12 +1,17 @@
 // memory debug window
+// the windows belong to the UI thread (Cocoa only allows windows on the main thread): the core
+// draws into buffers that are handed over like the main screen and gets key presses back as events
 extern crate minifb;
 
 mod font;
 
 use c64;
-use minifb::*;
+use c64::frame::{self, FrameReader, FrameWriter};
+use minifb::{Key, KeyRepeat};
 use std::io::Write;
+use std::sync::mpsc::Sender;
 use utils;
+use video;
 
 const DEBUG_W: usize = 640;
 const DEBUG_H: usize = 432;
@@ -21,31 +26,90 @@ const RASTER_COLOR: u32    = 0x000000FF;
 const BADLINE_COLOR: u32   = 0x0000FF00;
 
 
+// open the debugger windows - call on the UI thread, the returned debugger goes to the core
+pub fn open(events: Sender<c64::UiEvent>) -> Result<(Debugger, DebuggerWindows), String> {
+    let (debug_writer, debug_reader) = frame::frame_buffers(DEBUG_W * DEBUG_H);
+    let (vic_writer, vic_reader) = frame::frame_buffers(RASTER_DEBUG_W * RASTER_DEBUG_H);
+
+    // SDL2 only allows one context, which belongs to the main window
+    let debug_options = video::Options { scale: 2, ..video::Options::new() };
+    let mut debug_window = video::open(&video::Backend::Minifb, "Debug window", DEBUG_W, DEBUG_H, &debug_options)?;
+    let mut vic_window = video::open(&video::Backend::Minifb, "VIC", RASTER_DEBUG_W, RASTER_DEBUG_H, &video::Options::new())?;
+
+    debug_window.set_position(480, 20);
+    vic_window.set_position(270, 340);
+
+    let windows = DebuggerWindows {
+        debug_window: debug_window,
+        vic_window: vic_window,
+        debug_frames: debug_reader,
+        vic_frames: vic_reader,
+        events: events,
+    };
+
+    Ok((Debugger::new(debug_writer, vic_writer), windows))
+}
+
+
+// UI side: presents the debugger frames and forwards the debugger keys to the core
+pub struct DebuggerWindows {
+    debug_window: Box<dyn video::Display>,
+    vic_window:   Box<dyn video::Display>,
+    debug_frames: FrameReader,
+    vic_frames:   FrameReader,
+    events: Sender<c64::UiEvent>,
+}
+
+impl DebuggerWindows {
+    // called from the UI loop - windows are processed even while the core is paused
+    pub fn update(&mut self) {
+        if self.debug_window.is_open() {
+            match self.debug_frames.latest() {
+                Some(frame) => self.debug_window.present(frame),
+                None => self.debug_window.update(),
+            }
+
+            let keys = [(Key::Home, KeyRepeat::No), (Key::End, KeyRepeat::No), (Key::PageUp, KeyRepeat::Yes), (Key::PageDown, KeyRepeat::Yes)];
+            for &(key, repeat) in keys.iter() {
+                if self.debug_window.is_key_pressed(key, repeat) {
+                    let _ = self.events.send(c64::UiEvent::DebuggerKey(key));
+                }
+            }
+        }
+
+        if self.vic_window.is_open() {
+            match self.vic_frames.latest() {
+                Some(frame) => self.vic_window.present(frame),
+                None => self.vic_window.update(),
+            }
+        }
+    }
+}
+
+
+// core side: draws the debugger windows' contents
 pub struct Debugger {
-    debug_window: minifb::Window,
-    vic_window:   minifb::Window,
     font: font::SysFont,
     window_buffer: Vec<u32>, // main debugger window data buffer
     vic_buffer: Vec<u32>,    // VIC window data buffer
+    debug_frames: FrameWriter,
+    vic_frames:   FrameWriter,
     mempage_offset: u32,     // RAM preview memory page offset
     draw_mode: u8,
 }
 
 impl Debugger {
-    pub fn new() -> Debugger {
+    fn new(debug_frames: FrameWriter, vic_frames: FrameWriter) -> Debugger {
         let mut dbg = Debugger {
-            debug_window: Window::new("Debug window", DEBUG_W, DEBUG_H, WindowOptions { scale: Scale::X2, ..Default::default() }).unwrap(),
-            vic_window: Window::new("VIC", RASTER_DEBUG_W, RASTER_DEBUG_H, WindowOptions::default()).unwrap(),
             font: font::SysFont::new(),
             window_buffer: vec![0; DEBUG_W * DEBUG_H],
             vic_buffer: vec![0; RASTER_DEBUG_W * RASTER_DEBUG_H],
+            debug_frames: debug_frames,
+            vic_frames: vic_frames,
             mempage_offset: 0,
             draw_mode: 0,
         };
 
-        dbg.debug_window.set_position(480, 20);
-        dbg.vic_window.set_position(270, 340);
-
         for y in 1..26 {
             for x in 0..40 {
                 dbg.font.draw_char_rgb(&mut dbg.window_buffer, DEBUG_W, 8*x as usize, 8 + 8*y as usize, 102, 0x00101010);
@@ -57,15 +121,11 @@ impl Debugger {
     }
 
 
-    pub fn render(&mut self, cpu: &mut c64::cpu::CPUShared, memory: &mut c64::memory::MemShared) {
-        if self.debug_window.is_open() {
-            self.draw_border();
-
-            let home_pressed = self.debug_window.is_key_pressed(Key::Home, KeyRepeat::No);
-            let end_pressed  = self.debug_window.is_key_pressed(Key::End, KeyRepeat::No);
-
-            if home_pressed || end_pressed {
-                if home_pressed {
+    // key pressed in the debug window: Home/End switch the view, PageUp/PageDown scroll RAM
+    pub fn on_key(&mut self, key: Key) {
+        match key {
+            Key::Home | Key::End => {
+                if key == Key::Home {
                     if self.draw_mode == 0 {
                         self.draw_mode = 4;
                     }
@@ -73,7 +133,7 @@ impl Debugger {
                         self.draw_mode -= 1;
                     }
                 }
-                if end_pressed {
+                else {
                     if self.draw_mode == 4 {
                         self.draw_mode = 0;
                     }
@@ -93,41 +153,55 @@ impl Debugger {
                         self.clear_char(x, y);
                     }
                 }
-            }
+            },
+            Key::PageUp if self.draw_mode == 0 => {
+                self.mempage_offset += 0x400;
 
-            match self.draw_mode {
-                0 => self.draw_ram(memory),
-                1 => self.draw_vic(memory),
-                2 => self.draw_cia(cpu),
-                3 => self.draw_color_ram(memory),
-                4 => self.draw_sid(memory),
-                _ => ()
-            }
+                if self.mempage_offset > 0xFC00 {
+                    self.mempage_offset = 0;
+                }
+            },
+            Key::PageDown if self.draw_mode == 0 => {
+                if self.mempage_offset == 0x0000 {
+                    self.mempage_offset = 0x10000;
+                }
+                self.mempage_offset -= 0x400;
+            },
+            _ => ()
+        }
+    }
 
-            self.draw_gfx_mode(memory);
-            self.draw_latch_status(memory);
-            self.draw_data(memory);
-            self.draw_cpu(cpu);
 
-            let _ = self.debug_window.update_with_buffer(&self.window_buffer, DEBUG_W, DEBUG_H);
-        }
-        if self.vic_window.is_open() {
-            let _ = self.vic_window.update_with_buffer(&self.vic_buffer, DEBUG_W, DEBUG_H);
+    // draw both windows and hand them over to the UI
+    pub fn render(&mut self, cpu: &c64::cpu::CPU, bus: &mut c64::bus::Bus) {
+        self.draw_border();
+
+        match self.draw_mode {
+            0 => self.draw_ram(&mut bus.memory),
+            1 => self.draw_vic(&mut bus.memory),
+            2 => self.draw_cia(bus),
+            3 => self.draw_color_ram(&mut bus.memory),
+            4 => self.draw_sid(&mut bus.memory),
+            _ => ()
         }
+
+        self.draw_gfx_mode(&mut bus.memory);
+        self.draw_latch_status(&mut bus.memory);
+        self.draw_data(&mut bus.memory);
+        self.draw_cpu(cpu);
+
+        self.debug_frames.publish(&self.window_buffer);
+        self.vic_frames.publish(&self.vic_buffer);
     }
 
     
-    pub fn update_vic_window(&mut self, vic: &mut c64::vic::VICShared) {
-        if !self.vic_window.is_open() {
-            return;
-        }
-
-        let x = vic.borrow_mut().curr_cycle;
-        let y = vic.borrow_mut().raster_cnt;
-        let is_bad_line = vic.borrow_mut().is_bad_line;
-        let is_raster_irq = vic.borrow_mut().raster_irq == y;
-        let is_border = vic.borrow_mut().border_on;
-        let is_state_changed = vic.borrow_mut().dbg_reg_changed;
+    pub fn update_vic_window(&mut self, vic: &c64::vic::VIC) {
+        let x = vic.curr_cycle;
+        let y = vic.raster_cnt;
+        let is_bad_line = vic.is_bad_line;
+        let is_raster_irq = vic.raster_irq == y;
+        let is_border = vic.border_on;
+        let is_state_changed = vic.dbg_reg_changed;
         
         let mut dst_color = if is_border { BORDER_COLOR } else { BG_COLOR };
         dst_color = if is_state_changed { self.mix_colors(VIC_WRITE_COLOR, dst_color, 0.8) } else { dst_color };
@@ -141,21 +215,7 @@ impl Debugger {
     // *** private functions *** //
 
     // dump RAM page to screen
-    fn draw_ram(&mut self, memory: &mut c64::memory::MemShared) {
-        if self.debug_window.is_key_pressed(Key::PageUp, KeyRepeat::Yes) {
-            self.mempage_offset += 0x400;
-
-            if self.mempage_offset > 0xFC00 {
-                self.mempage_offset = 0;
-            }
-        }
-        if self.debug_window.is_key_pressed(Key::PageDown, KeyRepeat::Yes) {
-            if self.mempage_offset == 0x0000 {
-                self.mempage_offset = 0x10000;
-            }
-            self.mempage_offset -= 0x400;
-        }
-
+    fn draw_ram(&mut self, memory: &mut c64::memory::Memory) {
         let mut start = 0x0000 + self.mempage_offset as u16;
         let mut title = Vec::new();
         let mut hex_offset_x = 0;
@@ -165,7 +225,7 @@ impl Debugger {
 
         for y in 0..26 {
             for x in 0..40 {
-                let byte = memory.borrow_mut().get_ram_bank(c64::memory::MemType::Ram).read(start);
+                let byte = memory.get_ram_bank(c64::memory::MemType::Ram).read(start);
                 self.font.draw_char(&mut self.window_buffer, DEBUG_W, 8*x as usize, 8 + 8*y as usize, byte, 0x05);
 
                 self.draw_hex(hex_offset_x + x as usize, 28 + y as usize, byte);
@@ -180,7 +240,7 @@ impl Debugger {
 
 
     // VIC registers
-    fn draw_vic(&mut self, memory: &mut c64::memory::MemShared) {
+    fn draw_vic(&mut self, memory: &mut c64::memory::Memory) {
         let mut start = 0xD000;
         let mut title = Vec::new();
         let mut hex_offset_x = 0;
@@ -190,7 +250,7 @@ impl Debugger {
 
         for y in 0..25 {
             for x in 0..40 {
-                let byte = memory.borrow_mut().get_ram_bank(c64::memory::MemType::Io).read(start);
+                let byte = memory.get_ram_bank(c64::memory::MemType::Io).read(start);
                 self.font.draw_char(&mut self.window_buffer, DEBUG_W, 8*x as usize, 8 + 8*y as usize, byte, 0x05);
                 self.draw_hex(hex_offset_x + x as usize, 28 + y as usize, byte);
                 hex_offset_x += 1;
@@ -206,7 +266,7 @@ impl Debugger {
 
 
     // CIA registers
-    fn draw_cia(&mut self, cpu: &mut c64::cpu::CPUShared) {
+    fn draw_cia(&mut self, bus: &mut c64::bus::Bus) {
         let mut start = 0xDC00;
         let mut title = Vec::new();
         let mut hex_offset_x = 0;
@@ -221,7 +281,7 @@ impl Debugger {
                     start += 1;
                     continue;
                 }
-                let byte = cpu.borrow_mut().read_byte(start);
+                let byte = bus.read_byte(start);
                 self.font.draw_char(&mut self.window_buffer, DEBUG_W, 8*x as usize, 8 + 8*y as usize, byte, 0x05);
                 self.draw_hex(hex_offset_x + x as usize, 28 + y as usize, byte);
                 hex_offset_x += 1;
@@ -237,7 +297,7 @@ impl Debugger {
 
 
     // SID registers
-    fn draw_sid(&mut self, memory: &mut c64::memory::MemShared) {
+    fn draw_sid(&mut self, memory: &mut c64::memory::Memory) {
         let mut start = 0xD400;
         let mut title = Vec::new();
         let mut hex_offset_x = 0;
@@ -247,7 +307,7 @@ impl Debugger {
 
         for y in 0..25 {
             for x in 0..40 {
-                let byte = memory.borrow_mut().get_ram_bank(c64::memory::MemType::Io).read(start);
+                let byte = memory.get_ram_bank(c64::memory::MemType::Io).read(start);
                 self.font.draw_char(&mut self.window_buffer, DEBUG_W, 8*x as usize, 8 + 8*y as usize, byte, 0x05);
 
                 self.draw_hex(hex_offset_x + x as usize, 28 + y as usize, byte);
@@ -264,7 +324,7 @@ impl Debugger {
 
 
     // Color RAM
-    fn draw_color_ram(&mut self, memory: &mut c64::memory::MemShared) {
+    fn draw_color_ram(&mut self, memory: &mut c64::memory::Memory) {
         let mut start = 0xD800;
 
         let mut title = Vec::new();
@@ -276,7 +336,7 @@ impl Debugger {
 
         for y in 0..25 {
             for x in 0..40 {
-                let byte = memory.borrow_mut().get_ram_bank(c64::memory::MemType::Io).read(start);
+                let byte = memory.get_ram_bank(c64::memory::MemType::Io).read(start);
                 self.font.draw_char(&mut self.window_buffer, DEBUG_W, 8*x as usize, 8 + 8*y as usize, byte, 0x05);
 
                 self.draw_hex(hex_offset_x + x as usize, 28 + y as usize, byte);
@@ -313,9 +373,9 @@ impl Debugger {
 
 
     // basic C64 settings
-    fn draw_data(&mut self, memory: &mut c64::memory::MemShared) {
-        let d018 = memory.borrow_mut().get_ram_bank(c64::memory::MemType::Io).read(0xD018);
-        let dd00 = memory.borrow_mut().get_ram_bank(c64::memory::MemType::Io).read(0xDD00);
+    fn draw_data(&mut self, memory: &mut c64::memory::Memory) {
+        let d018 = memory.get_ram_bank(c64::memory::MemType::Io).read(0xD018);
+        let dd00 = memory.get_ram_bank(c64::memory::MemType::Io).read(0xDD00);
         
         let mut vmatrix_txt = Vec::new();
         let mut char_txt = Vec::new();
@@ -337,9 +397,9 @@ impl Debugger {
 
 
     // current graphics mode tags
-    fn draw_gfx_mode(&mut self, memory: &mut c64::memory::MemShared) {
-        let d011 = memory.borrow_mut().get_ram_bank(c64::memory::MemType::Io).read(0xD011);
-        let d016 = memory.borrow_mut().get_ram_bank(c64::memory::MemType::Io).read(0xD016);
+    fn draw_gfx_mode(&mut self, memory: &mut c64::memory::Memory) {
+        let d011 = memory.get_ram_bank(c64::memory::MemType::Io).read(0xD011);
+        let d016 = memory.get_ram_bank(c64::memory::MemType::Io).read(0xD016);
         let ecm_on = (d011 & 0x40) != 0;
         let mcm_on = (d016 & 0x10) != 0;
         let bmp_on = (d011 & 0x20) != 0;
@@ -352,11 +412,11 @@ impl Debugger {
 
 
     // active memory banks
-    fn draw_latch_status(&mut self, memory: &mut c64::memory::MemShared) {
-        let basic_on = memory.borrow_mut().basic_on;
-        let chargen_on = memory.borrow_mut().chargen_on;
-        let io_on = memory.borrow_mut().io_on;
-        let kernal_on = memory.borrow_mut().kernal_on;
+    fn draw_latch_status(&mut self, memory: &mut c64::memory::Memory) {
+        let basic_on = memory.basic_on;
+        let chargen_on = memory.chargen_on;
+        let io_on = memory.io_on;
+        let kernal_on = memory.kernal_on;
         
         self.font.draw_text(&mut self.window_buffer, DEBUG_W, 48, 25, "BASIC", if basic_on { 0x0A } else { 0x0B });
         self.font.draw_text(&mut self.window_buffer, DEBUG_W, 55, 25, "CHARGEN", if chargen_on { 0x0A } else { 0x0B });
@@ -366,19 +426,19 @@ impl Debugger {
 
 
     // draw CPU flags and registers
-    fn draw_cpu(&mut self, cpu: &mut c64::cpu::CPUShared) {
+    fn draw_cpu(&mut self, cpu: &c64::cpu::CPU) {
         let mut pc_txt = Vec::new();
         let mut a_txt = Vec::new();
         let mut x_txt = Vec::new();
         let mut y_txt = Vec::new();
         let mut sp_txt = Vec::new();
         let mut p_txt = Vec::new();
-        let _ = write!(&mut pc_txt, "${:04X}", cpu.borrow_mut().pc);
-        let _ = write!(&mut a_txt, "${:02X}", cpu.borrow_mut().a);
-        let _ = write!(&mut x_txt, "${:02X}", cpu.borrow_mut().x);
-        let _ = write!(&mut y_txt, "${:02X}", cpu.borrow_mut().y);
-        let _ = write!(&mut sp_txt, "${:02X}", cpu.borrow_mut().sp);
-        let _ = write!(&mut p_txt, "[{:08b}]", cpu.borrow_mut().p);
+        let _ = write!(&mut pc_txt, "${:04X}", cpu.pc);
+        let _ = write!(&mut a_txt, "${:02X}", cpu.a);
+        let _ = write!(&mut x_txt, "${:02X}", cpu.x);
+        let _ = write!(&mut y_txt, "${:02X}", cpu.y);
+        let _ = write!(&mut sp_txt, "${:02X}", cpu.sp);
+        let _ = write!(&mut p_txt, "[{:08b}]", cpu.p);
         
         self.font.draw_text(&mut self.window_buffer, DEBUG_W, 44, 22, "PC:", 0x0F);
         self.font.draw_text(&mut self.window_buffer, DEBUG_W, 47, 22, &String::from_utf8(pc_txt).unwrap().to_owned()[..], 0x0E);
@@ -0,0 +1,14 @@
+extern crate minifb;
+extern crate byteorder;
+extern crate num;
+
+#[macro_use]
+extern crate enum_primitive;
+
+pub mod utils;
+pub mod audio;
+pub mod c64;
+pub mod crt_tool;
+pub mod debugger;
+pub mod ui;
+pub mod video;
@@ -1,47 +1,474 @@
-extern crate minifb;
-extern crate byteorder;
-extern crate num;
+extern crate rust64;
 
-#[macro_use]
-extern crate enum_primitive;
-
-#[macro_use]
-mod utils;
-mod c64;
-mod debugger;
-
-use minifb::*;
+use rust64::{audio, c64, crt_tool, debugger, ui, video};
 use std::env;
+use std::process;
+use std::sync::mpsc;
+use std::thread;
 
 fn main() {
-    let args: Vec<String> = env::args().collect();
+    let args: Vec<String> = env::args().skip(1).collect();
+
+    // cartridge image tools don't start the emulator
+    if args.first().map_or(false, |arg| arg == "crt") {
+        if let Err(e) = crt_tool::run(&args[1..]) {
+            exit_with_error(&e);
+        }
+        return;
+    }
+
+    let mut args = args.into_iter();
 
     let mut prg_to_load  = String::new();
     let mut crt_to_load  = String::new();
+    let mut sid_to_play  = String::new();
+    let mut subtune      = None;
     let mut debugger_on  = false;
-    let mut window_scale = Scale::X1;
+    let mut window_scale = 1;
+    let mut georam_size  = 0;
+    let mut georam_image = None;
+    let mut reu_size     = 0;
+    let mut rom_config   = c64::roms::RomConfig::default();
+    let mut vic_model    = None;
+    let mut sid_model    = None;
+    let mut digiboost    = false;
+    let mut extra_sids   = Vec::new();
+    let mut sid_pans     = Vec::new();
+    let mut model_name   = String::from("c64");
+    let mut speed        = 100;
+    let mut warp         = false;
+    let mut audio_out    = audio::Backend::default();
+    let mut video_out    = video::Backend::Minifb;
+    let mut fullscreen   = false;
+    let mut record_file  = None;
+    let mut record_chans = audio::CHANNELS as u16;
+    let mut record_rate  = audio::SAMPLE_RATE;
+    let mut sid_log      = None;
+    let mut sid_dump     = None;
+    let mut midi_file    = None;
+    let mut duration     = None;
+    let mut port_devices = [None, None];
+    let mut true_aspect  = true;
 
     // process cmd line params
-    for i in 1..args.len() {
-        if args[i] == "debugger" {
+    while let Some(arg) = args.next() {
+        if arg == "debugger" {
             debugger_on = true;
         }
-        else if args[i] == "x2" {
-            window_scale = Scale::X2;
+        else if arg == "x2" {
+            window_scale = 2;
+        }
+        else if arg == "--scale" {
+            window_scale = match args.next().and_then(|scale| scale.parse::<u32>().ok()) {
+                Some(scale) if scale >= 1 && scale <= video::MAX_SCALE => scale,
+                _ => exit_with_error(&format!("--scale requires an integer scale factor (1-{})", video::MAX_SCALE)),
+            };
+        }
+        else if arg == "--video" {
+            video_out = match args.next().and_then(|name| video::Backend::from_name(&name)) {
+                Some(backend) => backend,
+                None => exit_with_error("--video requires a video output (minifb, sdl or offscreen)"),
+            };
+        }
+        else if arg == "--fullscreen" {
+            fullscreen = true;
+        }
+        else if arg == "--square-pixels" {
+            true_aspect = false;
+        }
+        else if arg == "--georam" {
+            georam_size = match args.next().and_then(|size| size.parse::<usize>().ok()) {
+                Some(size) => size,
+                None => exit_with_error("--georam requires a size in kilobytes (512, 1024, 2048 or 4096)"),
+            };
+        }
+        else if arg == "--neoram" {
+            georam_image = match args.next() {
+                Some(image) => Some(image),
+                None => exit_with_error("--neoram requires an image file name"),
+            };
+        }
+        else if arg == "--reu" {
+            reu_size = match args.next().and_then(|size| size.parse::<usize>().ok()) {
+                Some(size) => size,
+                None => exit_with_error("--reu requires a size in kilobytes (128, 256, 512 ... 16384)"),
+            };
+        }
+        else if arg == "--vic" {
+            vic_model = match args.next().and_then(|name| c64::vic::VICModel::from_name(&name)) {
+                Some(model) => Some(model),
+                None => exit_with_error("--vic requires a VIC-II model (pal/6569, 6569r1, ntsc/6567r8 or 6567r56a)"),
+            };
+        }
+        else if arg == "--sid-model" {
+            sid_model = match args.next().and_then(|name| c64::sid::SIDModel::from_name(&name)) {
+                Some(model) => Some(model),
+                None => exit_with_error("--sid-model requires a SID model (6581 or 8580)"),
+            };
+        }
+        else if arg == "--digiboost" {
+            digiboost = true;
+        }
+        else if arg == "--extra-sid" {
+            match args.next().and_then(|value| parse_extra_sid(&value)) {
+                Some(sid) => extra_sids.push(sid),
+                None => exit_with_error("--extra-sid requires an address (d420-d7e0 or de00-dfe0 in steps of 20) and optionally a model, e.g. d420,8580"),
+            }
         }
-        else if args[i].ends_with(".prg") {
-            prg_to_load = args[i].clone();
+        else if arg == "--sid-pan" {
+            sid_pans = match args.next().and_then(|value| value.split(',').map(|pan| pan.parse::<i32>().ok()).collect::<Option<Vec<_>>>()) {
+                Some(ref pans) if pans.len() <= c64::sound::MAX_SIDS && pans.iter().all(|&pan| pan >= -100 && pan <= 100) => pans.clone(),
+                _ => exit_with_error("--sid-pan requires a position for each SID from -100 (left) to 100 (right), e.g. -50,50"),
+            };
         }
-        else if args[i].ends_with(".crt") {
-            crt_to_load = args[i].clone();
+        else if arg == "--sid" {
+            sid_to_play = match args.next() {
+                Some(filename) => filename,
+                None => exit_with_error("--sid requires a .sid file name"),
+            };
+        }
+        else if arg == "--subtune" {
+            subtune = match args.next().and_then(|song| song.parse::<u16>().ok()) {
+                Some(song) if song >= 1 => Some(song),
+                _ => exit_with_error("--subtune requires a song number (starting at 1)"),
+            };
+        }
+        else if arg == "--speed" {
+            speed = match args.next().and_then(|speed| speed.parse::<u32>().ok()) {
+                Some(speed) if speed >= c64::MIN_SPEED && speed <= c64::MAX_SPEED => speed,
+                _ => exit_with_error(&format!("--speed requires a percentage ({}-{})", c64::MIN_SPEED, c64::MAX_SPEED)),
+            };
+        }
+        else if arg == "--warp" {
+            warp = true;
+        }
+        else if arg == "--audio" {
+            audio_out = match args.next().and_then(|name| audio::Backend::from_name(&name)) {
+                Some(backend) => backend,
+                None => exit_with_error("--audio requires an audio output (sdl, cpal, none or a .wav file name)"),
+            };
+        }
+        else if arg == "--record-audio" {
+            record_file = match args.next() {
+                Some(filename) => Some(filename),
+                None => exit_with_error("--record-audio requires a .wav file name"),
+            };
+        }
+        else if arg == "--record-channels" {
+            record_chans = match args.next().and_then(|channels| channels.parse::<u16>().ok()) {
+                Some(channels) if channels == 1 || channels == 2 => channels,
+                _ => exit_with_error("--record-channels requires 1 (mono) or 2 (stereo)"),
+            };
+        }
+        else if arg == "--record-rate" {
+            record_rate = match args.next().and_then(|rate| rate.parse::<u32>().ok()) {
+                Some(rate) if rate == 44100 || rate == 48000 => rate,
+                _ => exit_with_error("--record-rate requires a sample rate (44100 or 48000)"),
+            };
+        }
+        else if arg == "--sid-log" || arg == "--sid-dump" {
+            let filename = match args.next() {
+                Some(filename) => Some(filename),
+                None => exit_with_error(&format!("{} requires a file name", arg)),
+            };
+
+            if arg == "--sid-log" { sid_log = filename; } else { sid_dump = filename; }
+        }
+        else if arg == "--midi" {
+            midi_file = match args.next() {
+                Some(filename) => Some(filename),
+                None => exit_with_error("--midi requires a .mid file name"),
+            };
+        }
+        else if arg == "--port1" || arg == "--port2" {
+            let device = match args.next().and_then(|name| c64::control_port::Device::from_name(&name)) {
+                Some(device) => device,
+                None => exit_with_error(&format!("{} requires a device (joystick, paddles, 1351 or 1350)", arg)),
+            };
+
+            port_devices[if arg == "--port1" { 0 } else { 1 }] = Some(device);
+        }
+        else if arg == "--duration" {
+            duration = match args.next().and_then(|seconds| seconds.parse::<f64>().ok()) {
+                Some(seconds) if seconds > 0.0 => Some(seconds),
+                _ => exit_with_error("--duration requires a number of seconds"),
+            };
+        }
+        else if arg == "--model" {
+            model_name = match args.next() {
+                Some(name) => name,
+                None => exit_with_error("--model requires a machine model (c64, c64c, sx64, c64gs or max)"),
+            };
+        }
+        else if arg == "--rom-dir" || arg == "--basic" || arg == "--chargen" || arg == "--kernal" {
+            let value = match args.next() {
+                Some(value) => Some(value),
+                None => exit_with_error(&format!("{} requires a path", arg)),
+            };
+
+            match &arg[..] {
+                "--rom-dir" => rom_config.rom_dir = value,
+                "--basic"   => rom_config.basic   = value,
+                "--chargen" => rom_config.chargen = value,
+                _           => rom_config.kernal  = value,
+            }
+        }
+        else if arg.ends_with(".prg") {
+            prg_to_load = arg;
+        }
+        else if arg.ends_with(".crt") {
+            crt_to_load = arg;
+        }
+        else if arg.to_lowercase().ends_with(".sid") {
+            sid_to_play = arg;
+        }
+    }
+
+    // NeoRAM is a battery backed GeoRAM - default to 512K if only the image is given
+    if georam_image.is_some() && georam_size == 0 {
+        georam_size = 512;
+    }
+
+    // --vic, --sid-model and ROM overrides take precedence over the model preset
+    let mut model = match c64::model::MachineModel::from_name(&model_name) {
+        Some(model) => model,
+        None => exit_with_error(&format!("unknown machine model {} (expected c64, c64c, sx64, c64gs or max)", model_name)),
+    };
+
+    // a tune picks the machine it was made for, unless told otherwise
+    let tune = if sid_to_play.is_empty() { None } else {
+        match c64::psid::Psid::from_filename(&sid_to_play) {
+            Ok(tune) => Some(tune),
+            Err(e)   => exit_with_error(&e),
+        }
+    };
+
+    if let Some(ref tune) = tune {
+        println!("{}", tune.describe());
+
+        // the player starts from the booted kernal's BASIC main loop
+        if !model.has_roms {
+            exit_with_error(&format!("the {} has no kernal to boot, so it can't play SID tunes", model.name));
+        }
+
+        let song = subtune.unwrap_or(tune.start_song);
+        if song > tune.songs {
+            exit_with_error(&format!("--subtune {} is out of range: {} has {} song(s)", song, sid_to_play, tune.songs));
+        }
+        subtune = Some(song);
+
+        if let Some(tune_vic) = tune.vic_model() {
+            model.vic_model = tune_vic;
+        }
+
+        if let Some(tune_sid) = tune.sid_model(0) {
+            model.sid_model = tune_sid;
+        }
+
+        if extra_sids.is_empty() {
+            extra_sids = tune.extra_sids().iter().enumerate().map(|(i, &address)| (address, tune.sid_model(i + 1))).collect();
+        }
+    }
+
+    if let Some(vic_model) = vic_model {
+        model.vic_model = vic_model;
+    }
+
+    if let Some(sid_model) = sid_model {
+        model.sid_model = sid_model;
+    }
+
+    if extra_sids.len() > c64::sound::MAX_SIDS - 1 {
+        exit_with_error(&format!("at most {} extra SIDs can be added", c64::sound::MAX_SIDS - 1));
+    }
+
+    // $DE00-$DFFF belongs to the expansion port device, if there is one
+    let port_device = if !crt_to_load.is_empty() { Some("a cartridge") }
+                      else if georam_size > 0    { Some("GeoRAM/NeoRAM") }
+                      else if reu_size > 0       { Some("an REU") }
+                      else                       { None };
+
+    for (i, &(address, _)) in extra_sids.iter().enumerate() {
+        if extra_sids[..i].iter().any(|&(other, _)| other == address) {
+            exit_with_error(&format!("there is already a SID at ${:04X}", address));
+        }
+
+        if let Some(device) = port_device {
+            if address >= 0xDE00 {
+                exit_with_error(&format!("can't add a SID at ${:04X}: the I/O area is used by {} in the expansion port", address, device));
+            }
         }
     }
-    
-    let mut c64 = c64::C64::new(window_scale, debugger_on, &prg_to_load, &crt_to_load);
-    c64.reset();
+
+    // extra SIDs without a model are the same revision as the first one
+    let extra_sids: Vec<_> = extra_sids.into_iter().map(|(address, sid_model)| (address, sid_model.unwrap_or(model.sid_model))).collect();
+
+    if digiboost && model.sid_model != c64::sid::SIDModel::MOS8580 && extra_sids.iter().all(|&(_, m)| m != c64::sid::SIDModel::MOS8580) {
+        println!("Warning: --digiboost only affects the 8580 SID");
+    }
+
+    if rom_config.kernal.is_none() {
+        rom_config.kernal = model.kernal.map(String::from);
+    }
+
+    println!("Machine model {}", model.describe());
+
+    let roms = if model.has_roms {
+        match c64::roms::RomSet::load(&rom_config) {
+            Ok(roms) => roms,
+            Err(e)   => exit_with_error(&e),
+        }
+    }
+    else {
+        if crt_to_load.is_empty() {
+            println!("Warning: the {} has no ROMs and needs an Ultimax cartridge to start", model.name);
+        }
+        c64::roms::RomSet::unpopulated()
+    };
+
+    let mut expansions: Vec<Box<dyn c64::expansion::ExpansionPort>> = Vec::new();
+
+    if georam_size > 0 {
+        match c64::georam::GeoRAM::new(georam_size, georam_image.as_ref().map(|s| &s[..])) {
+            Ok(georam) => expansions.push(Box::new(georam)),
+            Err(e)     => exit_with_error(&e),
+        }
+    }
+
+    if reu_size > 0 {
+        match c64::reu::REU::new(reu_size) {
+            Ok(reu) => expansions.push(Box::new(reu)),
+            Err(e)  => exit_with_error(&e),
+        }
+    }
+
+    // the emulation core runs on its own thread: the main thread only presents
+    // frames and forwards input, so window handling can't stall emulation or audio
+    let (frame_writer, frame_reader) = c64::frame::frame_buffers(c64::SCREEN_WIDTH * c64::SCREEN_HEIGHT);
+    let (event_sender, event_receiver) = mpsc::channel();
+    let (title_sender, title_receiver) = mpsc::channel();
+
+    let video_options = video::Options {
+        scale: window_scale,
+        pixel_aspect: if true_aspect { model.vic_model.pixel_aspect() } else { 1.0 },
+    };
+
+    let mut display = match video::open(&video_out, "Rust64", c64::SCREEN_WIDTH, c64::SCREEN_HEIGHT, &video_options) {
+        Ok(display) => display,
+        Err(e)      => exit_with_error(&format!("Couldn't open video output: {}", e)),
+    };
+
+    // SDL2 only allows one context, so SDL2 audio has to be played through the SDL2 display
+    if video_out == video::Backend::Sdl && audio_out == audio::Backend::Sdl {
+        let output = audio::RemoteOutput::new();
+
+        match display.open_audio(output.clone()) {
+            Ok(_)  => audio_out = audio::Backend::Remote(output),
+            Err(e) => { println!("Couldn't open audio output: {} - sound is disabled", e); audio_out = audio::Backend::Null; },
+        }
+    }
+
+    // debugger windows are UI windows too - only the drawing happens on the core thread
+    let (debugger, debugger_windows) = if debugger_on {
+        match debugger::open(event_sender.clone()) {
+            Ok((debugger, windows)) => (Some(debugger), Some(windows)),
+            Err(e) => exit_with_error(&format!("Couldn't open debugger windows: {}", e)),
+        }
+    } else { (None, None) };
+
+    let mut ui = ui::UI::new(display, frame_reader, event_sender, title_receiver);
+
+    if let Some(windows) = debugger_windows {
+        ui.set_debugger(windows);
+    }
+
+    if fullscreen {
+        ui.set_fullscreen(true);
+    }
+
+    let core = thread::spawn(move || {
+        let mut c64 = c64::C64::new(roms, &model, debugger, &prg_to_load, &crt_to_load, &audio_out, frame_writer, event_receiver, title_sender);
+
+        for device in expansions {
+            if let Err(e) = c64.attach_expansion(device) {
+                exit_with_error(&e);
+            }
+        }
+
+        for (port, device) in port_devices.iter().enumerate() {
+            if let Some(device) = *device {
+                c64.set_port_device(port, device);
+            }
+        }
+
+        c64.set_speed(speed);
+        c64.set_warp(warp);
+        for &(address, sid_model) in extra_sids.iter() {
+            c64.add_sid(address, sid_model);
+        }
+
+        if !sid_pans.is_empty() {
+            c64.set_sid_panning(&sid_pans);
+        }
+
+        c64.set_digiboost(digiboost);
+
+        if let Some(tune) = tune {
+            c64.load_sid(tune, subtune.unwrap_or(1));
+        }
+
+        if sid_log.is_some() || sid_dump.is_some() {
+            c64.log_sid(sid_log.as_ref().map(|s| &s[..]), sid_dump.as_ref().map(|s| &s[..]));
+        }
+
+        if let Some(ref filename) = midi_file {
+            c64.export_midi(filename);
+        }
+
+        if let Some(seconds) = duration {
+            c64.set_duration(seconds);
+        }
+
+        c64.set_record_format(record_chans, record_rate);
+        if let Some(ref filename) = record_file {
+            c64.record_audio(filename);
+        }
+        c64.reset();
+
+        while c64.run() {}
+
+        c64.shutdown();
+    });
 
     // main update loop
-    while c64.main_window.is_open() {
-        c64.run();
+    while ui.is_open() {
+        ui.update();
+    }
+
+    ui.close();
+    let _ = core.join();
+}
+
+
+// ADDRESS[,MODEL] of an additional SID, e.g. d420 or de00,8580
+fn parse_extra_sid(value: &str) -> Option<(u16, Option<c64::sid::SIDModel>)> {
+    let mut parts = value.split(',');
+    let address = c64::sound::parse_address(parts.next()?)?;
+
+    let sid_model = match parts.next() {
+        Some(name) => Some(c64::sid::SIDModel::from_name(name)?),
+        None => None,
+    };
+
+    if parts.next().is_some() {
+        return None;
     }
+
+    Some((address, sid_model))
+}
+
+
+fn exit_with_error(msg: &str) -> ! {
+    println!("Error: {}", msg);
+    process::exit(1);
 }
@@ -0,0 +1,147 @@
+// main window: presents frames produced by the emulation core and forwards input to it
+// runs on the main thread, so window drags and resizes don't stall emulation or audio
+extern crate minifb;
+
+use c64;
+use c64::frame::FrameReader;
+use debugger::DebuggerWindows;
+use minifb::*;
+use std::sync::mpsc::{Receiver, Sender, TryRecvError};
+use video;
+
+// keys forwarded to the core - iterating over all keys is crawling-slow, so check individual keys
+const NUM_KEYS: usize = 93;
+const KEYS: [Key; NUM_KEYS] = [
+    Key::Key0, Key::Key1, Key::Key2, Key::Key3, Key::Key4, Key::Key5, Key::Key6, Key::Key7, Key::Key8, Key::Key9,
+    Key::A, Key::B, Key::C, Key::D, Key::E, Key::F, Key::G, Key::H, Key::I, Key::J, Key::K, Key::L, Key::M,
+    Key::N, Key::O, Key::P, Key::Q, Key::R, Key::S, Key::T, Key::U, Key::V, Key::W, Key::X, Key::Y, Key::Z,
+    Key::F1, Key::F2, Key::F3, Key::F4, Key::F5, Key::F6, Key::F7, Key::F8,
+    Key::Down, Key::Up, Key::Right, Key::Left,
+    Key::Space, Key::Comma, Key::Period, Key::Slash, Key::Enter, Key::Backspace, Key::Backquote,
+    Key::LeftShift, Key::RightShift, Key::Escape, Key::Minus, Key::Equal, Key::Insert, Key::Home,
+    Key::LeftBracket, Key::RightBracket, Key::Delete, Key::Semicolon, Key::Apostrophe, Key::Backslash,
+    Key::Tab, Key::LeftCtrl, Key::End,
+    // joystick
+    Key::NumPad1, Key::NumPad2, Key::NumPad3, Key::NumPad4, Key::NumPad5,
+    Key::NumPad6, Key::NumPad7, Key::NumPad8, Key::NumPad9, Key::RightCtrl, Key::NumLock,
+    // emulator control
+    Key::F9, Key::F10, Key::F11, Key::F12, Key::NumPadAsterisk, Key::NumPadPlus, Key::NumPadMinus,
+    Key::NumPadSlash, Key::NumPadDot, Key::PageUp, Key::PageDown,
+];
+
+
+pub struct UI {
+    display: Box<dyn video::Display>,
+    frames: FrameReader,
+    events: Sender<c64::UiEvent>,
+    titles: Receiver<String>,
+    debugger: Option<DebuggerWindows>,
+    keys_down: [bool; NUM_KEYS],
+    mouse_pos: Option<(f32, f32)>,
+    mouse_down: [bool; 2], // left, right
+    core_running: bool,
+    fullscreen_key: bool,
+}
+
+impl UI {
+    pub fn new(display: Box<dyn video::Display>, frames: FrameReader, events: Sender<c64::UiEvent>, titles: Receiver<String>) -> UI {
+        let mut ui = UI {
+            display: display,
+            frames: frames,
+            events: events,
+            titles: titles,
+            debugger: None,
+            keys_down: [false; NUM_KEYS],
+            mouse_pos: None,
+            mouse_down: [false; 2],
+            core_running: true,
+            fullscreen_key: false,
+        };
+
+        ui.display.set_position(75, 20);
+        ui
+    }
+
+
+    // false once the window is closed or the core has stopped
+    pub fn is_open(&self) -> bool {
+        self.display.is_open() && self.core_running
+    }
+
+
+    pub fn update(&mut self) {
+        loop {
+            match self.titles.try_recv() {
+                Ok(title) => self.display.set_title(&title),
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => { self.core_running = false; break; }
+            }
+        }
+
+        match self.frames.latest() {
+            Some(frame) => self.display.present(frame),
+            None => self.display.update(),
+        }
+
+        if let Some(ref mut debugger) = self.debugger {
+            debugger.update();
+        }
+
+        // Alt+Enter toggles fullscreen - Enter is not passed on while Alt is held
+        let alt_down = self.display.is_key_down(Key::LeftAlt) || self.display.is_key_down(Key::RightAlt);
+        let fullscreen_key = alt_down && self.display.is_key_down(Key::Enter);
+
+        if fullscreen_key && !self.fullscreen_key {
+            let fullscreen = !self.display.is_fullscreen();
+            self.set_fullscreen(fullscreen);
+        }
+        self.fullscreen_key = fullscreen_key;
+
+        // send key state changes only
+        for (i, &key) in KEYS.iter().enumerate() {
+            let key_down = self.display.is_key_down(key) && !(alt_down && key == Key::Enter);
+
+            if key_down != self.keys_down[i] {
+                self.keys_down[i] = key_down;
+                let _ = self.events.send(if key_down { c64::UiEvent::KeyDown(key) } else { c64::UiEvent::KeyUp(key) });
+            }
+        }
+
+        // mouse for paddles and mice in the control ports
+        let mouse_pos = self.display.mouse_pos();
+        if mouse_pos != self.mouse_pos {
+            self.mouse_pos = mouse_pos;
+            let _ = self.events.send(match mouse_pos {
+                Some((x, y)) => c64::UiEvent::MouseMove(x, y),
+                None => c64::UiEvent::MouseOut,
+            });
+        }
+
+        for (i, &button) in [MouseButton::Left, MouseButton::Right].iter().enumerate() {
+            let button_down = self.display.is_mouse_down(button);
+
+            if button_down != self.mouse_down[i] {
+                self.mouse_down[i] = button_down;
+                let _ = self.events.send(c64::UiEvent::MouseButton(button, button_down));
+            }
+        }
+    }
+
+
+    pub fn set_debugger(&mut self, debugger: DebuggerWindows) {
+        self.debugger = Some(debugger);
+    }
+
+
+    pub fn set_fullscreen(&mut self, fullscreen: bool) {
+        if let Err(e) = self.display.set_fullscreen(fullscreen) {
+            println!("Can't switch to fullscreen: {}", e);
+        }
+    }
+
+
+    // tell the core to stop
+    pub fn close(&mut self) {
+        let _ = self.events.send(c64::UiEvent::Quit);
+    }
+}
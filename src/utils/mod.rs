@@ -1,4 +1,5 @@
 // helper utility functions and macros
+use c64::bus;
 use c64::cpu;
 use c64::opcodes;
 use std::io::prelude::*;
@@ -7,16 +8,6 @@ use std::io::SeekFrom;
 use std::path::Path;
 
 
-// helper macros to easily extract references from Option<RefCell<...>>
-macro_rules! as_ref {
-    ($x:expr) => ($x.as_ref().unwrap().borrow_mut())
-}
-
-macro_rules! as_mut {
-    ($x:expr) => ($x.as_mut().unwrap().borrow_mut())
-}
-
-
 // common helper functions
 pub fn open_file(filename: &str, offset: u64) -> Vec<u8> {
     let path = Path::new(&filename);
@@ -93,7 +84,7 @@ impl OpDebugger {
 
 
 // output current instruction and CPU register status in a neat, readable fashion
-pub fn debug_instruction(opcode: u8, cpu: &mut cpu::CPU) {
+pub fn debug_instruction(opcode: u8, cpu: &mut cpu::CPU, bus: &mut bus::Bus) {
     cpu.prev_pc = cpu.pc;
     let prev_pc = cpu.prev_pc;
     
@@ -135,58 +126,58 @@ pub fn debug_instruction(opcode: u8, cpu: &mut cpu::CPU) {
             operand = format!("A      ");
         },
         opcodes::AddrMode::Immediate => {
-            operand_hex = format!(" {:02X}    ", cpu.read_byte(prev_pc));
-            operand = format!("#${:02X}   ", cpu.read_byte(prev_pc)); 
+            operand_hex = format!(" {:02X}    ", bus.read_byte(prev_pc));
+            operand = format!("#${:02X}   ", bus.read_byte(prev_pc)); 
         },
         opcodes::AddrMode::Absolute => {
-            operand_hex = format!(" {:02X} {:02X} ", cpu.read_byte(prev_pc), cpu.read_byte(prev_pc + 0x01));
-            operand = format!("${:04X}  ", cpu.read_word_le(cpu.prev_pc));
+            operand_hex = format!(" {:02X} {:02X} ", bus.read_byte(prev_pc), bus.read_byte(prev_pc + 0x01));
+            operand = format!("${:04X}  ", bus.read_word_le(cpu.prev_pc));
         },
         opcodes::AddrMode::AbsoluteIndexedX(ec) => {
             extra_cycle = ec;
-            operand_hex = format!(" {:02X} {:02X} ", cpu.read_byte(prev_pc), cpu.read_byte(prev_pc + 0x01));
-            operand = format!("${:04X},X", cpu.read_word_le(cpu.prev_pc));
+            operand_hex = format!(" {:02X} {:02X} ", bus.read_byte(prev_pc), bus.read_byte(prev_pc + 0x01));
+            operand = format!("${:04X},X", bus.read_word_le(cpu.prev_pc));
         },
         opcodes::AddrMode::AbsoluteIndexedY(ec) => {
             extra_cycle = ec;
-            operand_hex = format!(" {:02X} {:02X} ", cpu.read_byte(prev_pc), cpu.read_byte(prev_pc + 0x01));
-            operand = format!("${:04X},Y", cpu.read_word_le(cpu.prev_pc));
+            operand_hex = format!(" {:02X} {:02X} ", bus.read_byte(prev_pc), bus.read_byte(prev_pc + 0x01));
+            operand = format!("${:04X},Y", bus.read_word_le(cpu.prev_pc));
         },
         opcodes::AddrMode::Zeropage => {
-            operand_hex = format!(" {:02X}    ", cpu.read_byte(prev_pc));
-            operand = format!("${:02X}    ", cpu.read_byte(prev_pc));
+            operand_hex = format!(" {:02X}    ", bus.read_byte(prev_pc));
+            operand = format!("${:02X}    ", bus.read_byte(prev_pc));
         }, 
         opcodes::AddrMode::ZeropageIndexedX => {
-            operand_hex = format!(" {:02X}    ", cpu.read_byte(prev_pc));
-            operand = format!("${:02X},X  ", cpu.read_byte(prev_pc));
+            operand_hex = format!(" {:02X}    ", bus.read_byte(prev_pc));
+            operand = format!("${:02X},X  ", bus.read_byte(prev_pc));
         },
         opcodes::AddrMode::ZeropageIndexedY => {
-            operand_hex = format!(" {:02X}    ", cpu.read_byte(prev_pc));
-            operand = format!("${:02X},Y  ", cpu.read_byte(prev_pc));
+            operand_hex = format!(" {:02X}    ", bus.read_byte(prev_pc));
+            operand = format!("${:02X},Y  ", bus.read_byte(prev_pc));
         },
         opcodes::AddrMode::Relative => {
-            operand_hex = format!(" {:02X}    ", cpu.read_byte(prev_pc));
-            let b: i8 = cpu.read_byte(prev_pc) as i8;
+            operand_hex = format!(" {:02X}    ", bus.read_byte(prev_pc));
+            let b: i8 = bus.read_byte(prev_pc) as i8;
             operand = format!("${:04X}  ", ((cpu.prev_pc + 1) as i16 + b as i16) as u16);
         },
         opcodes::AddrMode::Indirect => {
-            operand_hex = format!(" {:02X} {:02X} ", cpu.read_byte(prev_pc), cpu.read_byte(prev_pc + 0x01));
-            operand = format!("(${:04X})", cpu.read_word_le(cpu.prev_pc));
+            operand_hex = format!(" {:02X} {:02X} ", bus.read_byte(prev_pc), bus.read_byte(prev_pc + 0x01));
+            operand = format!("(${:04X})", bus.read_word_le(cpu.prev_pc));
         },
         opcodes::AddrMode::IndexedIndirectX => {
-            operand_hex = format!(" {:02X}    ", cpu.read_byte(prev_pc));
-            operand = format!("(${:02X},X)", cpu.read_byte(prev_pc));
+            operand_hex = format!(" {:02X}    ", bus.read_byte(prev_pc));
+            operand = format!("(${:02X},X)", bus.read_byte(prev_pc));
         },
         opcodes::AddrMode::IndirectIndexedY(ec) => {
             extra_cycle = ec;
-            operand_hex = format!(" {:02X}    ", cpu.read_byte(prev_pc));
-            operand = format!("(${:02X}),Y", cpu.read_byte(prev_pc));
+            operand_hex = format!(" {:02X}    ", bus.read_byte(prev_pc));
+            operand = format!("(${:02X}),Y", bus.read_byte(prev_pc));
         },
     }
 
     // control latch bytes' status
-    let byte0 = cpu.read_byte(0x0000);
-    let byte1 = cpu.read_byte(0x0001);
+    let byte0 = bus.read_byte(0x0000);
+    let byte1 = bus.read_byte(0x0001);
 
     let mut total_cycles = cpu.instruction.cycles_to_fetch + cpu.instruction.cycles_to_run + cpu.instruction.cycles_to_rmw;
     let mut fetch_cycles = cpu.instruction.cycles_to_fetch;
@@ -0,0 +1,95 @@
+// minifb window - frames are scaled in software since minifb only offers power of two scale factors
+extern crate minifb;
+
+use self::minifb::{Key, KeyRepeat, MouseButton, MouseMode, Window, WindowOptions};
+use video::{Display, Options};
+
+
+pub struct MinifbDisplay {
+    window: Window,
+    width: usize,
+    height: usize,
+    scale: usize,
+    columns: Vec<usize>,     // source column for each output column
+    window_buffer: Vec<u32>, // scaled frame
+}
+
+impl MinifbDisplay {
+    pub fn open(title: &str, width: usize, height: usize, options: &Options) -> Result<MinifbDisplay, String> {
+        let (out_width, out_height) = options.output_size(width, height);
+        let window = Window::new(title, out_width, out_height, WindowOptions::default()).map_err(|e| e.to_string())?;
+
+        Ok(MinifbDisplay {
+            window: window,
+            width: width,
+            height: height,
+            scale: options.scale as usize,
+            columns: (0..out_width).map(|x| x * width / out_width).collect(),
+            window_buffer: vec![0; out_width * out_height],
+        })
+    }
+}
+
+
+impl Display for MinifbDisplay {
+    fn is_open(&self) -> bool {
+        self.window.is_open()
+    }
+
+
+    fn set_title(&mut self, title: &str) {
+        self.window.set_title(title);
+    }
+
+
+    fn set_position(&mut self, x: isize, y: isize) {
+        self.window.set_position(x, y);
+    }
+
+
+    fn present(&mut self, frame: &[u32]) {
+        let out_width = self.columns.len();
+
+        // nearest neighbour: stretch each row horizontally, then repeat it for the vertical scale
+        for y in 0..self.height {
+            let src = &frame[y * self.width..(y + 1) * self.width];
+            let first = y * self.scale * out_width;
+
+            for (x, &col) in self.columns.iter().enumerate() {
+                self.window_buffer[first + x] = src[col];
+            }
+
+            for i in 1..self.scale {
+                self.window_buffer.copy_within(first..first + out_width, first + i * out_width);
+            }
+        }
+
+        let _ = self.window.update_with_buffer(&self.window_buffer, out_width, self.height * self.scale);
+    }
+
+
+    fn update(&mut self) {
+        self.window.update();
+    }
+
+
+    fn is_key_down(&self, key: Key) -> bool {
+        self.window.is_key_down(key)
+    }
+
+
+    fn is_key_pressed(&self, key: Key, repeat: KeyRepeat) -> bool {
+        self.window.is_key_pressed(key, repeat)
+    }
+
+
+    fn mouse_pos(&self) -> Option<(f32, f32)> {
+        let (x, y) = self.window.get_mouse_pos(MouseMode::Discard)?;
+        Some((x * self.width as f32 / self.columns.len() as f32, y / self.scale as f32))
+    }
+
+
+    fn is_mouse_down(&self, button: MouseButton) -> bool {
+        self.window.get_mouse_down(button)
+    }
+}
@@ -0,0 +1,114 @@
+// video output backends
+// the emulator renders into plain 0RGB buffers - a display presents them in a window (minifb, SDL2)
+// or just keeps the last one (offscreen) and reports which keys are held down
+mod minifb;
+mod offscreen;
+#[cfg(all(feature = "sdl2", not(target_os = "redox")))]
+mod sdl;
+
+use audio;
+use minifb::{Key, KeyRepeat, MouseButton};
+
+pub use self::offscreen::OffscreenDisplay;
+
+pub const MAX_SCALE: u32 = 8;
+
+// how often displays without vsync are polled for input
+const UPDATE_INTERVAL_US: u64 = 4000;
+
+
+pub trait Display {
+    // false once the window was closed
+    fn is_open(&self) -> bool;
+    fn set_title(&mut self, title: &str);
+    fn set_position(&mut self, _x: isize, _y: isize) {}
+
+    // show a new frame of the size the display was opened with and process window events
+    fn present(&mut self, frame: &[u32]);
+
+    // process window events only
+    fn update(&mut self);
+
+    fn is_key_down(&self, key: Key) -> bool;
+    fn is_key_pressed(&self, key: Key, repeat: KeyRepeat) -> bool;
+
+    // mouse position in frame pixels - None while it's outside the frame
+    fn mouse_pos(&self) -> Option<(f32, f32)> { None }
+    fn is_mouse_down(&self, _button: MouseButton) -> bool { false }
+
+    fn is_fullscreen(&self) -> bool { false }
+
+    fn set_fullscreen(&mut self, _fullscreen: bool) -> Result<(), String> {
+        Err(String::from("fullscreen needs the SDL2 video backend (--video sdl)"))
+    }
+
+    // play audio through the display's own context (SDL2 only allows one per process)
+    fn open_audio(&mut self, _output: audio::RemoteOutput) -> Result<(), String> {
+        Err(String::from("this video backend has no audio device"))
+    }
+}
+
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Backend {
+    Minifb,
+    Sdl,
+    Offscreen, // no window - frames are only kept in memory
+}
+
+impl Backend {
+    pub fn from_name(name: &str) -> Option<Backend> {
+        match &name.to_lowercase()[..] {
+            "minifb"             => Some(Backend::Minifb),
+            "sdl" | "sdl2"       => Some(Backend::Sdl),
+            "offscreen" | "none" => Some(Backend::Offscreen),
+            _ => None
+        }
+    }
+}
+
+
+#[derive(Clone, Debug)]
+pub struct Options {
+    pub scale: u32,          // integer scale factor (1 - MAX_SCALE)
+    pub pixel_aspect: f64,   // pixel width/height - 1.0 for square pixels
+}
+
+impl Options {
+    pub fn new() -> Options {
+        Options {
+            scale: 1,
+            pixel_aspect: 1.0,
+        }
+    }
+
+
+    // window size for a frame of the given size
+    pub fn output_size(&self, width: usize, height: usize) -> (usize, usize) {
+        let scale = self.scale as f64;
+        ((width as f64 * scale * self.pixel_aspect).round() as usize, height * self.scale as usize)
+    }
+}
+
+
+pub fn open(backend: &Backend, title: &str, width: usize, height: usize, options: &Options) -> Result<Box<dyn Display>, String> {
+    match *backend {
+        Backend::Minifb    => Ok(Box::new(minifb::MinifbDisplay::open(title, width, height, options)?)),
+        Backend::Sdl       => open_sdl(title, width, height, options),
+        Backend::Offscreen => Ok(Box::new(OffscreenDisplay::new(width, height))),
+    }
+}
+
+
+// *** private functions *** //
+
+#[cfg(all(feature = "sdl2", not(target_os = "redox")))]
+fn open_sdl(title: &str, width: usize, height: usize, options: &Options) -> Result<Box<dyn Display>, String> {
+    Ok(Box::new(sdl::SdlDisplay::open(title, width, height, options)?))
+}
+
+
+#[cfg(not(all(feature = "sdl2", not(target_os = "redox"))))]
+fn open_sdl(_title: &str, _width: usize, _height: usize, _options: &Options) -> Result<Box<dyn Display>, String> {
+    Err(String::from("SDL2 video is not compiled in (build with --features sdl2)"))
+}
@@ -0,0 +1,56 @@
+// no window at all - keeps the last frame for headless runs
+use minifb::{Key, KeyRepeat};
+use std::thread;
+use std::time::Duration;
+use video::{Display, UPDATE_INTERVAL_US};
+
+
+pub struct OffscreenDisplay {
+    window_buffer: Vec<u32>,
+}
+
+impl OffscreenDisplay {
+    pub fn new(width: usize, height: usize) -> OffscreenDisplay {
+        OffscreenDisplay {
+            window_buffer: vec![0; width * height],
+        }
+    }
+
+
+    // last presented frame
+    pub fn window_buffer(&self) -> &[u32] {
+        &self.window_buffer
+    }
+}
+
+
+impl Display for OffscreenDisplay {
+    fn is_open(&self) -> bool {
+        true
+    }
+
+
+    fn set_title(&mut self, _title: &str) {}
+
+
+    fn present(&mut self, frame: &[u32]) {
+        self.window_buffer.copy_from_slice(frame);
+        self.update();
+    }
+
+
+    // nothing to poll - don't spin the caller
+    fn update(&mut self) {
+        thread::sleep(Duration::from_micros(UPDATE_INTERVAL_US));
+    }
+
+
+    fn is_key_down(&self, _key: Key) -> bool {
+        false
+    }
+
+
+    fn is_key_pressed(&self, _key: Key, _repeat: KeyRepeat) -> bool {
+        false
+    }
+}
@@ -0,0 +1,269 @@
+// SDL2 window - scaling, aspect ratio and vsync are left to the renderer, fullscreen is supported
+extern crate sdl2;
+
+use audio;
+use minifb::{Key, KeyRepeat, MouseButton};
+use self::sdl2::EventPump;
+use self::sdl2::audio::AudioDevice;
+use self::sdl2::event::Event;
+use self::sdl2::keyboard::Scancode;
+use self::sdl2::pixels::{Color, PixelFormatEnum};
+use self::sdl2::rect::Rect;
+use self::sdl2::render::{Texture, TextureCreator, WindowCanvas};
+use self::sdl2::video::{FullscreenType, WindowContext, WindowPos};
+use std::thread;
+use std::time::Duration;
+use video::{Display, Options, UPDATE_INTERVAL_US};
+
+
+pub struct SdlDisplay {
+    sdl_context: sdl2::Sdl,
+    canvas: WindowCanvas,
+    texture: Texture<'static>,
+    event_pump: EventPump,
+    width: usize,
+    height: usize,
+    pixel_aspect: f64,
+    is_open: bool,
+    keys_pressed: Vec<(Scancode, bool)>, // key down events since the last update (scancode, repeat)
+    audio_device: Option<AudioDevice<audio::sdl::RemoteCallback>>,
+}
+
+impl SdlDisplay {
+    pub fn open(title: &str, width: usize, height: usize, options: &Options) -> Result<SdlDisplay, String> {
+        let sdl_context = sdl2::init()?;
+        let video_subsystem = sdl_context.video()?;
+        let (out_width, out_height) = options.output_size(width, height);
+
+        let window = video_subsystem.window(title, out_width as u32, out_height as u32)
+            .resizable()
+            .build()
+            .map_err(|e| e.to_string())?;
+
+        let canvas = window.into_canvas().present_vsync().build().map_err(|e| e.to_string())?;
+
+        // the display lives until the program exits, so the texture can borrow a leaked creator
+        let texture_creator: &'static TextureCreator<WindowContext> = Box::leak(Box::new(canvas.texture_creator()));
+        let texture = texture_creator.create_texture_streaming(PixelFormatEnum::RGB888, width as u32, height as u32)
+            .map_err(|e| e.to_string())?;
+
+        let event_pump = sdl_context.event_pump()?;
+
+        Ok(SdlDisplay {
+            sdl_context: sdl_context,
+            canvas: canvas,
+            texture: texture,
+            event_pump: event_pump,
+            width: width,
+            height: height,
+            pixel_aspect: options.pixel_aspect,
+            is_open: true,
+            keys_pressed: Vec::new(),
+            audio_device: None,
+        })
+    }
+
+
+    // *** private functions *** //
+
+    // largest frame rectangle with the right aspect ratio that fits the window - integer scaled
+    // unless the window is too small for even 1x
+    fn frame_rect(&self) -> Rect {
+        let (win_width, win_height) = self.canvas.output_size().unwrap_or((self.width as u32, self.height as u32));
+        let frame_width = self.width as f64 * self.pixel_aspect;
+        let frame_height = self.height as f64;
+
+        let mut scale = (win_width as f64 / frame_width).min(win_height as f64 / frame_height);
+        if scale >= 1.0 {
+            scale = scale.floor();
+        }
+
+        let w = (frame_width * scale).round() as u32;
+        let h = (frame_height * scale).round() as u32;
+        Rect::new(((win_width - w.min(win_width)) / 2) as i32, ((win_height - h.min(win_height)) / 2) as i32, w, h)
+    }
+
+
+    fn poll_events(&mut self) {
+        self.keys_pressed.clear();
+
+        for event in self.event_pump.poll_iter() {
+            match event {
+                Event::Quit { .. } => self.is_open = false,
+                Event::KeyDown { scancode: Some(scancode), repeat, .. } => self.keys_pressed.push((scancode, repeat)),
+                _ => ()
+            }
+        }
+    }
+}
+
+
+impl Display for SdlDisplay {
+    fn is_open(&self) -> bool {
+        self.is_open
+    }
+
+
+    fn set_title(&mut self, title: &str) {
+        let _ = self.canvas.window_mut().set_title(title);
+    }
+
+
+    fn set_position(&mut self, x: isize, y: isize) {
+        self.canvas.window_mut().set_position(WindowPos::Positioned(x as i32), WindowPos::Positioned(y as i32));
+    }
+
+
+    fn present(&mut self, frame: &[u32]) {
+        let width = self.width;
+        let _ = self.texture.with_lock(None, |pixels: &mut [u8], pitch: usize| {
+            for (row, src) in frame.chunks(width).enumerate() {
+                let dst = &mut pixels[row * pitch..row * pitch + width * 4];
+
+                for (px, &color) in dst.chunks_mut(4).zip(src.iter()) {
+                    px.copy_from_slice(&color.to_ne_bytes());
+                }
+            }
+        });
+
+        let rect = self.frame_rect();
+        self.canvas.set_draw_color(Color::RGB(0, 0, 0));
+        self.canvas.clear();
+        let _ = self.canvas.copy(&self.texture, None, rect);
+        self.canvas.present(); // waits for vsync
+
+        self.poll_events();
+    }
+
+
+    fn update(&mut self) {
+        self.poll_events();
+        thread::sleep(Duration::from_micros(UPDATE_INTERVAL_US));
+    }
+
+
+    fn is_key_down(&self, key: Key) -> bool {
+        match scancode(key) {
+            Some(scancode) => self.event_pump.keyboard_state().is_scancode_pressed(scancode),
+            None => false
+        }
+    }
+
+
+    fn is_key_pressed(&self, key: Key, repeat: KeyRepeat) -> bool {
+        match scancode(key) {
+            Some(scancode) => self.keys_pressed.iter().any(|&(sc, is_repeat)| sc == scancode && (!is_repeat || repeat == KeyRepeat::Yes)),
+            None => false
+        }
+    }
+
+
+    fn mouse_pos(&self) -> Option<(f32, f32)> {
+        // mouse coordinates are in window units, which can differ from pixels on high DPI screens
+        let mouse = self.event_pump.mouse_state();
+        let (win_width, win_height) = self.canvas.window().size();
+        let (out_width, out_height) = self.canvas.output_size().ok()?;
+        let x = mouse.x() as f32 * out_width as f32 / win_width.max(1) as f32;
+        let y = mouse.y() as f32 * out_height as f32 / win_height.max(1) as f32;
+
+        let rect = self.frame_rect();
+        let fx = (x - rect.x() as f32) * self.width as f32 / rect.width() as f32;
+        let fy = (y - rect.y() as f32) * self.height as f32 / rect.height() as f32;
+
+        if fx >= 0.0 && fy >= 0.0 && fx < self.width as f32 && fy < self.height as f32 { Some((fx, fy)) } else { None }
+    }
+
+
+    fn is_mouse_down(&self, button: MouseButton) -> bool {
+        let mouse = self.event_pump.mouse_state();
+
+        match button {
+            MouseButton::Left   => mouse.left(),
+            MouseButton::Middle => mouse.middle(),
+            MouseButton::Right  => mouse.right(),
+        }
+    }
+
+
+    fn is_fullscreen(&self) -> bool {
+        self.canvas.window().fullscreen_state() != FullscreenType::Off
+    }
+
+
+    // fullscreen at the desktop resolution - the frame is scaled to fit
+    fn set_fullscreen(&mut self, fullscreen: bool) -> Result<(), String> {
+        let mode = if fullscreen { FullscreenType::Desktop } else { FullscreenType::Off };
+        self.canvas.window_mut().set_fullscreen(mode)
+    }
+
+
+    fn open_audio(&mut self, output: audio::RemoteOutput) -> Result<(), String> {
+        let audio_subsystem = self.sdl_context.audio()?;
+        self.audio_device = Some(audio::sdl::open_remote(&audio_subsystem, output)?);
+        Ok(())
+    }
+}
+
+
+// *** private functions *** //
+
+// keys are matched by position, like minifb does
+fn scancode(key: Key) -> Option<Scancode> {
+    let scancode = match key {
+        Key::Key0 => Scancode::Num0, Key::Key1 => Scancode::Num1, Key::Key2 => Scancode::Num2,
+        Key::Key3 => Scancode::Num3, Key::Key4 => Scancode::Num4, Key::Key5 => Scancode::Num5,
+        Key::Key6 => Scancode::Num6, Key::Key7 => Scancode::Num7, Key::Key8 => Scancode::Num8,
+        Key::Key9 => Scancode::Num9,
+        Key::A => Scancode::A, Key::B => Scancode::B, Key::C => Scancode::C, Key::D => Scancode::D,
+        Key::E => Scancode::E, Key::F => Scancode::F, Key::G => Scancode::G, Key::H => Scancode::H,
+        Key::I => Scancode::I, Key::J => Scancode::J, Key::K => Scancode::K, Key::L => Scancode::L,
+        Key::M => Scancode::M, Key::N => Scancode::N, Key::O => Scancode::O, Key::P => Scancode::P,
+        Key::Q => Scancode::Q, Key::R => Scancode::R, Key::S => Scancode::S, Key::T => Scancode::T,
+        Key::U => Scancode::U, Key::V => Scancode::V, Key::W => Scancode::W, Key::X => Scancode::X,
+        Key::Y => Scancode::Y, Key::Z => Scancode::Z,
+        Key::F1 => Scancode::F1, Key::F2 => Scancode::F2, Key::F3 => Scancode::F3, Key::F4 => Scancode::F4,
+        Key::F5 => Scancode::F5, Key::F6 => Scancode::F6, Key::F7 => Scancode::F7, Key::F8 => Scancode::F8,
+        Key::F9 => Scancode::F9, Key::F10 => Scancode::F10, Key::F11 => Scancode::F11, Key::F12 => Scancode::F12,
+        Key::Down => Scancode::Down, Key::Up => Scancode::Up, Key::Right => Scancode::Right, Key::Left => Scancode::Left,
+        Key::Space        => Scancode::Space,
+        Key::Comma        => Scancode::Comma,
+        Key::Period       => Scancode::Period,
+        Key::Slash        => Scancode::Slash,
+        Key::Enter        => Scancode::Return,
+        Key::Backspace    => Scancode::Backspace,
+        Key::Backquote    => Scancode::Grave,
+        Key::LeftShift    => Scancode::LShift,
+        Key::RightShift   => Scancode::RShift,
+        Key::LeftCtrl     => Scancode::LCtrl,
+        Key::RightCtrl    => Scancode::RCtrl,
+        Key::LeftAlt      => Scancode::LAlt,
+        Key::RightAlt     => Scancode::RAlt,
+        Key::Escape       => Scancode::Escape,
+        Key::Minus        => Scancode::Minus,
+        Key::Equal        => Scancode::Equals,
+        Key::Insert       => Scancode::Insert,
+        Key::Delete       => Scancode::Delete,
+        Key::Home         => Scancode::Home,
+        Key::End          => Scancode::End,
+        Key::PageUp       => Scancode::PageUp,
+        Key::PageDown     => Scancode::PageDown,
+        Key::LeftBracket  => Scancode::LeftBracket,
+        Key::RightBracket => Scancode::RightBracket,
+        Key::Semicolon    => Scancode::Semicolon,
+        Key::Apostrophe   => Scancode::Apostrophe,
+        Key::Backslash    => Scancode::Backslash,
+        Key::Tab          => Scancode::Tab,
+        Key::NumPad1 => Scancode::Kp1, Key::NumPad2 => Scancode::Kp2, Key::NumPad3 => Scancode::Kp3,
+        Key::NumPad4 => Scancode::Kp4, Key::NumPad5 => Scancode::Kp5, Key::NumPad6 => Scancode::Kp6,
+        Key::NumPad7 => Scancode::Kp7, Key::NumPad8 => Scancode::Kp8, Key::NumPad9 => Scancode::Kp9,
+        Key::NumLock        => Scancode::NumLockClear,
+        Key::NumPadAsterisk => Scancode::KpMultiply,
+        Key::NumPadPlus     => Scancode::KpPlus,
+        Key::NumPadMinus    => Scancode::KpMinus,
+        Key::NumPadSlash    => Scancode::KpDivide,
+        Key::NumPadDot      => Scancode::KpPeriod,
+        _ => return None
+    };
+
+    Some(scancode)
+}